@@ -1,43 +1,49 @@
+#[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct AccessControlPolicy {
     /// object key attribute to lookup in the object.
     /// Thinking something like this {"uuid": "06f822e7-3265-4722-beec-d50d8e9140e0"}
     /// I wonder if you can put a unique constrain on a column like that
-    #[prost(message, repeated, tag="1")]
+    #[prost(message, repeated, tag = "1")]
     pub lookup_object_key: ::prost::alloc::vec::Vec<LookupObjectKey>,
     /// data_type name. This normally correlates with table name, and or model type name.
     /// I like to think of it as either an aggregate, or an entity. It's worth noteing
     /// if access is restricted to an aggregate, all of it's encompasing entities will
     /// inhert the same restriction restriction
-    #[prost(string, tag="2")]
+    #[prost(string, tag = "2")]
     pub data_type: ::prost::alloc::string::String,
     /// string mapping of the different operations that can be performed on a resource
-    #[prost(enumeration="Operation", tag="3")]
+    #[prost(enumeration = "Operation", tag = "3")]
     pub operation: i32,
     /// subject of the action being evaluated
-    #[prost(message, optional, tag="4")]
+    #[prost(message, optional, tag = "4")]
     pub subject: ::core::option::Option<Subject>,
     /// outcom is set so a policy can be either a deny filter, or allowed filter.
     /// A example policy might be allow all users with driver role to read this specific
     /// data.
-    #[prost(enumeration="Outcome", tag="5")]
+    #[prost(enumeration = "Outcome", tag = "5")]
     pub outcome: i32,
+    /// unique id of the policy, set by the server when the policy is created
+    #[prost(string, tag = "6")]
+    pub id: ::prost::alloc::string::String,
 }
-/// ObjectKey is a key value mapping that can be used to lookup an aggregate/entity 
+/// ObjectKey is a key value mapping that can be used to lookup an aggregate/entity
 /// example {"uuid": "b7e3597a-88af-4f20-a9b5-0d49f2c8376e"} or (where ?KEY = ?VALUE )
+#[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct LookupObjectKey {
-    #[prost(string, tag="1")]
+    #[prost(string, tag = "1")]
     pub key: ::prost::alloc::string::String,
-    #[prost(string, tag="2")]
+    #[prost(string, tag = "2")]
     pub value: ::prost::alloc::string::String,
 }
 /// Details of the client/user making the request
+#[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Subject {
-    #[prost(string, tag="1")]
+    #[prost(string, tag = "1")]
     pub user_id: ::prost::alloc::string::String,
-    #[prost(string, repeated, tag="2")]
+    #[prost(string, repeated, tag = "2")]
     pub group_ids: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
 }
 /// Operations are actions that can be taken on a resource
@@ -48,14 +54,42 @@ pub struct Subject {
 #[repr(i32)]
 pub enum Operation {
     DataOperationUnspecified = 0,
-    /// data specific operations 
+    /// data specific operations
     Insert = 1,
     Update = 2,
     Read = 3,
     Delete = 4,
-    /// experimental field, writing 
+    /// experimental field, writing
     ChangePermission = 5,
 }
+impl Operation {
+    /// String value of the enum field names used in the ProtoBuf definition.
+    ///
+    /// The values are not transformed in any way and thus are considered stable
+    /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            Operation::DataOperationUnspecified => "DATA_OPERATION_UNSPECIFIED",
+            Operation::Insert => "INSERT",
+            Operation::Update => "UPDATE",
+            Operation::Read => "READ",
+            Operation::Delete => "DELETE",
+            Operation::ChangePermission => "CHANGE_PERMISSION",
+        }
+    }
+    /// Creates an enum from field names used in the ProtoBuf definition.
+    pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+        match value {
+            "DATA_OPERATION_UNSPECIFIED" => Some(Self::DataOperationUnspecified),
+            "INSERT" => Some(Self::Insert),
+            "UPDATE" => Some(Self::Update),
+            "READ" => Some(Self::Read),
+            "DELETE" => Some(Self::Delete),
+            "CHANGE_PERMISSION" => Some(Self::ChangePermission),
+            _ => None,
+        }
+    }
+}
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
 #[repr(i32)]
 pub enum Outcome {
@@ -63,28 +97,123 @@ pub enum Outcome {
     Allowed = 1,
     Denied = 2,
 }
-/// Is the client allowed to perform the specific operation on the 
+impl Outcome {
+    /// String value of the enum field names used in the ProtoBuf definition.
+    ///
+    /// The values are not transformed in any way and thus are considered stable
+    /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            Outcome::Unspecifield => "OUTCOME_UNSPECIFIELD",
+            Outcome::Allowed => "ALLOWED",
+            Outcome::Denied => "DENIED",
+        }
+    }
+    /// Creates an enum from field names used in the ProtoBuf definition.
+    pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+        match value {
+            "OUTCOME_UNSPECIFIELD" => Some(Self::Unspecifield),
+            "ALLOWED" => Some(Self::Allowed),
+            "DENIED" => Some(Self::Denied),
+            _ => None,
+        }
+    }
+}
+/// Is the client allowed to perform the specific operation on the
 /// aggregate/entity that was found with the LookupObjectKey
+#[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct EvaluatePolicyRequest {
-    #[prost(message, repeated, tag="1")]
+    #[prost(message, repeated, tag = "1")]
     pub lookup_object_key: ::prost::alloc::vec::Vec<LookupObjectKey>,
-    #[prost(string, tag="2")]
+    #[prost(string, tag = "2")]
     pub data_type: ::prost::alloc::string::String,
-    #[prost(enumeration="Operation", tag="3")]
+    #[prost(enumeration = "Operation", tag = "3")]
     pub operation: i32,
-    #[prost(string, tag="4")]
+    /// role of the caller, it's matched against the group_ids of a policy subject
+    #[prost(string, tag = "4")]
     pub role: ::prost::alloc::string::String,
+    /// the user, and groups the user belongs to, making the request
+    #[prost(message, optional, tag = "5")]
+    pub subject: ::core::option::Option<Subject>,
+    /// when set the response includes a trace of how the outcome was reached
+    #[prost(bool, tag = "6")]
+    pub explain: bool,
 }
+#[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct EvaluatePolicyResponse {
-    #[prost(enumeration="Outcome", tag="1")]
+    #[prost(enumeration = "Outcome", tag = "1")]
     pub outcome: i32,
+    /// only set when the request asked to explain the decision
+    #[prost(message, optional, tag = "2")]
+    pub trace: ::core::option::Option<PolicyDecisionTrace>,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct PolicyEvaluation {
+    #[prost(message, optional, tag = "1")]
+    pub policy: ::core::option::Option<AccessControlPolicy>,
+    /// true when the policy applied, and its outcome was combined
+    #[prost(bool, tag = "2")]
+    pub matched: bool,
+    #[prost(enumeration = "SkipReason", tag = "3")]
+    pub skip_reason: i32,
+    /// human readable detail of the match, or of why it was skipped
+    #[prost(string, tag = "4")]
+    pub detail: ::prost::alloc::string::String,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct PolicyDecisionTrace {
+    /// every policy loaded for the data_type and operation, in evaluation order
+    #[prost(message, repeated, tag = "1")]
+    pub policies: ::prost::alloc::vec::Vec<PolicyEvaluation>,
+    /// name of the combining algorithm, eg. `deny-overrides`
+    #[prost(string, tag = "2")]
+    pub combining_algorithm: ::prost::alloc::string::String,
+    /// how the outcomes of the matched policies were combined into the final outcome
+    #[prost(string, tag = "3")]
+    pub combining_step: ::prost::alloc::string::String,
+}
+/// Why a policy for the requested data_type and operation did not take part in the decision
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum SkipReason {
+    /// the policy applied to the request
+    Unspecified = 0,
+    /// neither the user nor the groups of the policy subject match the request
+    SubjectMismatch = 1,
+    /// a lookup_object_key of the policy is missing from the request, or has another value
+    ObjectMismatch = 2,
+}
+impl SkipReason {
+    /// String value of the enum field names used in the ProtoBuf definition.
+    ///
+    /// The values are not transformed in any way and thus are considered stable
+    /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            SkipReason::Unspecified => "SKIP_REASON_UNSPECIFIED",
+            SkipReason::SubjectMismatch => "SUBJECT_MISMATCH",
+            SkipReason::ObjectMismatch => "OBJECT_MISMATCH",
+        }
+    }
+    /// Creates an enum from field names used in the ProtoBuf definition.
+    pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+        match value {
+            "SKIP_REASON_UNSPECIFIED" => Some(Self::Unspecified),
+            "SUBJECT_MISMATCH" => Some(Self::SubjectMismatch),
+            "OBJECT_MISMATCH" => Some(Self::ObjectMismatch),
+            _ => None,
+        }
+    }
 }
 /// Generated client implementations.
 pub mod policy_evaluator_client {
     #![allow(unused_variables, dead_code, missing_docs, clippy::let_unit_value)]
     use tonic::codegen::*;
+    use tonic::codegen::http::Uri;
     /// Client integration point
     #[derive(Debug, Clone)]
     pub struct PolicyEvaluatorClient<T> {
@@ -94,7 +223,7 @@ pub mod policy_evaluator_client {
         /// Attempt to create a new client by connecting to a given endpoint.
         pub async fn connect<D>(dst: D) -> Result<Self, tonic::transport::Error>
         where
-            D: std::convert::TryInto<tonic::transport::Endpoint>,
+            D: TryInto<tonic::transport::Endpoint>,
             D::Error: Into<StdError>,
         {
             let conn = tonic::transport::Endpoint::new(dst)?.connect().await?;
@@ -112,6 +241,10 @@ pub mod policy_evaluator_client {
             let inner = tonic::client::Grpc::new(inner);
             Self { inner }
         }
+        pub fn with_origin(inner: T, origin: Uri) -> Self {
+            let inner = tonic::client::Grpc::with_origin(inner, origin);
+            Self { inner }
+        }
         pub fn with_interceptor<F>(
             inner: T,
             interceptor: F,
@@ -131,19 +264,35 @@ pub mod policy_evaluator_client {
         {
             PolicyEvaluatorClient::new(InterceptedService::new(inner, interceptor))
         }
-        /// Compress requests with `gzip`.
+        /// Compress requests with the given encoding.
         ///
         /// This requires the server to support it otherwise it might respond with an
         /// error.
         #[must_use]
-        pub fn send_gzip(mut self) -> Self {
-            self.inner = self.inner.send_gzip();
+        pub fn send_compressed(mut self, encoding: CompressionEncoding) -> Self {
+            self.inner = self.inner.send_compressed(encoding);
+            self
+        }
+        /// Enable decompressing responses.
+        #[must_use]
+        pub fn accept_compressed(mut self, encoding: CompressionEncoding) -> Self {
+            self.inner = self.inner.accept_compressed(encoding);
             self
         }
-        /// Enable decompressing responses with `gzip`.
+        /// Limits the maximum size of a decoded message.
+        ///
+        /// Default: `4MB`
         #[must_use]
-        pub fn accept_gzip(mut self) -> Self {
-            self.inner = self.inner.accept_gzip();
+        pub fn max_decoding_message_size(mut self, limit: usize) -> Self {
+            self.inner = self.inner.max_decoding_message_size(limit);
+            self
+        }
+        /// Limits the maximum size of an encoded message.
+        ///
+        /// Default: `usize::MAX`
+        #[must_use]
+        pub fn max_encoding_message_size(mut self, limit: usize) -> Self {
+            self.inner = self.inner.max_encoding_message_size(limit);
             self
         }
         /// integration point for the service clients to call.
@@ -151,7 +300,10 @@ pub mod policy_evaluator_client {
         pub async fn evaluate_policy(
             &mut self,
             request: impl tonic::IntoRequest<super::EvaluatePolicyRequest>,
-        ) -> Result<tonic::Response<super::EvaluatePolicyResponse>, tonic::Status> {
+        ) -> std::result::Result<
+            tonic::Response<super::EvaluatePolicyResponse>,
+            tonic::Status,
+        > {
             self.inner
                 .ready()
                 .await
@@ -165,7 +317,15 @@ pub mod policy_evaluator_client {
             let path = http::uri::PathAndQuery::from_static(
                 "/draft.access_controls.v1.PolicyEvaluator/EvaluatePolicy",
             );
-            self.inner.unary(request.into_request(), path, codec).await
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(
+                    GrpcMethod::new(
+                        "draft.access_controls.v1.PolicyEvaluator",
+                        "EvaluatePolicy",
+                    ),
+                );
+            self.inner.unary(req, path, codec).await
         }
     }
 }
@@ -173,7 +333,7 @@ pub mod policy_evaluator_client {
 pub mod policy_evaluator_server {
     #![allow(unused_variables, dead_code, missing_docs, clippy::let_unit_value)]
     use tonic::codegen::*;
-    ///Generated trait containing gRPC methods that should be implemented for use with PolicyEvaluatorServer.
+    /// Generated trait containing gRPC methods that should be implemented for use with PolicyEvaluatorServer.
     #[async_trait]
     pub trait PolicyEvaluator: Send + Sync + 'static {
         /// integration point for the service clients to call.
@@ -181,14 +341,19 @@ pub mod policy_evaluator_server {
         async fn evaluate_policy(
             &self,
             request: tonic::Request<super::EvaluatePolicyRequest>,
-        ) -> Result<tonic::Response<super::EvaluatePolicyResponse>, tonic::Status>;
+        ) -> std::result::Result<
+            tonic::Response<super::EvaluatePolicyResponse>,
+            tonic::Status,
+        >;
     }
     /// Client integration point
     #[derive(Debug)]
     pub struct PolicyEvaluatorServer<T: PolicyEvaluator> {
         inner: _Inner<T>,
-        accept_compression_encodings: (),
-        send_compression_encodings: (),
+        accept_compression_encodings: EnabledCompressionEncodings,
+        send_compression_encodings: EnabledCompressionEncodings,
+        max_decoding_message_size: Option<usize>,
+        max_encoding_message_size: Option<usize>,
     }
     struct _Inner<T>(Arc<T>);
     impl<T: PolicyEvaluator> PolicyEvaluatorServer<T> {
@@ -201,6 +366,8 @@ pub mod policy_evaluator_server {
                 inner,
                 accept_compression_encodings: Default::default(),
                 send_compression_encodings: Default::default(),
+                max_decoding_message_size: None,
+                max_encoding_message_size: None,
             }
         }
         pub fn with_interceptor<F>(
@@ -212,6 +379,34 @@ pub mod policy_evaluator_server {
         {
             InterceptedService::new(Self::new(inner), interceptor)
         }
+        /// Enable decompressing requests with the given encoding.
+        #[must_use]
+        pub fn accept_compressed(mut self, encoding: CompressionEncoding) -> Self {
+            self.accept_compression_encodings.enable(encoding);
+            self
+        }
+        /// Compress responses with the given encoding, if the client supports it.
+        #[must_use]
+        pub fn send_compressed(mut self, encoding: CompressionEncoding) -> Self {
+            self.send_compression_encodings.enable(encoding);
+            self
+        }
+        /// Limits the maximum size of a decoded message.
+        ///
+        /// Default: `4MB`
+        #[must_use]
+        pub fn max_decoding_message_size(mut self, limit: usize) -> Self {
+            self.max_decoding_message_size = Some(limit);
+            self
+        }
+        /// Limits the maximum size of an encoded message.
+        ///
+        /// Default: `usize::MAX`
+        #[must_use]
+        pub fn max_encoding_message_size(mut self, limit: usize) -> Self {
+            self.max_encoding_message_size = Some(limit);
+            self
+        }
     }
     impl<T, B> tonic::codegen::Service<http::Request<B>> for PolicyEvaluatorServer<T>
     where
@@ -225,7 +420,7 @@ pub mod policy_evaluator_server {
         fn poll_ready(
             &mut self,
             _cx: &mut Context<'_>,
-        ) -> Poll<Result<(), Self::Error>> {
+        ) -> Poll<std::result::Result<(), Self::Error>> {
             Poll::Ready(Ok(()))
         }
         fn call(&mut self, req: http::Request<B>) -> Self::Future {
@@ -247,7 +442,7 @@ pub mod policy_evaluator_server {
                             &mut self,
                             request: tonic::Request<super::EvaluatePolicyRequest>,
                         ) -> Self::Future {
-                            let inner = self.0.clone();
+                            let inner = Arc::clone(&self.0);
                             let fut = async move {
                                 (*inner).evaluate_policy(request).await
                             };
@@ -256,6 +451,8 @@ pub mod policy_evaluator_server {
                     }
                     let accept_compression_encodings = self.accept_compression_encodings;
                     let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
                     let inner = self.inner.clone();
                     let fut = async move {
                         let inner = inner.0;
@@ -265,6 +462,10 @@ pub mod policy_evaluator_server {
                             .apply_compression_config(
                                 accept_compression_encodings,
                                 send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
                             );
                         let res = grpc.unary(method, req).await;
                         Ok(res)
@@ -293,12 +494,14 @@ pub mod policy_evaluator_server {
                 inner,
                 accept_compression_encodings: self.accept_compression_encodings,
                 send_compression_encodings: self.send_compression_encodings,
+                max_decoding_message_size: self.max_decoding_message_size,
+                max_encoding_message_size: self.max_encoding_message_size,
             }
         }
     }
     impl<T: PolicyEvaluator> Clone for _Inner<T> {
         fn clone(&self) -> Self {
-            Self(self.0.clone())
+            Self(Arc::clone(&self.0))
         }
     }
     impl<T: std::fmt::Debug> std::fmt::Debug for _Inner<T> {
@@ -306,8 +509,837 @@ pub mod policy_evaluator_server {
             write!(f, "{:?}", self.0)
         }
     }
-    impl<T: PolicyEvaluator> tonic::transport::NamedService
-    for PolicyEvaluatorServer<T> {
+    impl<T: PolicyEvaluator> tonic::server::NamedService for PolicyEvaluatorServer<T> {
         const NAME: &'static str = "draft.access_controls.v1.PolicyEvaluator";
     }
 }
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct CreatePolicyRequest {
+    /// the id is ignored, one is generated by the server
+    #[prost(message, optional, tag = "1")]
+    pub policy: ::core::option::Option<AccessControlPolicy>,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct CreatePolicyResponse {
+    #[prost(message, optional, tag = "1")]
+    pub policy: ::core::option::Option<AccessControlPolicy>,
+    #[prost(int64, tag = "2")]
+    pub version: i64,
+}
+/// empty fields are not filtered on
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ListPoliciesRequest {
+    #[prost(string, tag = "1")]
+    pub data_type: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub subject_user_id: ::prost::alloc::string::String,
+    #[prost(string, tag = "3")]
+    pub subject_group_id: ::prost::alloc::string::String,
+    #[prost(enumeration = "Operation", tag = "4")]
+    pub operation: i32,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ListPoliciesResponse {
+    #[prost(message, repeated, tag = "1")]
+    pub policies: ::prost::alloc::vec::Vec<AccessControlPolicy>,
+    /// current version of the policy set
+    #[prost(int64, tag = "2")]
+    pub version: i64,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct UpdatePolicyRequest {
+    /// replaces the policy with the same id
+    #[prost(message, optional, tag = "1")]
+    pub policy: ::core::option::Option<AccessControlPolicy>,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct UpdatePolicyResponse {
+    #[prost(message, optional, tag = "1")]
+    pub policy: ::core::option::Option<AccessControlPolicy>,
+    #[prost(int64, tag = "2")]
+    pub version: i64,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct DeletePolicyRequest {
+    #[prost(string, tag = "1")]
+    pub id: ::prost::alloc::string::String,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct DeletePolicyResponse {
+    #[prost(int64, tag = "1")]
+    pub version: i64,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct PolicySetVersion {
+    #[prost(int64, tag = "1")]
+    pub version: i64,
+    /// create, update, delete or rollback
+    #[prost(string, tag = "2")]
+    pub change: ::prost::alloc::string::String,
+    #[prost(string, tag = "3")]
+    pub policy_id: ::prost::alloc::string::String,
+    /// state of the policy after the change, unset when the change removed it
+    #[prost(message, optional, tag = "4")]
+    pub policy: ::core::option::Option<AccessControlPolicy>,
+    #[prost(string, tag = "5")]
+    pub changed_by: ::prost::alloc::string::String,
+    /// RFC 3339 timestamp
+    #[prost(string, tag = "6")]
+    pub created_at: ::prost::alloc::string::String,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ListPolicyVersionsRequest {
+    /// only return versions for this policy when set
+    #[prost(string, tag = "1")]
+    pub policy_id: ::prost::alloc::string::String,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ListPolicyVersionsResponse {
+    #[prost(message, repeated, tag = "1")]
+    pub versions: ::prost::alloc::vec::Vec<PolicySetVersion>,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct RollbackPoliciesRequest {
+    #[prost(int64, tag = "1")]
+    pub version: i64,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct RollbackPoliciesResponse {
+    /// the version recorded by the rollback
+    #[prost(int64, tag = "1")]
+    pub version: i64,
+}
+/// Generated client implementations.
+pub mod policy_admin_client {
+    #![allow(unused_variables, dead_code, missing_docs, clippy::let_unit_value)]
+    use tonic::codegen::*;
+    use tonic::codegen::http::Uri;
+    /// Policy administration point. Every call requires the caller to be allowed the
+    /// CHANGE_PERMISSION operation on the `access_control_policies` data_type.
+    #[derive(Debug, Clone)]
+    pub struct PolicyAdminClient<T> {
+        inner: tonic::client::Grpc<T>,
+    }
+    impl PolicyAdminClient<tonic::transport::Channel> {
+        /// Attempt to create a new client by connecting to a given endpoint.
+        pub async fn connect<D>(dst: D) -> Result<Self, tonic::transport::Error>
+        where
+            D: TryInto<tonic::transport::Endpoint>,
+            D::Error: Into<StdError>,
+        {
+            let conn = tonic::transport::Endpoint::new(dst)?.connect().await?;
+            Ok(Self::new(conn))
+        }
+    }
+    impl<T> PolicyAdminClient<T>
+    where
+        T: tonic::client::GrpcService<tonic::body::BoxBody>,
+        T::Error: Into<StdError>,
+        T::ResponseBody: Body<Data = Bytes> + Send + 'static,
+        <T::ResponseBody as Body>::Error: Into<StdError> + Send,
+    {
+        pub fn new(inner: T) -> Self {
+            let inner = tonic::client::Grpc::new(inner);
+            Self { inner }
+        }
+        pub fn with_origin(inner: T, origin: Uri) -> Self {
+            let inner = tonic::client::Grpc::with_origin(inner, origin);
+            Self { inner }
+        }
+        pub fn with_interceptor<F>(
+            inner: T,
+            interceptor: F,
+        ) -> PolicyAdminClient<InterceptedService<T, F>>
+        where
+            F: tonic::service::Interceptor,
+            T::ResponseBody: Default,
+            T: tonic::codegen::Service<
+                http::Request<tonic::body::BoxBody>,
+                Response = http::Response<
+                    <T as tonic::client::GrpcService<tonic::body::BoxBody>>::ResponseBody,
+                >,
+            >,
+            <T as tonic::codegen::Service<
+                http::Request<tonic::body::BoxBody>,
+            >>::Error: Into<StdError> + Send + Sync,
+        {
+            PolicyAdminClient::new(InterceptedService::new(inner, interceptor))
+        }
+        /// Compress requests with the given encoding.
+        ///
+        /// This requires the server to support it otherwise it might respond with an
+        /// error.
+        #[must_use]
+        pub fn send_compressed(mut self, encoding: CompressionEncoding) -> Self {
+            self.inner = self.inner.send_compressed(encoding);
+            self
+        }
+        /// Enable decompressing responses.
+        #[must_use]
+        pub fn accept_compressed(mut self, encoding: CompressionEncoding) -> Self {
+            self.inner = self.inner.accept_compressed(encoding);
+            self
+        }
+        /// Limits the maximum size of a decoded message.
+        ///
+        /// Default: `4MB`
+        #[must_use]
+        pub fn max_decoding_message_size(mut self, limit: usize) -> Self {
+            self.inner = self.inner.max_decoding_message_size(limit);
+            self
+        }
+        /// Limits the maximum size of an encoded message.
+        ///
+        /// Default: `usize::MAX`
+        #[must_use]
+        pub fn max_encoding_message_size(mut self, limit: usize) -> Self {
+            self.inner = self.inner.max_encoding_message_size(limit);
+            self
+        }
+        pub async fn create_policy(
+            &mut self,
+            request: impl tonic::IntoRequest<super::CreatePolicyRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::CreatePolicyResponse>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/draft.access_controls.v1.PolicyAdmin/CreatePolicy",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(
+                    GrpcMethod::new(
+                        "draft.access_controls.v1.PolicyAdmin",
+                        "CreatePolicy",
+                    ),
+                );
+            self.inner.unary(req, path, codec).await
+        }
+        pub async fn list_policies(
+            &mut self,
+            request: impl tonic::IntoRequest<super::ListPoliciesRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::ListPoliciesResponse>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/draft.access_controls.v1.PolicyAdmin/ListPolicies",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(
+                    GrpcMethod::new(
+                        "draft.access_controls.v1.PolicyAdmin",
+                        "ListPolicies",
+                    ),
+                );
+            self.inner.unary(req, path, codec).await
+        }
+        pub async fn update_policy(
+            &mut self,
+            request: impl tonic::IntoRequest<super::UpdatePolicyRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::UpdatePolicyResponse>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/draft.access_controls.v1.PolicyAdmin/UpdatePolicy",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(
+                    GrpcMethod::new(
+                        "draft.access_controls.v1.PolicyAdmin",
+                        "UpdatePolicy",
+                    ),
+                );
+            self.inner.unary(req, path, codec).await
+        }
+        pub async fn delete_policy(
+            &mut self,
+            request: impl tonic::IntoRequest<super::DeletePolicyRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::DeletePolicyResponse>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/draft.access_controls.v1.PolicyAdmin/DeletePolicy",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(
+                    GrpcMethod::new(
+                        "draft.access_controls.v1.PolicyAdmin",
+                        "DeletePolicy",
+                    ),
+                );
+            self.inner.unary(req, path, codec).await
+        }
+        /// every change to the policy set is recorded as a new version
+        pub async fn list_policy_versions(
+            &mut self,
+            request: impl tonic::IntoRequest<super::ListPolicyVersionsRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::ListPolicyVersionsResponse>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/draft.access_controls.v1.PolicyAdmin/ListPolicyVersions",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(
+                    GrpcMethod::new(
+                        "draft.access_controls.v1.PolicyAdmin",
+                        "ListPolicyVersions",
+                    ),
+                );
+            self.inner.unary(req, path, codec).await
+        }
+        /// restore the policy set to how it was at a version, the rollback is recorded as a new version
+        pub async fn rollback_policies(
+            &mut self,
+            request: impl tonic::IntoRequest<super::RollbackPoliciesRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::RollbackPoliciesResponse>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/draft.access_controls.v1.PolicyAdmin/RollbackPolicies",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(
+                    GrpcMethod::new(
+                        "draft.access_controls.v1.PolicyAdmin",
+                        "RollbackPolicies",
+                    ),
+                );
+            self.inner.unary(req, path, codec).await
+        }
+    }
+}
+/// Generated server implementations.
+pub mod policy_admin_server {
+    #![allow(unused_variables, dead_code, missing_docs, clippy::let_unit_value)]
+    use tonic::codegen::*;
+    /// Generated trait containing gRPC methods that should be implemented for use with PolicyAdminServer.
+    #[async_trait]
+    pub trait PolicyAdmin: Send + Sync + 'static {
+        async fn create_policy(
+            &self,
+            request: tonic::Request<super::CreatePolicyRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::CreatePolicyResponse>,
+            tonic::Status,
+        >;
+        async fn list_policies(
+            &self,
+            request: tonic::Request<super::ListPoliciesRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::ListPoliciesResponse>,
+            tonic::Status,
+        >;
+        async fn update_policy(
+            &self,
+            request: tonic::Request<super::UpdatePolicyRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::UpdatePolicyResponse>,
+            tonic::Status,
+        >;
+        async fn delete_policy(
+            &self,
+            request: tonic::Request<super::DeletePolicyRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::DeletePolicyResponse>,
+            tonic::Status,
+        >;
+        /// every change to the policy set is recorded as a new version
+        async fn list_policy_versions(
+            &self,
+            request: tonic::Request<super::ListPolicyVersionsRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::ListPolicyVersionsResponse>,
+            tonic::Status,
+        >;
+        /// restore the policy set to how it was at a version, the rollback is recorded as a new version
+        async fn rollback_policies(
+            &self,
+            request: tonic::Request<super::RollbackPoliciesRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::RollbackPoliciesResponse>,
+            tonic::Status,
+        >;
+    }
+    /// Policy administration point. Every call requires the caller to be allowed the
+    /// CHANGE_PERMISSION operation on the `access_control_policies` data_type.
+    #[derive(Debug)]
+    pub struct PolicyAdminServer<T: PolicyAdmin> {
+        inner: _Inner<T>,
+        accept_compression_encodings: EnabledCompressionEncodings,
+        send_compression_encodings: EnabledCompressionEncodings,
+        max_decoding_message_size: Option<usize>,
+        max_encoding_message_size: Option<usize>,
+    }
+    struct _Inner<T>(Arc<T>);
+    impl<T: PolicyAdmin> PolicyAdminServer<T> {
+        pub fn new(inner: T) -> Self {
+            Self::from_arc(Arc::new(inner))
+        }
+        pub fn from_arc(inner: Arc<T>) -> Self {
+            let inner = _Inner(inner);
+            Self {
+                inner,
+                accept_compression_encodings: Default::default(),
+                send_compression_encodings: Default::default(),
+                max_decoding_message_size: None,
+                max_encoding_message_size: None,
+            }
+        }
+        pub fn with_interceptor<F>(
+            inner: T,
+            interceptor: F,
+        ) -> InterceptedService<Self, F>
+        where
+            F: tonic::service::Interceptor,
+        {
+            InterceptedService::new(Self::new(inner), interceptor)
+        }
+        /// Enable decompressing requests with the given encoding.
+        #[must_use]
+        pub fn accept_compressed(mut self, encoding: CompressionEncoding) -> Self {
+            self.accept_compression_encodings.enable(encoding);
+            self
+        }
+        /// Compress responses with the given encoding, if the client supports it.
+        #[must_use]
+        pub fn send_compressed(mut self, encoding: CompressionEncoding) -> Self {
+            self.send_compression_encodings.enable(encoding);
+            self
+        }
+        /// Limits the maximum size of a decoded message.
+        ///
+        /// Default: `4MB`
+        #[must_use]
+        pub fn max_decoding_message_size(mut self, limit: usize) -> Self {
+            self.max_decoding_message_size = Some(limit);
+            self
+        }
+        /// Limits the maximum size of an encoded message.
+        ///
+        /// Default: `usize::MAX`
+        #[must_use]
+        pub fn max_encoding_message_size(mut self, limit: usize) -> Self {
+            self.max_encoding_message_size = Some(limit);
+            self
+        }
+    }
+    impl<T, B> tonic::codegen::Service<http::Request<B>> for PolicyAdminServer<T>
+    where
+        T: PolicyAdmin,
+        B: Body + Send + 'static,
+        B::Error: Into<StdError> + Send + 'static,
+    {
+        type Response = http::Response<tonic::body::BoxBody>;
+        type Error = std::convert::Infallible;
+        type Future = BoxFuture<Self::Response, Self::Error>;
+        fn poll_ready(
+            &mut self,
+            _cx: &mut Context<'_>,
+        ) -> Poll<std::result::Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+        fn call(&mut self, req: http::Request<B>) -> Self::Future {
+            let inner = self.inner.clone();
+            match req.uri().path() {
+                "/draft.access_controls.v1.PolicyAdmin/CreatePolicy" => {
+                    #[allow(non_camel_case_types)]
+                    struct CreatePolicySvc<T: PolicyAdmin>(pub Arc<T>);
+                    impl<
+                        T: PolicyAdmin,
+                    > tonic::server::UnaryService<super::CreatePolicyRequest>
+                    for CreatePolicySvc<T> {
+                        type Response = super::CreatePolicyResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::CreatePolicyRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                (*inner).create_policy(request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = CreatePolicySvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/draft.access_controls.v1.PolicyAdmin/ListPolicies" => {
+                    #[allow(non_camel_case_types)]
+                    struct ListPoliciesSvc<T: PolicyAdmin>(pub Arc<T>);
+                    impl<
+                        T: PolicyAdmin,
+                    > tonic::server::UnaryService<super::ListPoliciesRequest>
+                    for ListPoliciesSvc<T> {
+                        type Response = super::ListPoliciesResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::ListPoliciesRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                (*inner).list_policies(request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = ListPoliciesSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/draft.access_controls.v1.PolicyAdmin/UpdatePolicy" => {
+                    #[allow(non_camel_case_types)]
+                    struct UpdatePolicySvc<T: PolicyAdmin>(pub Arc<T>);
+                    impl<
+                        T: PolicyAdmin,
+                    > tonic::server::UnaryService<super::UpdatePolicyRequest>
+                    for UpdatePolicySvc<T> {
+                        type Response = super::UpdatePolicyResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::UpdatePolicyRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                (*inner).update_policy(request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = UpdatePolicySvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/draft.access_controls.v1.PolicyAdmin/DeletePolicy" => {
+                    #[allow(non_camel_case_types)]
+                    struct DeletePolicySvc<T: PolicyAdmin>(pub Arc<T>);
+                    impl<
+                        T: PolicyAdmin,
+                    > tonic::server::UnaryService<super::DeletePolicyRequest>
+                    for DeletePolicySvc<T> {
+                        type Response = super::DeletePolicyResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::DeletePolicyRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                (*inner).delete_policy(request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = DeletePolicySvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/draft.access_controls.v1.PolicyAdmin/ListPolicyVersions" => {
+                    #[allow(non_camel_case_types)]
+                    struct ListPolicyVersionsSvc<T: PolicyAdmin>(pub Arc<T>);
+                    impl<
+                        T: PolicyAdmin,
+                    > tonic::server::UnaryService<super::ListPolicyVersionsRequest>
+                    for ListPolicyVersionsSvc<T> {
+                        type Response = super::ListPolicyVersionsResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::ListPolicyVersionsRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                (*inner).list_policy_versions(request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = ListPolicyVersionsSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/draft.access_controls.v1.PolicyAdmin/RollbackPolicies" => {
+                    #[allow(non_camel_case_types)]
+                    struct RollbackPoliciesSvc<T: PolicyAdmin>(pub Arc<T>);
+                    impl<
+                        T: PolicyAdmin,
+                    > tonic::server::UnaryService<super::RollbackPoliciesRequest>
+                    for RollbackPoliciesSvc<T> {
+                        type Response = super::RollbackPoliciesResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::RollbackPoliciesRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                (*inner).rollback_policies(request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = RollbackPoliciesSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                _ => {
+                    Box::pin(async move {
+                        Ok(
+                            http::Response::builder()
+                                .status(200)
+                                .header("grpc-status", "12")
+                                .header("content-type", "application/grpc")
+                                .body(empty_body())
+                                .unwrap(),
+                        )
+                    })
+                }
+            }
+        }
+    }
+    impl<T: PolicyAdmin> Clone for PolicyAdminServer<T> {
+        fn clone(&self) -> Self {
+            let inner = self.inner.clone();
+            Self {
+                inner,
+                accept_compression_encodings: self.accept_compression_encodings,
+                send_compression_encodings: self.send_compression_encodings,
+                max_decoding_message_size: self.max_decoding_message_size,
+                max_encoding_message_size: self.max_encoding_message_size,
+            }
+        }
+    }
+    impl<T: PolicyAdmin> Clone for _Inner<T> {
+        fn clone(&self) -> Self {
+            Self(Arc::clone(&self.0))
+        }
+    }
+    impl<T: std::fmt::Debug> std::fmt::Debug for _Inner<T> {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "{:?}", self.0)
+        }
+    }
+    impl<T: PolicyAdmin> tonic::server::NamedService for PolicyAdminServer<T> {
+        const NAME: &'static str = "draft.access_controls.v1.PolicyAdmin";
+    }
+}
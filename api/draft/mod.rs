@@ -4,4 +4,9 @@ pub mod draft {
             include!("draft.access_controls.v1.rs");
         }
     }
+    pub mod users {
+        pub mod v1 {
+            include!("draft.users.v1.rs");
+        }
+    }
 }
@@ -3,6 +3,9 @@ use std::path::Path;
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     build_draft().unwrap();
 
+    // the migrations are embedded with `sqlx::migrate!`, a new one has to trigger a rebuild
+    println!("cargo:rerun-if-changed=migrations");
+
     Ok(())
 }
 
@@ -10,6 +13,11 @@ fn build_draft() -> Result<(), Box<dyn std::error::Error>> {
   let out_dir = Path::new("./api/draft");
   let includes = Path::new("mod.rs");
 
+  // tonic-build no longer bundles protoc, fall back to the vendored binary when one isn't configured
+  if std::env::var_os("PROTOC").is_none() {
+    std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+  }
+
   // configure the tonic builder
   tonic_build::configure()
       .out_dir(out_dir)
@@ -17,6 +25,8 @@ fn build_draft() -> Result<(), Box<dyn std::error::Error>> {
       .compile(&[
         "./protos/draft/access_controls/v1/models.proto",
         "./protos/draft/access_controls/v1/service.proto",
+        "./protos/draft/access_controls/v1/admin.proto",
+        "./protos/draft/writer_interface.proto",
       ], &["."])?;
 
   Ok(())
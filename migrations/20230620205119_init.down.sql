@@ -1,4 +1,4 @@
 -- Add down migration script here
+DROP TABLE IF EXISTS user_roles;
 DROP TABLE IF EXISTS users;
 DROP TABLE IF EXISTS roles;
-DROP TABLE IF EXISTS user_roles;
\ No newline at end of file
@@ -0,0 +1,24 @@
+-- Add up migration script here
+-- every refresh token that has been issued. A family starts at login, and each
+-- rotation adds a child that points at the token it replaced.
+CREATE TABLE IF NOT EXISTS refresh_tokens (
+    -- `jti` claim of the token
+    id UUID PRIMARY KEY,
+    family_id UUID NOT NULL,
+    parent_id UUID,
+    user_id UUID NOT NULL,
+    client_id VARCHAR(255) NOT NULL,
+
+    issued_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    expires_at TIMESTAMPTZ NOT NULL,
+    -- set when the token is exchanged for a new pair
+    retired_at TIMESTAMPTZ,
+    -- set when the family is revoked
+    revoked_at TIMESTAMPTZ,
+
+    FOREIGN KEY (parent_id) REFERENCES refresh_tokens(id),
+    FOREIGN KEY (user_id) REFERENCES users(id)
+);
+
+CREATE INDEX IF NOT EXISTS refresh_tokens_family_id_idx ON refresh_tokens (family_id);
+CREATE INDEX IF NOT EXISTS refresh_tokens_user_id_idx ON refresh_tokens (user_id);
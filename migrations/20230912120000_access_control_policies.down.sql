@@ -0,0 +1,3 @@
+-- Add down migration script here
+DROP TABLE IF EXISTS access_control_policy_object_keys;
+DROP TABLE IF EXISTS access_control_policies;
@@ -0,0 +1,25 @@
+-- Add up migration script here
+-- an `AccessControlPolicy`. `operation` and `outcome` hold the values of the protobuf enums
+CREATE TABLE IF NOT EXISTS access_control_policies (
+    id UUID DEFAULT uuid_generate_v4() PRIMARY KEY,
+
+    data_type VARCHAR(255) NOT NULL,
+    operation INTEGER NOT NULL,
+    outcome INTEGER NOT NULL,
+    -- the subject the policy applies to, a policy without a user or groups applies to everyone
+    subject_user_id VARCHAR(255) NOT NULL DEFAULT '',
+    subject_group_ids TEXT[] NOT NULL DEFAULT '{}',
+
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS access_control_policies_lookup_idx ON access_control_policies (data_type, operation);
+
+-- `LookupObjectKey` matchers of a policy, every key has to match the object for the policy to apply
+CREATE TABLE IF NOT EXISTS access_control_policy_object_keys (
+    policy_id UUID NOT NULL,
+    key VARCHAR(255) NOT NULL,
+    value VARCHAR(500) NOT NULL,
+    FOREIGN KEY (policy_id) REFERENCES access_control_policies(id) ON DELETE CASCADE,
+    UNIQUE (policy_id, key)
+);
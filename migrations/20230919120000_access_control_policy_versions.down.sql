@@ -0,0 +1,3 @@
+-- Add down migration script here
+DROP TABLE IF EXISTS access_control_policy_versions;
+DELETE FROM access_control_policies WHERE data_type = 'access_control_policies' AND operation = 5;
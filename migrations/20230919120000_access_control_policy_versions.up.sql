@@ -0,0 +1,47 @@
+-- Add up migration script here
+-- history of the policy set. Every change records the state of the changed policy after the
+-- change, so the policy set at any version is the latest row per policy up to that version.
+CREATE TABLE IF NOT EXISTS access_control_policy_versions (
+    version BIGSERIAL PRIMARY KEY,
+
+    -- create, update, delete or rollback
+    change VARCHAR(16) NOT NULL,
+    policy_id UUID NOT NULL,
+    -- true when the policy does not exist after the change
+    deleted BOOLEAN NOT NULL DEFAULT false,
+    data_type VARCHAR(255) NOT NULL DEFAULT '',
+    operation INTEGER NOT NULL DEFAULT 0,
+    outcome INTEGER NOT NULL DEFAULT 0,
+    subject_user_id VARCHAR(255) NOT NULL DEFAULT '',
+    subject_group_ids TEXT[] NOT NULL DEFAULT '{}',
+    -- `LookupObjectKey` matchers as two arrays of the same length
+    object_keys TEXT[] NOT NULL DEFAULT '{}',
+    object_values TEXT[] NOT NULL DEFAULT '{}',
+
+    changed_by VARCHAR(500) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS access_control_policy_versions_policy_id_idx ON access_control_policy_versions (policy_id, version);
+
+-- members of the admin role are allowed to administer policies
+INSERT INTO access_control_policies (data_type, operation, outcome, subject_group_ids)
+VALUES ('access_control_policies', 5, 1, '{admin}');
+
+-- start the history with every policy that already exists
+INSERT INTO access_control_policy_versions (change, policy_id, data_type, operation, outcome, subject_user_id, subject_group_ids, object_keys, object_values, changed_by)
+SELECT
+    'create',
+    p.id,
+    p.data_type,
+    p.operation,
+    p.outcome,
+    p.subject_user_id,
+    p.subject_group_ids,
+    COALESCE(array_agg(k.key ORDER BY k.key) FILTER (WHERE k.key IS NOT NULL), '{}'),
+    COALESCE(array_agg(k.value ORDER BY k.key) FILTER (WHERE k.key IS NOT NULL), '{}'),
+    'migration'
+FROM access_control_policies p
+LEFT JOIN access_control_policy_object_keys k ON k.policy_id = p.id
+GROUP BY p.id
+ORDER BY p.created_at;
@@ -0,0 +1,2 @@
+-- Add down migration script here
+ALTER TABLE roles DROP COLUMN IF EXISTS scopes;
@@ -0,0 +1,6 @@
+-- Add up migration script here
+-- scopes are granted to a user through their roles, and added to the access token at login
+ALTER TABLE roles ADD COLUMN IF NOT EXISTS scopes TEXT[] NOT NULL DEFAULT '{}';
+
+UPDATE roles SET scopes = '{users:read,users:write,policies:read,policies:write}' WHERE name = 'admin';
+UPDATE roles SET scopes = '{users:read}' WHERE name = 'default';
@@ -0,0 +1,3 @@
+-- Add down migration script here
+DROP TABLE IF EXISTS confirmation_codes;
+ALTER TABLE users DROP COLUMN IF EXISTS confirmed_at;
@@ -0,0 +1,25 @@
+-- Add up migration script here
+-- a user can't login until their email is confirmed, users that already exist are confirmed
+ALTER TABLE users ADD COLUMN IF NOT EXISTS confirmed_at TIMESTAMPTZ;
+UPDATE users SET confirmed_at = now() WHERE confirmed_at IS NULL;
+
+-- codes sent to the user to confirm their email, only a sha256 of the code is stored
+CREATE TABLE IF NOT EXISTS confirmation_codes (
+    id UUID DEFAULT uuid_generate_v4() PRIMARY KEY,
+    user_id UUID NOT NULL,
+
+    code_hash VARCHAR(64) NOT NULL,
+    -- failed attempts against this code, it stops working after too many
+    attempts INT NOT NULL DEFAULT 0,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    expires_at TIMESTAMPTZ NOT NULL,
+    -- set when the code is used, or replaced by a newer code
+    used_at TIMESTAMPTZ,
+    -- the client that asked for the code, sends are limited per ip
+    requested_ip VARCHAR(45),
+
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS confirmation_codes_user_id_idx ON confirmation_codes (user_id);
+CREATE INDEX IF NOT EXISTS confirmation_codes_requested_ip_idx ON confirmation_codes (requested_ip, created_at);
@@ -0,0 +1,22 @@
+-- Add up migration script here
+-- mail is queued in the same transaction as the change it's about, and delivered by the outbox worker
+CREATE TABLE IF NOT EXISTS mail_outbox (
+    id UUID DEFAULT uuid_generate_v4() PRIMARY KEY,
+
+    to_address VARCHAR(255) NOT NULL,
+    subject VARCHAR(255) NOT NULL,
+    -- name of the mail/<template>.html and mail/<template>.txt templates, and their json context. The context
+    -- can hold reset tokens and confirmation codes, it's cleared once the mail is sent or given up on
+    template VARCHAR(255) NOT NULL,
+    context JSONB NOT NULL DEFAULT '{}',
+
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    attempts INT NOT NULL DEFAULT 0,
+    last_error TEXT,
+    next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    -- set once the mail is sent, or when it's given up on after too many attempts
+    sent_at TIMESTAMPTZ,
+    failed_at TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS mail_outbox_pending_idx ON mail_outbox (next_attempt_at) WHERE sent_at IS NULL AND failed_at IS NULL;
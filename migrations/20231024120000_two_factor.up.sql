@@ -0,0 +1,28 @@
+-- Add up migration script here
+-- the TOTP secret of a user, enrollment is pending until it's confirmed with a code
+CREATE TABLE IF NOT EXISTS totp_credentials (
+    user_id UUID PRIMARY KEY,
+
+    -- base32 encoded, the server needs the secret itself to compute the codes
+    secret VARCHAR(64) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    confirmed_at TIMESTAMPTZ,
+    -- time step of the last code that was used, so a code can't be replayed
+    last_used_step BIGINT,
+
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+-- one time codes to sign in with when the authenticator is lost, only a sha256 of each code is stored
+CREATE TABLE IF NOT EXISTS recovery_codes (
+    id UUID DEFAULT uuid_generate_v4() PRIMARY KEY,
+    user_id UUID NOT NULL,
+
+    code_hash VARCHAR(64) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    used_at TIMESTAMPTZ,
+
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS recovery_codes_user_id_idx ON recovery_codes (user_id);
@@ -0,0 +1,15 @@
+-- Add up migration script here
+-- failed logins counted per account (kind 'email') and per client ip (kind 'ip')
+CREATE TABLE IF NOT EXISTS login_attempts (
+    kind VARCHAR(16) NOT NULL,
+    key VARCHAR(255) NOT NULL,
+
+    failures INT NOT NULL DEFAULT 0,
+    last_failure_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    -- logins before this are refused without checking the password, the backoff
+    next_attempt_at TIMESTAMPTZ,
+    -- set when the failures pass the lockout threshold
+    locked_until TIMESTAMPTZ,
+
+    PRIMARY KEY (kind, key)
+);
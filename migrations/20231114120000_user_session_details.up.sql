@@ -0,0 +1,21 @@
+-- Add up migration script here
+-- who a session of `user_sessions` belongs to and where it's used from, `user_sessions` itself is managed by axum_session
+CREATE TABLE IF NOT EXISTS user_session_details (
+    id UUID DEFAULT uuid_generate_v4() PRIMARY KEY,
+    -- id of the `user_sessions` row, it's the session cookie so it's never shown
+    session_id VARCHAR(128) NOT NULL UNIQUE,
+    user_id UUID NOT NULL,
+    -- family of the refresh token the session was signed in with, revoked with the session
+    refresh_family_id UUID,
+
+    user_agent TEXT NOT NULL DEFAULT '',
+    ip VARCHAR(64) NOT NULL DEFAULT '',
+
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    last_seen_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    revoked_at TIMESTAMPTZ,
+
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS user_session_details_user_id_idx ON user_session_details (user_id);
@@ -0,0 +1,3 @@
+-- Add up migration script here
+-- a disabled user can't sign in, their sessions are revoked when they're disabled
+ALTER TABLE users ADD COLUMN IF NOT EXISTS disabled_at TIMESTAMPTZ;
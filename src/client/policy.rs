@@ -0,0 +1,107 @@
+use clap::Subcommand;
+use tonic::Request;
+
+use crate::api::draft::access_controls::v1::{
+    policy_evaluator_client::PolicyEvaluatorClient,
+    EvaluatePolicyRequest,
+    LookupObjectKey,
+    Operation,
+    PolicyDecisionTrace,
+    Subject,
+};
+use crate::client::{ClientError, ClientResult};
+
+#[derive(Subcommand, Debug)]
+pub enum PolicyCommand {
+    /// evaluate a request against the policies of the server
+    Test {
+        #[clap(long)]
+        data_type: String,
+        /// INSERT, UPDATE, READ, DELETE or CHANGE_PERMISSION
+        #[clap(long)]
+        operation: String,
+        #[clap(long, default_value = "")]
+        user: String,
+        /// group of the user, can be repeated
+        #[clap(long)]
+        group: Vec<String>,
+        #[clap(long, default_value = "")]
+        role: String,
+        /// object key as key=value, can be repeated
+        #[clap(long)]
+        key: Vec<String>,
+        /// print which policies matched, which were skipped and why
+        #[clap(long)]
+        explain: bool,
+        /// access token of a user allowed CHANGE_PERMISSION, `--explain` needs it
+        #[clap(long, env = "ACCESS_TOKEN")]
+        token: Option<String>,
+    },
+}
+
+pub async fn run(address: &str, command: PolicyCommand) -> ClientResult<()> {
+    match command {
+        PolicyCommand::Test { data_type, operation, user, group, role, key, explain, token } => {
+            let operation = match Operation::from_str_name(&operation.to_uppercase()) {
+                Some(v) => v,
+                None => return Err(ClientError(format!("unknown operation {}", operation))),
+            };
+
+            let mut lookup_object_key = vec![];
+            for k in key {
+                match k.split_once('=') {
+                    Some((key, value)) => lookup_object_key.push(LookupObjectKey {
+                        key: key.to_owned(),
+                        value: value.to_owned(),
+                    }),
+                    None => return Err(ClientError(format!("object key {} is not key=value", k))),
+                }
+            }
+
+            let mut client = PolicyEvaluatorClient::connect(address.to_owned())
+                .await
+                .map_err(|e| ClientError(format!("failed to connect to {}: {}", address, e)))?;
+
+            let mut request = Request::new(EvaluatePolicyRequest {
+                lookup_object_key,
+                data_type,
+                operation: operation as i32,
+                role,
+                subject: Some(Subject { user_id: user, group_ids: group }),
+                explain,
+            });
+
+            if let Some(token) = token {
+                let value = format!("Bearer {}", token)
+                    .parse()
+                    .map_err(|_e| ClientError(String::from("the token can't be sent as metadata")))?;
+                request.metadata_mut().insert("authorization", value);
+            }
+
+            let res = client.evaluate_policy(request)
+            .await
+            .map_err(|e| ClientError(e.message().to_owned()))?
+            .into_inner();
+
+            if let Some(trace) = &res.trace {
+                print_trace(trace);
+            }
+            println!("{}", res.outcome().as_str_name());
+
+            Ok(())
+        },
+    }
+}
+
+fn print_trace(trace: &PolicyDecisionTrace) {
+    for evaluation in &trace.policies {
+        let id = evaluation.policy.as_ref().map(|p| p.id.as_str()).unwrap_or_default();
+
+        match evaluation.matched {
+            true => println!("matched  {} {}", id, evaluation.detail),
+            false => println!("skipped  {} {}: {}", id, evaluation.skip_reason().as_str_name(), evaluation.detail),
+        }
+    }
+
+    println!("{}: {}", trace.combining_algorithm, trace.combining_step);
+}
@@ -0,0 +1,44 @@
+use clap::Subcommand;
+use sqlx::postgres::PgPool;
+
+use crate::client::{ClientError, ClientResult};
+use crate::controller::roles::{self, RolesError};
+
+#[derive(Subcommand, Debug)]
+pub enum RoleCommand {
+    /// create a role, users are given it with `user set-role`
+    Create {
+        name: String,
+        #[clap(long, default_value = "")]
+        description: String,
+        /// scope granted to users with the role, eg. users:read, can be repeated
+        #[clap(long)]
+        scope: Vec<String>,
+    },
+    /// list every role with its scopes
+    List,
+}
+
+pub async fn run(pool: &PgPool, command: RoleCommand) -> ClientResult<()> {
+    match command {
+        RoleCommand::Create { name, description, scope } => {
+            match roles::insert_role(pool, &name, &description, &scope).await {
+                Ok(id) => {
+                    println!("created {} {}", id, name);
+                    Ok(())
+                },
+                Err(RolesError::FailedInsertUniqueName) => Err(ClientError(format!("the role {} already exists", name))),
+                Err(e) => Err(ClientError(format!("{:?}", e))),
+            }
+        },
+        RoleCommand::List => {
+            let roles = roles::find_roles(pool).await.map_err(|e| ClientError(format!("{:?}", e)))?;
+
+            for role in roles {
+                println!("{:<20} {:>5} users  {:<60} {}", role.name, role.users, role.scopes.join(","), role.description);
+            }
+
+            Ok(())
+        },
+    }
+}
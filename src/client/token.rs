@@ -0,0 +1,98 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{TimeZone, Utc};
+use clap::Subcommand;
+use sqlx::postgres::PgPool;
+
+use crate::client::{connect, ClientError, ClientResult};
+use crate::common::config::Config;
+use crate::common::{jwt, keys};
+use crate::controller::users::{self, UsersError};
+
+#[derive(Subcommand, Debug)]
+pub enum TokenCommand {
+    /// forge an access token for a user, signed with the keyset of the config
+    Mint {
+        email: String,
+        /// scope of the token, can be repeated. The scopes of the users roles when none are given
+        #[clap(long)]
+        scope: Vec<String>,
+    },
+    /// print the header and claims of a token, and whether it verifies with the keyset
+    Inspect {
+        token: String,
+    },
+}
+
+pub async fn run(config: &Config, command: TokenCommand) -> ClientResult<()> {
+    match command {
+        TokenCommand::Mint { email, scope } => {
+            // the server would never accept a token signed with an ephemeral key
+            if config.tokens.keyset.is_none() {
+                return Err(ClientError(String::from("tokens.keyset is not set, a token can only be minted with the keyset of the server")))
+            }
+
+            let keys = keys::load(&config.tokens).map_err(|e| ClientError(format!("failed to load the keyset: {}", e)))?;
+            let pool = connect(config).await?;
+            let scopes = scopes_for(&pool, &email, scope).await?;
+
+            let tokens = jwt::ForgeOptions::new()
+                .subject(email)
+                .issuer(keys.issuer().to_owned())
+                .audience(keys.audience().to_vec())
+                .authorized_parties(keys.client_id().to_owned())
+                .scopes(scopes)
+                .forge(&keys)
+                .map_err(|e| ClientError(format!("failed to forge the token: {}", e)))?;
+
+            println!("{}", tokens.access_token);
+            Ok(())
+        },
+        TokenCommand::Inspect { token } => {
+            let mut parts = token.split('.');
+            let (header, claims) = match (parts.next(), parts.next(), parts.next()) {
+                (Some(h), Some(c), Some(_s)) => (decode_part(h)?, decode_part(c)?),
+                _ => return Err(ClientError(String::from("the token isn't a JWT, it needs three parts"))),
+            };
+
+            println!("header: {}", serde_json::to_string_pretty(&header).unwrap_or_default());
+            println!("claims: {}", serde_json::to_string_pretty(&claims).unwrap_or_default());
+
+            for claim in ["iat", "exp"] {
+                if let Some(at) = claims.get(claim).and_then(|v| v.as_i64()).and_then(|v| Utc.timestamp_opt(v, 0).single()) {
+                    println!("{}: {}", claim, at.to_rfc3339());
+                }
+            }
+
+            let keys = keys::load(&config.tokens).map_err(|e| ClientError(format!("failed to load the keyset: {}", e)))?;
+            match jwt::decode_token::<serde_json::Value>(&keys, &token) {
+                Ok(_v) => println!("valid"),
+                Err(jwt::Error::ExpiredToken) => println!("expired"),
+                Err(jwt::Error::DecodeError) => println!("invalid, it isn't signed with the keyset or isn't for our issuer and audience"),
+            }
+
+            Ok(())
+        },
+    }
+}
+
+// the scopes of the users roles, unless they're given
+async fn scopes_for(pool: &PgPool, email: &str, scope: Vec<String>) -> ClientResult<Vec<String>> {
+    let user_id = match users::find_user_id(pool, email).await {
+        Ok(v) => v,
+        Err(UsersError::NotFound) => return Err(ClientError(format!("no user with the email {}", email))),
+        Err(e) => return Err(ClientError(format!("{:?}", e))),
+    };
+
+    if !scope.is_empty() {
+        return Ok(scope)
+    }
+
+    users::find_scopes(pool, user_id).await.map_err(|e| ClientError(format!("{:?}", e)))
+}
+
+fn decode_part(part: &str) -> ClientResult<serde_json::Value> {
+    let bytes = URL_SAFE_NO_PAD.decode(part)
+        .map_err(|e| ClientError(format!("the token isn't base64url: {}", e)))?;
+
+    serde_json::from_slice(&bytes).map_err(|e| ClientError(format!("the token isn't json: {}", e)))
+}
@@ -0,0 +1,149 @@
+use clap::Subcommand;
+use rand::distributions::{Alphanumeric, DistString};
+use sqlx::postgres::PgPool;
+
+use crate::client::{ClientError, ClientResult};
+use crate::common::password_policy::PasswordPolicy;
+use crate::controller::users::{self, InsertUserParams, UsersError};
+
+#[derive(Subcommand, Debug)]
+pub enum UserCommand {
+    /// create a user, their email is confirmed since no code is sent
+    Create {
+        email: String,
+        /// a random password is generated and printed when it isn't given
+        #[clap(long)]
+        password: Option<String>,
+        #[clap(long, default_value = "default")]
+        role: String,
+    },
+    /// list every user with their roles
+    List,
+    /// stop a user from signing in, their sessions are revoked
+    Disable {
+        email: String,
+    },
+    /// let a disabled user sign in again
+    Enable {
+        email: String,
+    },
+    /// replace the roles of a user with one role
+    SetRole {
+        email: String,
+        role: String,
+    },
+    /// set a new password, their sessions are revoked
+    ResetPassword {
+        email: String,
+        /// a random password is generated and printed when it isn't given
+        #[clap(long)]
+        password: Option<String>,
+    },
+}
+
+pub async fn run(pool: &PgPool, policy: &PasswordPolicy, command: UserCommand) -> ClientResult<()> {
+    match command {
+        UserCommand::Create { email, password, role } => {
+            let password = password_for(policy, &email, password)?;
+            let params = InsertUserParams { email: email.clone(), password: password.value.clone(), role_name: role };
+
+            let user_id = users::insert_user(pool, &params).await.map_err(|e| user_error(&email, e))?;
+            users::confirm_user(pool, user_id).await.map_err(|e| user_error(&email, e))?;
+
+            println!("created {} {}", user_id, email);
+            password.print();
+            Ok(())
+        },
+        UserCommand::List => {
+            let users = users::find_users(pool).await.map_err(|e| user_error("", e))?;
+
+            for user in users {
+                let status = match (user.disabled_at, user.confirmed_at) {
+                    (Some(_v), _) => "disabled",
+                    (None, None) => "unconfirmed",
+                    (None, Some(_v)) => "active",
+                };
+
+                println!("{} {:<40} {:<12} {}", user.id, user.email, status, user.roles.join(","));
+            }
+
+            Ok(())
+        },
+        UserCommand::Disable { email } => {
+            users::set_disabled(pool, &email, true).await.map_err(|e| user_error(&email, e))?;
+            println!("disabled {}, their sessions are revoked", email);
+            Ok(())
+        },
+        UserCommand::Enable { email } => {
+            users::set_disabled(pool, &email, false).await.map_err(|e| user_error(&email, e))?;
+            println!("enabled {}", email);
+            Ok(())
+        },
+        UserCommand::SetRole { email, role } => {
+            users::set_role(pool, &email, &role).await.map_err(|e| user_error(&email, e))?;
+            println!("{} has the role {}, it's in their tokens from their next login or refresh", email, role);
+            Ok(())
+        },
+        UserCommand::ResetPassword { email, password } => {
+            let password = password_for(policy, &email, password)?;
+
+            users::set_password(pool, &email, &password.value).await.map_err(|e| user_error(&email, e))?;
+
+            println!("password of {} is reset, their sessions are revoked", email);
+            password.print();
+            Ok(())
+        },
+    }
+}
+
+struct NewPassword {
+    value: String,
+    generated: bool,
+}
+
+impl NewPassword {
+    // a generated password is only ever shown here
+    fn print(&self) {
+        if self.generated {
+            println!("password: {}", self.value);
+        }
+    }
+}
+
+// a given password has to follow the policy, like it does on the forms
+fn password_for(policy: &PasswordPolicy, email: &str, password: Option<String>) -> ClientResult<NewPassword> {
+    match password {
+        Some(value) => match policy.check(&value, email) {
+            Ok(()) => Ok(NewPassword { value, generated: false }),
+            Err(violation) => Err(ClientError(format!("the password is refused: {}", violation.code()))),
+        },
+        None => generate_password(policy, email).map(|value| NewPassword { value, generated: true }),
+    }
+}
+
+// groups of random letters and digits joined with `-`, until one follows the policy
+fn generate_password(policy: &PasswordPolicy, email: &str) -> ClientResult<String> {
+    let groups = (policy.min_length / 5 + 1).max(4);
+
+    for _ in 0..100 {
+        let password = (0..groups)
+            .map(|_i| Alphanumeric.sample_string(&mut rand::thread_rng(), 4))
+            .collect::<Vec<String>>()
+            .join("-");
+
+        if policy.check(&password, email).is_ok() {
+            return Ok(password)
+        }
+    }
+
+    Err(ClientError(String::from("failed to generate a password that follows the policy, give one with --password")))
+}
+
+fn user_error(email: &str, e: UsersError) -> ClientError {
+    match e {
+        UsersError::NotFound => ClientError(format!("no user with the email {}", email)),
+        UsersError::FailedUserInsertUniqueEmail => ClientError(format!("{} is already in use", email)),
+        UsersError::FailedRoleNameLookup => ClientError(String::from("no role with that name, see `client role list`")),
+        e => ClientError(format!("{:?}", e)),
+    }
+}
@@ -0,0 +1,120 @@
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use axum::http::HeaderMap;
+use ipnet::IpNet;
+
+use crate::common::config::ServerConfig;
+
+/// The address of the client a request came from, inserted into every request by `multiplex`.
+/// Logins are throttled by it and sessions are tagged with it.
+#[derive(Debug, Clone, Copy)]
+pub struct ClientIp(pub IpAddr);
+
+/// Networks of the reverse proxies in front of the server, their `X-Forwarded-For` is believed
+pub type TrustedProxies = Arc<Vec<IpNet>>;
+
+/// The `server.trusted_proxies` networks, a bare address is a network of one
+pub fn load(config: &ServerConfig) -> TrustedProxies {
+    Arc::new(config.trusted_proxies.iter().filter_map(|p| parse_network(p)).collect())
+}
+
+/// `10.0.0.0/8` or `10.0.0.1`
+pub fn parse_network(value: &str) -> Option<IpNet> {
+    match value.parse::<IpNet>() {
+        Ok(v) => Some(v),
+        Err(_e) => value.parse::<IpAddr>().ok().map(IpNet::from),
+    }
+}
+
+/// The peer, unless it's a trusted proxy. Then `X-Forwarded-For` is read from the right, every
+/// proxy appends the address it got the request from, and the first hop that isn't a trusted
+/// proxy is the client. Hops left of it were sent by the client and could be anything.
+pub fn resolve(proxies: &[IpNet], peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+    let trusted = |ip: &IpAddr| proxies.iter().any(|p| p.contains(ip));
+
+    if !trusted(&peer) {
+        return peer
+    }
+
+    let hops: Vec<&str> = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|v| v.trim())
+        .collect();
+
+    let mut client = peer;
+    for hop in hops.iter().rev() {
+        // a hop that isn't an address can't be trusted, nor anything left of it
+        let ip = match hop.parse::<IpAddr>() {
+            Ok(v) => v,
+            Err(_e) => return client,
+        };
+
+        if !trusted(&ip) {
+            return ip
+        }
+
+        client = ip;
+    }
+
+    client
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(forwarded_for: &[&str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for v in forwarded_for {
+            headers.append("x-forwarded-for", v.parse().unwrap());
+        }
+        headers
+    }
+
+    fn proxies() -> Vec<IpNet> {
+        vec![parse_network("10.0.0.0/8").unwrap(), parse_network("192.168.1.1").unwrap()]
+    }
+
+    #[test]
+    fn untrusted_peer_is_the_client() {
+        let peer: IpAddr = "203.0.113.7".parse().unwrap();
+        assert_eq!(resolve(&proxies(), peer, &headers(&["198.51.100.1"])), peer);
+    }
+
+    #[test]
+    fn no_proxies_ignores_the_header() {
+        let peer: IpAddr = "10.0.0.2".parse().unwrap();
+        assert_eq!(resolve(&[], peer, &headers(&["198.51.100.1"])), peer);
+    }
+
+    #[test]
+    fn trusted_peer_takes_the_first_untrusted_hop_from_the_right() {
+        let peer: IpAddr = "10.0.0.2".parse().unwrap();
+        let client = resolve(&proxies(), peer, &headers(&["1.1.1.1, 198.51.100.1, 192.168.1.1"]));
+        assert_eq!(client, "198.51.100.1".parse::<IpAddr>().unwrap());
+    }
+
+    #[test]
+    fn repeated_headers_are_one_list() {
+        let peer: IpAddr = "10.0.0.2".parse().unwrap();
+        let client = resolve(&proxies(), peer, &headers(&["198.51.100.1", "10.1.1.1"]));
+        assert_eq!(client, "198.51.100.1".parse::<IpAddr>().unwrap());
+    }
+
+    #[test]
+    fn garbage_hop_stops_at_the_last_trusted_one() {
+        let peer: IpAddr = "10.0.0.2".parse().unwrap();
+        let client = resolve(&proxies(), peer, &headers(&["198.51.100.1, unknown, 10.1.1.1"]));
+        assert_eq!(client, "10.1.1.1".parse::<IpAddr>().unwrap());
+    }
+
+    #[test]
+    fn trusted_peer_without_header_is_the_client() {
+        let peer: IpAddr = "10.0.0.2".parse().unwrap();
+        assert_eq!(resolve(&proxies(), peer, &HeaderMap::new()), peer);
+    }
+}
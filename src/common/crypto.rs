@@ -1,3 +1,6 @@
+use std::sync::OnceLock;
+
+use argon2::{Algorithm, Argon2, Params, Version};
 use pbkdf2::{
     password_hash::{
         rand_core::OsRng,
@@ -5,21 +8,182 @@ use pbkdf2::{
     },
     Pbkdf2
 };
+use scrypt::Scrypt;
 
-pub fn hash_password(password: String) -> String {
-    let salt = SaltString::generate(&mut OsRng);
-    // Hash password to PHC string ($pbkdf2-sha256$...)
-    Pbkdf2.hash_password(password.as_bytes(), &salt).unwrap().to_string()
+use crate::common::config::Argon2Config;
+
+#[derive(Debug)]
+pub enum PasswordError {
+    // the stored hash isn't a PHC string of a supported algorithm
+    InvalidHash(String),
+    FailedHash(String),
 }
 
-pub fn validate_password(provided: String, actual: String) -> bool {
-    let pw_hash = PasswordHash::new(&provided).unwrap();
-    match Pbkdf2.verify_password(actual.as_bytes(), &pw_hash) {
-        Ok(_v) => {
-            true
+impl std::fmt::Display for PasswordError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PasswordError::InvalidHash(e) => write!(f, "invalid password hash: {}", e),
+            PasswordError::FailedHash(e) => write!(f, "failed to hash the password: {}", e),
         }
-        Err(_e) => {
-            false
+    }
+}
+
+/// Argon2id parameters for new hashes, set from the `[argon2]` config at startup. The defaults
+/// are the OWASP recommendation, 19 MiB, 2 iterations, 1 lane.
+static ARGON2_PARAMS: OnceLock<Params> = OnceLock::new();
+
+/// Set the parameters of new hashes, before the first password is hashed. The config has
+/// already been validated, so the parameters are known to be accepted.
+pub fn configure_argon2(config: &Argon2Config) {
+    let params = Params::new(config.memory_kib, config.iterations, config.parallelism, None)
+        .expect("invalid argon2 parameters");
+
+    if ARGON2_PARAMS.set(params).is_err() {
+        println!("argon2 parameters are already set");
+    }
+}
+
+fn argon2_params() -> &'static Params {
+    ARGON2_PARAMS.get_or_init(|| {
+        let config = Argon2Config::default();
+        Params::new(config.memory_kib, config.iterations, config.parallelism, None)
+            .expect("invalid argon2 parameters")
+    })
+}
+
+fn argon2() -> Argon2<'static> {
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, argon2_params().clone())
+}
+
+/// Hash a new password with Argon2id, to a PHC string ($argon2id$v=19$m=...)
+pub fn hash_password(password: &str) -> Result<String, PasswordError> {
+    let salt = SaltString::generate(&mut OsRng);
+
+    match argon2().hash_password(password.as_bytes(), &salt) {
+        Ok(v) => Ok(v.to_string()),
+        Err(e) => Err(PasswordError::FailedHash(e.to_string())),
+    }
+}
+
+/// Check a password against the stored PHC string, it can be an argon2id, pbkdf2 or scrypt hash.
+/// The parameters are read from the hash, so hashes made with older parameters still verify.
+pub fn verify_password(stored: &str, password: &str) -> Result<bool, PasswordError> {
+    let hash = PasswordHash::new(stored).map_err(|e| PasswordError::InvalidHash(e.to_string()))?;
+
+    let verifier: &dyn PasswordVerifier = match hash.algorithm.as_str() {
+        "argon2id" | "argon2i" | "argon2d" => &Argon2::default(),
+        "pbkdf2-sha256" | "pbkdf2-sha512" | "pbkdf2" => &Pbkdf2,
+        "scrypt" => &Scrypt,
+        other => return Err(PasswordError::InvalidHash(format!("unsupported algorithm {}", other))),
+    };
+
+    match verifier.verify_password(password.as_bytes(), &hash) {
+        Ok(_v) => Ok(true),
+        Err(pbkdf2::password_hash::Error::Password) => Ok(false),
+        Err(e) => Err(PasswordError::InvalidHash(e.to_string())),
+    }
+}
+
+/// Whether a hash should be replaced with a new one the next time the password is known,
+/// because it's another algorithm, or Argon2id with weaker parameters than the current ones
+pub fn needs_rehash(stored: &str) -> bool {
+    let hash = match PasswordHash::new(stored) {
+        Ok(v) => v,
+        Err(_e) => return true,
+    };
+
+    if hash.algorithm.as_str() != "argon2id" || hash.version != Some(Version::V0x13.into()) {
+        return true
+    }
+
+    match Params::try_from(&hash) {
+        Ok(params) => {
+            let current = argon2_params();
+            params.m_cost() < current.m_cost()
+                || params.t_cost() < current.t_cost()
+                || params.p_cost() < current.p_cost()
+        },
+        Err(_e) => true,
+    }
+}
+
+/// sha256 of a short lived secret like a confirmation code, hex encoded. These are random and
+/// expire quickly so they don't need a salted password hash, and can be looked up by their hash.
+pub fn hash_token(token: &str) -> String {
+    ring::digest::digest(&ring::digest::SHA256, token.as_bytes())
+        .as_ref()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Compare two hashes without leaking where they differ
+pub fn hashes_match(a: &str, b: &str) -> bool {
+    ring::constant_time::verify_slices_are_equal(a.as_bytes(), b.as_bytes()).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // small parameters, these only have to be valid hashes of their algorithm
+    fn weak_argon2(algorithm: Algorithm, password: &str) -> String {
+        let salt = SaltString::generate(&mut OsRng);
+        Argon2::new(algorithm, Version::V0x13, Params::new(8, 1, 1, None).unwrap())
+            .hash_password(password.as_bytes(), &salt)
+            .unwrap()
+            .to_string()
+    }
+
+    fn pbkdf2(password: &str) -> String {
+        let salt = SaltString::generate(&mut OsRng);
+        let params = pbkdf2::Params { rounds: 1000, output_length: 32 };
+        Pbkdf2.hash_password_customized(password.as_bytes(), None, None, params, &salt).unwrap().to_string()
+    }
+
+    fn scrypt(password: &str) -> String {
+        let salt = SaltString::generate(&mut OsRng);
+        let params = scrypt::Params::new(4, 8, 1, 32).unwrap();
+        Scrypt.hash_password_customized(password.as_bytes(), None, None, params, &salt).unwrap().to_string()
+    }
+
+    #[test]
+    fn new_hashes_are_argon2id_and_verify() {
+        let hash = hash_password("correct horse").unwrap();
+        assert!(hash.starts_with("$argon2id$v=19$"));
+        assert!(verify_password(&hash, "correct horse").unwrap());
+        assert!(!verify_password(&hash, "wrong horse").unwrap());
+        assert!(!needs_rehash(&hash));
+    }
+
+    #[test]
+    fn verifies_every_supported_algorithm() {
+        for hash in [weak_argon2(Algorithm::Argon2id, "secret"), weak_argon2(Algorithm::Argon2i, "secret"), pbkdf2("secret"), scrypt("secret")] {
+            assert!(verify_password(&hash, "secret").unwrap(), "{}", hash);
+            assert!(!verify_password(&hash, "Secret").unwrap(), "{}", hash);
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn rehashes_other_algorithms_and_weaker_parameters() {
+        assert!(needs_rehash(&pbkdf2("secret")));
+        assert!(needs_rehash(&scrypt("secret")));
+        assert!(needs_rehash(&weak_argon2(Algorithm::Argon2i, "secret")));
+        assert!(needs_rehash(&weak_argon2(Algorithm::Argon2id, "secret")));
+        assert!(needs_rehash("not a hash"));
+    }
+
+    #[test]
+    fn rejects_invalid_hashes() {
+        assert!(matches!(verify_password("not a hash", "secret"), Err(PasswordError::InvalidHash(_))));
+        assert!(matches!(verify_password("$bcrypt$v=1$c2FsdHNhbHQ$aGFzaA", "secret"), Err(PasswordError::InvalidHash(_))));
+    }
+
+    #[test]
+    fn token_hashes() {
+        // sha256("abc")
+        assert_eq!(hash_token("abc"), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert!(hashes_match(&hash_token("abc"), &hash_token("abc")));
+        assert!(!hashes_match(&hash_token("abc"), &hash_token("abd")));
+    }
+}
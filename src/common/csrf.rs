@@ -0,0 +1,280 @@
+use std::sync::Arc;
+
+use axum::{
+    async_trait,
+    body::{Bytes, HttpBody},
+    extract::{FromRequest, FromRequestParts},
+    http::{header::AUTHORIZATION, request::Parts, HeaderMap, Request, StatusCode},
+    response::{IntoResponse, Response},
+    BoxError,
+};
+use axum_session::{Session, SessionPgPool};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::Utc;
+use rand::RngCore;
+use ring::hmac;
+use serde::{de::DeserializeOwned, Deserialize};
+
+use crate::common::config::CsrfConfig;
+
+/// CsrfKeys is the shared handle to the signing key that is passed around as an `Extension`
+pub type CsrfKeys = Arc<CsrfKey>;
+
+/// how long a form can be left open before it has to be reloaded
+pub const TOKEN_TTL_SECONDS: i64 = 2 * 60 * 60;
+/// bytes of randomness in every token, two tokens are never the same
+const NONCE_LENGTH: usize = 16;
+
+/// Key the authenticity tokens are signed with
+pub struct CsrfKey(hmac::Key);
+
+impl CsrfKey {
+    pub fn new(secret: &[u8]) -> Self {
+        CsrfKey(hmac::Key::new(hmac::HMAC_SHA256, secret))
+    }
+
+    /// A token for the session with `session_id`, issued at `issued_at` (seconds)
+    ///
+    /// `<issued_at>.<nonce>.<signature>`, the signature covers the session id so a token
+    /// can't be used with another session
+    pub fn sign(&self, session_id: &str, issued_at: i64) -> String {
+        let mut nonce = [0u8; NONCE_LENGTH];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let nonce = URL_SAFE_NO_PAD.encode(nonce);
+
+        let tag = hmac::sign(&self.0, message(session_id, issued_at, &nonce).as_bytes());
+
+        format!("{}.{}.{}", issued_at, nonce, URL_SAFE_NO_PAD.encode(tag.as_ref()))
+    }
+
+    /// Check a token was signed for the session and hasn't expired at `now` (seconds).
+    /// The signature is compared in constant time.
+    pub fn verify(&self, session_id: &str, token: &str, now: i64) -> bool {
+        let mut parts = token.splitn(3, '.');
+        let (issued_at, nonce, signature) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(i), Some(n), Some(s)) => (i, n, s),
+            _ => return false,
+        };
+
+        let issued_at = match issued_at.parse::<i64>() {
+            Ok(v) => v,
+            Err(_e) => return false,
+        };
+
+        // a little leeway for clocks of other instances
+        if issued_at > now + 60 || now - issued_at > TOKEN_TTL_SECONDS {
+            return false
+        }
+
+        let signature = match URL_SAFE_NO_PAD.decode(signature) {
+            Ok(v) => v,
+            Err(_e) => return false,
+        };
+
+        hmac::verify(&self.0, message(session_id, issued_at, nonce).as_bytes(), &signature).is_ok()
+    }
+}
+
+fn message(session_id: &str, issued_at: i64, nonce: &str) -> String {
+    format!("{}.{}.{}", session_id, issued_at, nonce)
+}
+
+/// Load the key from `csrf.secret`, at least 32 bytes. Without it a random key is made, and
+/// forms that were open when the server restarted have to be reloaded.
+pub fn load(config: &CsrfConfig) -> CsrfKeys {
+    match &config.secret {
+        Some(v) => Arc::new(CsrfKey::new(v.as_bytes())),
+        None => {
+            println!("csrf.secret is not set, authenticity tokens are signed with a random key");
+            let mut secret = [0u8; 32];
+            rand::thread_rng().fill_bytes(&mut secret);
+            Arc::new(CsrfKey::new(&secret))
+        }
+    }
+}
+
+/// Whether the request is from an api client, they send their access token in a header.
+/// A browser can't be made to send it by another site, so there's nothing to forge.
+pub fn is_bearer(headers: &HeaderMap) -> bool {
+    headers
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.starts_with("Bearer "))
+        .unwrap_or(false)
+}
+
+/// Issues authenticity tokens for the forms of a page
+///
+/// ```ignore
+/// context.insert("authenticity_token", &csrf.token());
+/// ```
+pub struct Csrf {
+    key: CsrfKeys,
+    session_id: String,
+}
+
+impl Csrf {
+    pub fn token(&self) -> String {
+        self.key.sign(&self.session_id, Utc::now().timestamp())
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Csrf
+where
+    S: Send + Sync,
+{
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let key = match parts.extensions.get::<CsrfKeys>() {
+            Some(v) => v.clone(),
+            None => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+        };
+
+        let session = match parts.extensions.get::<Session<SessionPgPool>>() {
+            Some(v) => v.clone(),
+            None => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+        };
+
+        Ok(Csrf { key, session_id: session.get_session_id().await.inner() })
+    }
+}
+
+#[derive(Deserialize)]
+struct AuthenticityToken {
+    authenticity_token: Option<String>,
+}
+
+/// The form was missing a valid authenticity token
+pub struct CsrfRejection(StatusCode);
+
+impl IntoResponse for CsrfRejection {
+    fn into_response(self) -> Response {
+        match self.0 {
+            StatusCode::FORBIDDEN => (self.0, "The form has expired, go back and reload the page").into_response(),
+            status => status.into_response(),
+        }
+    }
+}
+
+/// A `Form` that has to come with the `authenticity_token` of the session, it's used in
+/// place of `Form` by every handler of a form
+///
+/// ```ignore
+/// CsrfForm(req): CsrfForm<LoginRequest>,
+/// ```
+pub struct CsrfForm<T>(pub T);
+
+#[async_trait]
+impl<T, S, B> FromRequest<S, B> for CsrfForm<T>
+where
+    T: DeserializeOwned,
+    B: HttpBody + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+    S: Send + Sync,
+{
+    type Rejection = CsrfRejection;
+
+    async fn from_request(req: Request<B>, state: &S) -> Result<Self, Self::Rejection> {
+        let bearer = is_bearer(req.headers());
+        let key = req.extensions().get::<CsrfKeys>().cloned();
+        let session = req.extensions().get::<Session<SessionPgPool>>().cloned();
+
+        let body = match Bytes::from_request(req, state).await {
+            Ok(v) => v,
+            Err(_e) => return Err(CsrfRejection(StatusCode::BAD_REQUEST)),
+        };
+
+        if !bearer {
+            let (key, session) = match (key, session) {
+                (Some(k), Some(s)) => (k, s),
+                _ => return Err(CsrfRejection(StatusCode::INTERNAL_SERVER_ERROR)),
+            };
+
+            let token = match serde_urlencoded::from_bytes::<AuthenticityToken>(&body) {
+                Ok(AuthenticityToken { authenticity_token: Some(v) }) => v,
+                _ => return Err(CsrfRejection(StatusCode::FORBIDDEN)),
+            };
+
+            let session_id = session.get_session_id().await.inner();
+            if !key.verify(&session_id, &token, Utc::now().timestamp()) {
+                println!("authenticity token rejected");
+                return Err(CsrfRejection(StatusCode::FORBIDDEN))
+            }
+        }
+
+        match serde_urlencoded::from_bytes::<T>(&body) {
+            Ok(v) => Ok(CsrfForm(v)),
+            Err(e) => {
+                println!("failed to read the form {}", e);
+                Err(CsrfRejection(StatusCode::UNPROCESSABLE_ENTITY))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: i64 = 1_700_000_000;
+
+    fn key() -> CsrfKey {
+        CsrfKey::new(b"0123456789abcdef0123456789abcdef")
+    }
+
+    #[test]
+    fn verifies_its_own_token() {
+        let token = key().sign("session", NOW);
+        assert!(key().verify("session", &token, NOW));
+    }
+
+    #[test]
+    fn tokens_are_never_the_same() {
+        assert_ne!(key().sign("session", NOW), key().sign("session", NOW));
+    }
+
+    #[test]
+    fn rejects_another_session() {
+        let token = key().sign("session", NOW);
+        assert!(!key().verify("other", &token, NOW));
+    }
+
+    #[test]
+    fn rejects_another_key() {
+        let token = key().sign("session", NOW);
+        assert!(!CsrfKey::new(b"another key, another key, another").verify("session", &token, NOW));
+    }
+
+    #[test]
+    fn expires_after_the_ttl() {
+        let token = key().sign("session", NOW);
+        assert!(key().verify("session", &token, NOW + TOKEN_TTL_SECONDS));
+        assert!(!key().verify("session", &token, NOW + TOKEN_TTL_SECONDS + 1));
+    }
+
+    #[test]
+    fn allows_a_minute_of_clock_skew() {
+        let token = key().sign("session", NOW + 60);
+        assert!(key().verify("session", &token, NOW));
+
+        let token = key().sign("session", NOW + 61);
+        assert!(!key().verify("session", &token, NOW));
+    }
+
+    #[test]
+    fn rejects_a_changed_issued_at() {
+        let token = key().sign("session", NOW);
+        let (_issued_at, rest) = token.split_once('.').unwrap();
+        assert!(!key().verify("session", &format!("{}.{}", NOW + 1, rest), NOW));
+    }
+
+    #[test]
+    fn rejects_malformed_tokens() {
+        for token in ["", "abc", "1.2", "x.nonce.signature", &format!("{}.nonce.not base64", NOW)] {
+            assert!(!key().verify("session", token, NOW), "{}", token);
+        }
+    }
+}
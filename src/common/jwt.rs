@@ -1,28 +1,37 @@
 use std::fmt::Debug;
 use chrono::{Utc, Duration};
 use serde::{Serialize, Deserialize};
+use uuid::Uuid;
 use jsonwebtoken::{
     decode, 
     encode, 
-    Algorithm, 
-    DecodingKey, 
-    EncodingKey, 
     Header, 
     Validation, 
     TokenData,
+    decode_header,
     errors::ErrorKind
 };
 
+use crate::common::keys::KeyStore;
+
 #[derive(Debug)]
 pub struct TokenError(String);
 
 pub type JwtResult<T> = Result<T, TokenError>;
 
+impl std::fmt::Display for TokenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
 #[derive(Debug)]
 pub struct Tokens {
     pub id_token: String,
     pub access_token: String,
     pub refresh_token: Option<String>,
+    // claims of the forged refresh token, so it can be persisted to the `refresh_tokens` table
+    pub refresh_token_claims: Option<RefreshTokenClaims>,
 }
 
 #[derive(Default, Clone)]
@@ -47,6 +56,12 @@ pub struct ForgeOptions {
     // what access the token is for. For, example a scope might have `users` in it. That could 
     // be used as a mapping to the acl for which a user would have some type of access to the `users` resource.
     pub scope: Vec<String>,
+    // amr, the authentication methods the user signed in with, eg. `pwd` and `otp`
+    // ref: https://www.rfc-editor.org/rfc/rfc8176
+    pub amr: Vec<String>,
+    // jti and exp of a refresh token that is already in the `refresh_tokens` table, it's signed
+    // again instead of a new one being minted
+    pub refresh_token_id: Option<(String, i64)>,
 }
 
 impl ForgeOptions {
@@ -58,6 +73,8 @@ impl ForgeOptions {
             audience: vec![],
             authorized_parties: String::new(),
             scope: vec![],
+            amr: vec![],
+            refresh_token_id: None,
         }
     }
 
@@ -95,15 +112,25 @@ impl ForgeOptions {
         self
     }
 
-    // forge executes the builder returning the tokens
-    pub fn forge(self) -> JwtResult<Tokens> {
-        forge_tokens(self)
+    pub fn authentication_methods(mut self, amr: Vec<String>) -> Self {
+        self.amr = amr;
+        self
+    }
+
+    pub fn reissue_refresh_token(mut self, jti: String, exp: i64) -> Self {
+        self.refresh_token_id = Some((jti, exp));
+        self
+    }
+
+    // forge executes the builder returning the tokens signed with the active key
+    pub fn forge(self, keys: &KeyStore) -> JwtResult<Tokens> {
+        forge_tokens(keys, self)
     }
 }
 
-pub fn forge_tokens(options: ForgeOptions) -> JwtResult<Tokens> { 
-    let key = b"secret";
-    let header = Header { kid: Some("signing_key".to_owned()), alg: Algorithm::HS512, ..Default::default() };
+pub fn forge_tokens(keys: &KeyStore, options: ForgeOptions) -> JwtResult<Tokens> { 
+    let (kid, alg, key) = keys.signing_key();
+    let header = Header { kid: Some(kid.to_owned()), alg, ..Default::default() };
     let now = Utc::now();
     let access_token_expiry = now + Duration::minutes(60);
     let id_token_expiry = now + Duration::days(365);
@@ -113,6 +140,7 @@ pub fn forge_tokens(options: ForgeOptions) -> JwtResult<Tokens> {
         access_token: String::new(),
         id_token: String::new(),
         refresh_token: None,
+        refresh_token_claims: None,
     };
 
     match encode(&header, &AccessTokenClaims{
@@ -121,9 +149,11 @@ pub fn forge_tokens(options: ForgeOptions) -> JwtResult<Tokens> {
         aud: options.audience.clone(),
         azp: options.authorized_parties.clone(),
         exp: access_token_expiry.timestamp(),
-        iat: access_token_expiry.timestamp(),
+        iat: now.timestamp(),
         scope: options.scope.clone(),
-    }, &EncodingKey::from_secret(key)) {
+        amr: options.amr.clone(),
+        jti: Uuid::new_v4().to_string(),
+    }, key) {
         Ok(t) => {
             println!("access_token minted");
             tokens.access_token = t.clone();
@@ -139,9 +169,10 @@ pub fn forge_tokens(options: ForgeOptions) -> JwtResult<Tokens> {
         sub: options.subject.clone(),
         aud: options.audience.clone(),
         exp: id_token_expiry.timestamp(),
-        iat: id_token_expiry.timestamp(), 
+        iat: now.timestamp(),
         email: options.subject.clone(),
-    }, &EncodingKey::from_secret(key)) {
+        amr: options.amr.clone(),
+    }, key) {
         Ok(t) => {
             println!("id_token minted");
             tokens.id_token = t.clone()
@@ -152,21 +183,31 @@ pub fn forge_tokens(options: ForgeOptions) -> JwtResult<Tokens> {
         }
     }
 
-    if options.offline_mode == true {
+    if options.offline_mode {
         println!("offline_mode is true");
 
-        match encode(&header, &RefreshTokenClaims{
+        let (jti, exp) = match &options.refresh_token_id {
+            Some((jti, exp)) => (jti.clone(), *exp),
+            None => (Uuid::new_v4().to_string(), refresh_token_expiry.timestamp()),
+        };
+
+        let claims = RefreshTokenClaims{
             iss: options.issuer.clone(),
             sub: options.subject.clone(),
             aud: options.audience.clone(),
-            exp: refresh_token_expiry.timestamp(),
-            iat: refresh_token_expiry.timestamp(),
+            exp,
+            iat: now.timestamp(),
             scope: options.scope.clone(), 
             client_id: options.authorized_parties.clone(),
-        }, &EncodingKey::from_secret(key)) {
+            jti,
+            amr: options.amr.clone(),
+        };
+
+        match encode(&header, &claims, key) {
             Ok(t) => {
                 println!("refresh_token minted");
-                tokens.refresh_token = Some(t.clone())
+                tokens.refresh_token = Some(t.clone());
+                tokens.refresh_token_claims = Some(claims);
             },
             Err(e) => {
                 println!("failed to min refresh token");
@@ -181,13 +222,52 @@ pub fn forge_tokens(options: ForgeOptions) -> JwtResult<Tokens> {
 #[derive(Debug)]
 pub enum Error { 
     DecodeError,
+    // the signature is valid but the token is past its `exp`, it can be renewed with a refresh token
+    ExpiredToken,
+}
+
+/// Look up the key named by the `kid` in the token header, and build the validation for it.
+/// Tokens signed with a retired key are still accepted, unknown or missing `kid`s are not.
+/// The `iss` has to be ours, and the `aud` has to contain one of our audiences.
+fn verifying_key<'a>(keys: &'a KeyStore, token: &str) -> Option<(Validation, &'a jsonwebtoken::DecodingKey)> {
+    let header = match decode_header(token) {
+        Ok(h) => h,
+        Err(_e) => {
+            println!("failed to decode token header");
+            return None
+        }
+    };
+
+    let kid = match header.kid {
+        Some(kid) => kid,
+        None => {
+            println!("token has no kid");
+            return None
+        }
+    };
+
+    match keys.verifying_key(&kid) {
+        Some((alg, key)) => {
+            let mut validation = Validation::new(alg);
+            validation.set_issuer(&[keys.issuer()]);
+            validation.set_audience(keys.audience());
+            Some((validation, key))
+        },
+        None => {
+            println!("unknown kid: {}", kid);
+            None
+        }
+    }
 }
 
 /// Check to tokens validity, and decode into specified claims
-pub fn decode_token<T: for<'de> Deserialize<'de> + Debug>(token: &str) -> Result<TokenData<T>, Error> {
-    let validation = Validation::new(Algorithm::HS512);
+pub fn decode_token<T: for<'de> Deserialize<'de> + Debug>(keys: &KeyStore, token: &str) -> Result<TokenData<T>, Error> {
+    let (validation, key) = match verifying_key(keys, token) {
+        Some(v) => v,
+        None => return Err(Error::DecodeError),
+    };
 
-    match decode::<T>(&token, &DecodingKey::from_secret(b"secret"), &validation) {
+    match decode::<T>(token, key, &validation) {
         Ok(c) => { 
             Ok(c)
         },
@@ -200,9 +280,13 @@ pub fn decode_token<T: for<'de> Deserialize<'de> + Debug>(token: &str) -> Result
                 println!("Issuer is invalid"); // Example on how to handle a specific error
                 Err(Error::DecodeError)
             }
+            ErrorKind::InvalidAudience => {
+                println!("Audience is invalid");
+                Err(Error::DecodeError)
+            }
             ErrorKind::ExpiredSignature => {
                 println!{"expired token signature"};
-                Err(Error::DecodeError)
+                Err(Error::ExpiredToken)
             }
             _ => {
                 println!("Some other errors");
@@ -213,32 +297,34 @@ pub fn decode_token<T: for<'de> Deserialize<'de> + Debug>(token: &str) -> Result
     }
 }
 
-pub fn is_token_valid<T: for<'de> Deserialize<'de> + Debug>(token: &str) -> bool {
-    let validation = Validation::new(Algorithm::HS512);
+pub fn is_token_valid<T: for<'de> Deserialize<'de> + Debug>(keys: &KeyStore, token: &str) -> bool {
+    let (validation, key) = match verifying_key(keys, token) {
+        Some(v) => v,
+        None => return false,
+    };
 
-    match decode::<T>(&token, &DecodingKey::from_secret(b"secret"), &validation) {
+    match decode::<T>(token, key, &validation) {
         Ok(c) => {
             println!("{:?}", c);
-            return true 
+            true
         },
         Err(err) => match *err.kind() {
             ErrorKind::InvalidToken => {
                 println!("Token is invalid"); // Example on how to handle a specific error
-                return false
+                false
             }
             ErrorKind::InvalidIssuer => {
                 println!("Issuer is invalid"); // Example on how to handle a specific error
-                return false
+                false
             }
             ErrorKind::ExpiredSignature => {
                 println!{"expired token signature"};
-
-                return false
+                false
             }
             _ => {
                 println!("Some other errors");
                 println!{"error: {:?}", *err.kind()}
-                return false
+                false
             }
         },
     }
@@ -257,15 +343,28 @@ pub fn is_token_valid<T: for<'de> Deserialize<'de> + Debug>(token: &str) -> bool
 ///     "iat": 1311280970,
 ///     "scope": "openid profile read:patients read:admin"
 ///   }
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AccessTokenClaims {
     iss: String,
-    sub: String,
+    pub sub: String,
     aud: Vec<String>,
     azp: String,
-    exp: i64,
-    iat: i64,
-    scope: Vec<String>,
+    pub exp: i64,
+    // compared with `users.sessions_revoked_at` to reject tokens issued before a password reset
+    pub iat: i64,
+    pub scope: Vec<String>,
+    // tokens forged before `amr` was added don't have it
+    #[serde(default)]
+    pub amr: Vec<String>,
+    // id of the token, it's put on the `revoked_access_tokens` list at logout
+    #[serde(default)]
+    pub jti: String,
+}
+
+impl AccessTokenClaims {
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scope.iter().any(|s| s == scope)
+    }
 }
 
 // pub fn forge_access_token(email: &str) -> JwtResult<AccessToken> {
@@ -282,7 +381,7 @@ pub struct AccessTokenClaims {
 //         exp: access_token_expiry.timestamp(),
 //         iat: access_token_expiry.timestamp(),
 //         scope: vec![String::from("retail")],
-//     }, &EncodingKey::from_secret(key)) {
+//     }, key) {
 //         Ok(t) => {
 //             println!("access_token minted");
 //             return Ok(AccessToken{
@@ -309,16 +408,22 @@ pub struct AccessTokenClaims {
 ///     "iat": 1311280970,
 ///     "scope": "openid profile read:patients read:admin"
 ///     "client_id": "my_client_id",
+///     "jti": "4f1c9d6e-7e0b-4b8e-9d7a-1f8f0a3c2b5d"
 ///   }
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RefreshTokenClaims {
-    iss: String,
-    sub: String,
-    aud: Vec<String>,
-    exp: i64,
+    pub iss: String,
+    pub sub: String,
+    pub aud: Vec<String>,
+    pub exp: i64,
     iat: i64,
-    scope: Vec<String>,
-    client_id: String,
+    pub scope: Vec<String>,
+    pub client_id: String,
+    // id of the token in the `refresh_tokens` table
+    pub jti: String,
+    // carried over to the renewed tokens, renewing isn't signing in again
+    #[serde(default)]
+    pub amr: Vec<String>,
 }
 
 // ref: https://auth0.com/docs/secure/tokens/id-tokens/id-token-structure
@@ -332,7 +437,7 @@ pub struct RefreshTokenClaims {
 //     "email": "janedoe@example.com",
 //     "picture": "http://example.com/janedoe/me.jpg"
 //   }
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct IdTokenClaims {
     iss: String,
     sub: String,
@@ -340,4 +445,6 @@ pub struct IdTokenClaims {
     exp: i64,
     iat: i64,
     pub email: String,
+    #[serde(default)]
+    amr: Vec<String>,
 }
\ No newline at end of file
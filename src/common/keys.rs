@@ -0,0 +1,432 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{
+    jwk::{
+        AlgorithmParameters,
+        CommonParameters,
+        EllipticCurve,
+        Jwk,
+        JwkSet,
+        OctetKeyPairParameters,
+        OctetKeyPairType,
+        PublicKeyUse,
+        RSAKeyParameters,
+        RSAKeyType,
+    },
+    Algorithm,
+    DecodingKey,
+    EncodingKey,
+};
+use rand::distributions::{Alphanumeric, DistString};
+use ring::signature::{Ed25519KeyPair, KeyPair, RsaKeyPair};
+use serde::Deserialize;
+
+use crate::common::config::TokenConfig;
+
+#[derive(Debug)]
+pub struct KeyError(String);
+
+pub type KeyResult<T> = Result<T, KeyError>;
+
+impl std::fmt::Display for KeyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Keys is the shared handle to the key store that is passed around as an `Extension`
+pub type Keys = Arc<KeyStore>;
+
+/// Shape of the keyset file pointed to by `tokens.keyset`
+///
+/// active = "2023-09"
+///
+/// [[keys]]
+/// kid = "2023-09"
+/// alg = "RS256"
+/// private_key = "keys/2023-09.pem"
+///
+/// [[keys]]
+/// kid = "2023-06"
+/// alg = "HS512"
+/// secret = "..."
+/// retired = true
+#[derive(Debug, Deserialize)]
+pub struct KeySetConfig {
+    // kid of the key that new tokens are signed with
+    pub active: String,
+    pub keys: Vec<KeyConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct KeyConfig {
+    pub kid: String,
+    // one of HS512, RS256 or EdDSA
+    pub alg: Algorithm,
+    // shared secret, only used by HS512 keys
+    pub secret: Option<String>,
+    // path to a PEM encoded private key (PKCS#1 or PKCS#8 for RSA, PKCS#8 for EdDSA)
+    pub private_key: Option<PathBuf>,
+    // a retired key is never used for signing, but tokens that were signed with it
+    // are still accepted until they expire. Its public key stays in the JWKS.
+    #[serde(default)]
+    pub retired: bool,
+}
+
+struct SigningKey {
+    algorithm: Algorithm,
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+    // public half of the key, `None` for symmetric keys which must never be published
+    jwk: Option<Jwk>,
+}
+
+/// KeyStore holds every key the server knows about. Tokens are always signed with the
+/// active key, and verified with whichever key the `kid` in the token header points at.
+pub struct KeyStore {
+    active: String,
+    keys: HashMap<String, SigningKey>,
+    // `iss` of the tokens we forge, tokens from any other issuer are rejected
+    issuer: String,
+    // `aud` of the tokens we forge, a token has to be for at least one of them
+    audience: Vec<String>,
+    // `azp` of the tokens forged at login
+    client_id: String,
+}
+
+/// Load the key store from the keyset file in `tokens.keyset`. When no keyset is configured
+/// an ephemeral HS512 key is generated, so every restart will invalidate issued tokens.
+pub fn load(config: &TokenConfig) -> KeyResult<Keys> {
+    let store = match &config.keyset {
+        Some(path) => {
+            let raw = fs::read_to_string(path)
+                .map_err(|e| KeyError(format!("failed to read keyset {}: {}", path.display(), e)))?;
+            let keyset = toml::from_str::<KeySetConfig>(&raw)
+                .map_err(|e| KeyError(format!("failed to parse keyset {}: {}", path.display(), e)))?;
+
+            KeyStore::from_config(keyset)?
+        },
+        None => {
+            println!("tokens.keyset not set, generating an ephemeral signing key");
+            KeyStore::ephemeral()
+        }
+    };
+
+    Ok(Arc::new(store.with_claims(config)))
+}
+
+impl KeyStore {
+    pub fn from_config(config: KeySetConfig) -> KeyResult<KeyStore> {
+        let mut keys = HashMap::new();
+
+        for key in config.keys {
+            if key.kid == config.active && key.retired {
+                return Err(KeyError(format!("active key {} can not be retired", key.kid)))
+            }
+
+            let signing_key = SigningKey::from_config(&key)?;
+            if keys.insert(key.kid.clone(), signing_key).is_some() {
+                return Err(KeyError(format!("duplicate kid {}", key.kid)))
+            }
+        }
+
+        if !keys.contains_key(&config.active) {
+            return Err(KeyError(format!("active key {} is not in the keyset", config.active)))
+        }
+
+        Ok(KeyStore {
+            active: config.active,
+            keys,
+            issuer: String::new(),
+            audience: Vec::new(),
+            client_id: String::new(),
+        })
+    }
+
+    pub fn ephemeral() -> KeyStore {
+        let secret = Alphanumeric.sample_string(&mut rand::thread_rng(), 64);
+        let mut keys = HashMap::new();
+        keys.insert(String::from("ephemeral"), SigningKey::hmac(secret.as_bytes()));
+
+        KeyStore {
+            active: String::from("ephemeral"),
+            keys,
+            issuer: String::new(),
+            audience: Vec::new(),
+            client_id: String::new(),
+        }
+    }
+
+    /// Set the issuer, audience and client id of the tokens forged with the keys
+    pub fn with_claims(mut self, config: &TokenConfig) -> KeyStore {
+        self.issuer = config.issuer.clone();
+        self.audience = config.audience.clone();
+        self.client_id = config.client_id.clone();
+        self
+    }
+
+    pub fn issuer(&self) -> &str {
+        &self.issuer
+    }
+
+    pub fn audience(&self) -> &[String] {
+        &self.audience
+    }
+
+    pub fn client_id(&self) -> &str {
+        &self.client_id
+    }
+
+    /// kid, algorithm and key used to sign new tokens
+    pub fn signing_key(&self) -> (&str, Algorithm, &EncodingKey) {
+        let key = &self.keys[&self.active];
+        (&self.active, key.algorithm, &key.encoding)
+    }
+
+    /// algorithm and key used to verify a token signed with `kid`
+    pub fn verifying_key(&self, kid: &str) -> Option<(Algorithm, &DecodingKey)> {
+        self.keys.get(kid).map(|k| (k.algorithm, &k.decoding))
+    }
+
+    /// Public keys of every asymmetric key, active and retired
+    pub fn jwks(&self) -> JwkSet {
+        let mut keys: Vec<Jwk> = self.keys.values()
+            .filter_map(|k| k.jwk.clone())
+            .collect();
+        keys.sort_by(|a, b| a.common.key_id.cmp(&b.common.key_id));
+
+        JwkSet { keys }
+    }
+}
+
+impl SigningKey {
+    fn from_config(config: &KeyConfig) -> KeyResult<SigningKey> {
+        match config.alg {
+            Algorithm::HS512 => {
+                match &config.secret {
+                    Some(secret) => Ok(SigningKey::hmac(secret.as_bytes())),
+                    None => Err(KeyError(format!("key {} is missing a secret", config.kid))),
+                }
+            },
+            Algorithm::RS256 => {
+                let (raw, der) = read_pem(config)?;
+                let pair = RsaKeyPair::from_pkcs8(&der)
+                    .or_else(|_e| RsaKeyPair::from_der(&der))
+                    .map_err(|e| KeyError(format!("key {} is not a valid RSA key: {}", config.kid, e)))?;
+                let n = URL_SAFE_NO_PAD.encode(pair.public_key().modulus().big_endian_without_leading_zero());
+                let e = URL_SAFE_NO_PAD.encode(pair.public_key().exponent().big_endian_without_leading_zero());
+                let decoding = DecodingKey::from_rsa_components(&n, &e)
+                    .map_err(|e| KeyError(e.to_string()))?;
+
+                Ok(SigningKey {
+                    algorithm: Algorithm::RS256,
+                    encoding: EncodingKey::from_rsa_pem(&raw)
+                        .map_err(|e| KeyError(e.to_string()))?,
+                    decoding,
+                    jwk: Some(public_jwk(config, AlgorithmParameters::RSA(RSAKeyParameters {
+                        key_type: RSAKeyType::RSA,
+                        n,
+                        e,
+                    }))),
+                })
+            },
+            Algorithm::EdDSA => {
+                let (raw, der) = read_pem(config)?;
+                let pair = Ed25519KeyPair::from_pkcs8_maybe_unchecked(&der)
+                    .map_err(|e| KeyError(format!("key {} is not a valid Ed25519 key: {}", config.kid, e)))?;
+                let x = URL_SAFE_NO_PAD.encode(pair.public_key().as_ref());
+                let decoding = DecodingKey::from_ed_components(&x)
+                    .map_err(|e| KeyError(e.to_string()))?;
+
+                Ok(SigningKey {
+                    algorithm: Algorithm::EdDSA,
+                    encoding: EncodingKey::from_ed_pem(&raw)
+                        .map_err(|e| KeyError(e.to_string()))?,
+                    decoding,
+                    jwk: Some(public_jwk(config, AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                        key_type: OctetKeyPairType::OctetKeyPair,
+                        curve: EllipticCurve::Ed25519,
+                        x,
+                    }))),
+                })
+            },
+            other => Err(KeyError(format!("key {} uses unsupported algorithm {:?}", config.kid, other))),
+        }
+    }
+
+    fn hmac(secret: &[u8]) -> SigningKey {
+        SigningKey {
+            algorithm: Algorithm::HS512,
+            encoding: EncodingKey::from_secret(secret),
+            decoding: DecodingKey::from_secret(secret),
+            jwk: None,
+        }
+    }
+}
+
+// read the private key of `config`, returning both the PEM file and the DER it wraps
+fn read_pem(config: &KeyConfig) -> KeyResult<(Vec<u8>, Vec<u8>)> {
+    let path = match &config.private_key {
+        Some(p) => p,
+        None => return Err(KeyError(format!("key {} is missing a private_key", config.kid))),
+    };
+
+    let raw = fs::read(path)
+        .map_err(|e| KeyError(format!("failed to read {}: {}", path.display(), e)))?;
+
+    match pem::parse(&raw) {
+        Ok(p) => Ok((raw, p.contents)),
+        Err(e) => Err(KeyError(format!("failed to parse {}: {}", path.display(), e))),
+    }
+}
+
+fn public_jwk(config: &KeyConfig, algorithm: AlgorithmParameters) -> Jwk {
+    Jwk {
+        common: CommonParameters {
+            public_key_use: Some(PublicKeyUse::Signature),
+            algorithm: Some(config.alg),
+            key_id: Some(config.kid.clone()),
+            ..Default::default()
+        },
+        algorithm,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::decode_header;
+    use ring::rand::SystemRandom;
+
+    use crate::common::jwt::{self, AccessTokenClaims, ForgeOptions};
+
+    // PKCS#8 DER of a fresh Ed25519 key
+    fn ed25519_der() -> Vec<u8> {
+        Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap().as_ref().to_vec()
+    }
+
+    // `x` of the JWK of the key
+    fn public_x(der: &[u8]) -> String {
+        URL_SAFE_NO_PAD.encode(Ed25519KeyPair::from_pkcs8(der).unwrap().public_key().as_ref())
+    }
+
+    // the key written to a PEM file, `store` removes it once it's loaded
+    fn ed25519_key(kid: &str, der: &[u8], retired: bool) -> KeyConfig {
+        let path = std::env::temp_dir().join(format!("key-{}.pem", uuid::Uuid::new_v4()));
+        fs::write(&path, pem::encode(&pem::Pem { tag: String::from("PRIVATE KEY"), contents: der.to_vec() })).unwrap();
+
+        KeyConfig {
+            kid: kid.to_owned(),
+            alg: Algorithm::EdDSA,
+            secret: None,
+            private_key: Some(path),
+            retired,
+        }
+    }
+
+    fn hmac_key(kid: &str, retired: bool) -> KeyConfig {
+        KeyConfig {
+            kid: kid.to_owned(),
+            alg: Algorithm::HS512,
+            secret: Some(String::from("a shared secret that must never be published")),
+            private_key: None,
+            retired,
+        }
+    }
+
+    fn store(active: &str, keys: Vec<KeyConfig>) -> KeyStore {
+        let paths: Vec<PathBuf> = keys.iter().filter_map(|k| k.private_key.clone()).collect();
+        let store = KeyStore::from_config(KeySetConfig { active: active.to_owned(), keys });
+        for path in paths {
+            fs::remove_file(path).unwrap();
+        }
+
+        store.unwrap().with_claims(&TokenConfig::default())
+    }
+
+    fn forge(keys: &KeyStore) -> String {
+        let config = TokenConfig::default();
+        ForgeOptions::new()
+            .subject(String::from("jane@example.com"))
+            .issuer(config.issuer)
+            .audience(config.audience)
+            .forge(keys)
+            .unwrap()
+            .access_token
+    }
+
+    #[test]
+    fn tokens_are_signed_with_the_active_key() {
+        let retired = ed25519_key("2023-06", &ed25519_der(), true);
+        let active = ed25519_key("2023-09", &ed25519_der(), false);
+        let keys = store("2023-09", vec![retired, active]);
+
+        let token = forge(&keys);
+        let header = decode_header(&token).unwrap();
+        assert_eq!(header.kid.as_deref(), Some("2023-09"));
+        assert_eq!(header.alg, Algorithm::EdDSA);
+        assert!(jwt::decode_token::<AccessTokenClaims>(&keys, &token).is_ok());
+    }
+
+    #[test]
+    fn token_of_a_retired_key_still_verifies() {
+        let old = ed25519_der();
+        let token = forge(&store("2023-06", vec![ed25519_key("2023-06", &old, false)]));
+
+        // the same key after the rotation
+        let retired = ed25519_key("2023-06", &old, true);
+        let active = ed25519_key("2023-09", &ed25519_der(), false);
+        let keys = store("2023-09", vec![retired, active]);
+
+        assert!(jwt::decode_token::<AccessTokenClaims>(&keys, &token).is_ok());
+    }
+
+    #[test]
+    fn unknown_kid_is_rejected() {
+        let token = forge(&store("other", vec![ed25519_key("other", &ed25519_der(), false)]));
+        let keys = store("2023-09", vec![ed25519_key("2023-09", &ed25519_der(), false)]);
+
+        assert!(matches!(jwt::decode_token::<AccessTokenClaims>(&keys, &token), Err(jwt::Error::DecodeError)));
+    }
+
+    #[test]
+    fn retired_key_can_not_be_active() {
+        let keys = vec![hmac_key("2023-09", true)];
+        assert!(KeyStore::from_config(KeySetConfig { active: String::from("2023-09"), keys }).is_err());
+    }
+
+    #[test]
+    fn jwks_has_the_public_asymmetric_keys_only() {
+        let (retired, active) = (ed25519_der(), ed25519_der());
+        let keys = store("2023-09", vec![
+            ed25519_key("2023-09", &active, false),
+            ed25519_key("2023-06", &retired, true),
+            hmac_key("shared", true),
+        ]);
+
+        let jwks = keys.jwks();
+        let kids: Vec<_> = jwks.keys.iter().map(|k| k.common.key_id.clone().unwrap()).collect();
+        assert_eq!(kids, vec!["2023-06", "2023-09"]);
+
+        for (jwk, x) in jwks.keys.iter().zip([public_x(&retired), public_x(&active)]) {
+            assert_eq!(jwk.common.algorithm, Some(Algorithm::EdDSA));
+            assert_eq!(jwk.common.public_key_use, Some(PublicKeyUse::Signature));
+            match &jwk.algorithm {
+                AlgorithmParameters::OctetKeyPair(p) => assert_eq!(p.x, x),
+                other => panic!("unexpected key {:?}", other),
+            }
+        }
+
+        // no private half (`d`) and no shared secret
+        let json = serde_json::to_value(&jwks).unwrap();
+        for key in json["keys"].as_array().unwrap() {
+            assert!(key.get("d").is_none());
+            assert!(key.get("k").is_none());
+        }
+        assert!(!json.to_string().contains("shared"));
+    }
+}
@@ -1,7 +1,18 @@
 pub mod crypto;
+pub mod config;
 pub mod database;
+pub mod migrate;
 pub mod runtime;
 pub mod router;
 pub mod templates;
 pub mod session;
-pub mod jwt;
\ No newline at end of file
+pub mod jwt;
+pub mod keys;
+pub mod mailer;
+pub mod totp;
+pub mod password_policy;
+pub mod csrf;
+pub mod shutdown;
+pub mod multiplex;
+pub mod client_ip;
+pub mod tls;
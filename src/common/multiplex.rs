@@ -0,0 +1,120 @@
+use std::convert::Infallible;
+use std::future::{ready, Future, Ready};
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use axum::body::{self, Body, BoxBody};
+use axum::extract::ConnectInfo;
+use axum::http::{header, HeaderMap, Method, Request, Response, StatusCode};
+use axum::response::IntoResponse;
+use axum::Router;
+use hyper::server::conn::AddrStream;
+use tower::{Service, ServiceExt};
+
+use crate::common::client_ip::{self, ClientIp, TrustedProxies};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Serves the axum router and the gRPC services on one listener. A request goes to gRPC when its
+/// content type is `application/grpc...`, that's gRPC over HTTP/2 and gRPC-web over HTTP/1.1 or 2,
+/// or when it's the CORS preflight of a gRPC-web call. Everything else goes to the router.
+#[derive(Clone)]
+pub struct Multiplex<G> {
+    http: Router,
+    grpc: G,
+    remote_addr: SocketAddr,
+    trusted_proxies: TrustedProxies,
+}
+
+/// Makes a `Multiplex` for every connection, so both sides know the address of the client
+#[derive(Clone)]
+pub struct MakeMultiplex<G> {
+    http: Router,
+    grpc: G,
+    trusted_proxies: TrustedProxies,
+}
+
+pub fn new<G>(http: Router, grpc: G, trusted_proxies: TrustedProxies) -> MakeMultiplex<G> {
+    MakeMultiplex { http, grpc, trusted_proxies }
+}
+
+impl<G: Clone> MakeMultiplex<G> {
+    pub fn connection(&self, remote_addr: SocketAddr) -> Multiplex<G> {
+        Multiplex {
+            http: self.http.clone(),
+            grpc: self.grpc.clone(),
+            remote_addr,
+            trusted_proxies: self.trusted_proxies.clone(),
+        }
+    }
+}
+
+impl<G: Clone> Service<&AddrStream> for MakeMultiplex<G> {
+    type Response = Multiplex<G>;
+    type Error = Infallible;
+    type Future = Ready<Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, conn: &AddrStream) -> Self::Future {
+        ready(Ok(self.connection(conn.remote_addr())))
+    }
+}
+
+impl<G> Service<Request<Body>> for Multiplex<G>
+where
+    G: Service<Request<Body>, Response = Response<tonic::body::BoxBody>> + Clone + Send + 'static,
+    G::Future: Send + 'static,
+    G::Error: Into<BoxError>,
+{
+    type Response = Response<BoxBody>;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    // both sides are called through a clone with `oneshot`, which waits for them to be ready
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, mut req: Request<Body>) -> Self::Future {
+        // the same as `into_make_service_with_connect_info`
+        req.extensions_mut().insert(ConnectInfo(self.remote_addr));
+        // logins are throttled by it, behind a trusted proxy it's read from X-Forwarded-For
+        let client_ip = client_ip::resolve(&self.trusted_proxies, self.remote_addr.ip(), req.headers());
+        req.extensions_mut().insert(ClientIp(client_ip));
+
+        if !is_grpc(req.method(), req.headers()) {
+            return Box::pin(self.http.clone().oneshot(req))
+        }
+
+        let grpc = self.grpc.clone();
+
+        Box::pin(async move {
+            match grpc.oneshot(req).await {
+                Ok(v) => Ok(v.map(body::boxed)),
+                Err(e) => {
+                    println!("gRPC request failed: {}", e.into());
+                    Ok(StatusCode::INTERNAL_SERVER_ERROR.into_response())
+                }
+            }
+        })
+    }
+}
+
+// gRPC-web clients send `x-grpc-web`, so it's in the headers their preflight asks for
+fn is_grpc(method: &Method, headers: &HeaderMap) -> bool {
+    let content_type = headers.get(header::CONTENT_TYPE).and_then(|v| v.to_str().ok()).unwrap_or_default();
+
+    if content_type.starts_with("application/grpc") {
+        return true
+    }
+
+    *method == Method::OPTIONS && headers
+        .get(header::ACCESS_CONTROL_REQUEST_HEADERS)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.split(',').any(|h| h.trim().eq_ignore_ascii_case("x-grpc-web")))
+        .unwrap_or(false)
+}
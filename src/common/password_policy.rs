@@ -0,0 +1,228 @@
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+/// PasswordPolicies is the shared handle to the policy that is passed around as an `Extension`
+pub type PasswordPolicies = Arc<PasswordPolicy>;
+
+/// length of the sha1 prefix that names a file of the breached password directory
+const PREFIX_LENGTH: usize = 5;
+
+/// Rules every new password has to follow, at signup, on reset and when an admin sets it.
+/// Serialized into the template context so the forms can describe them, and read from the
+/// `[password]` section of the config.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub max_length: usize,
+    pub require_lowercase: bool,
+    pub require_uppercase: bool,
+    pub require_digit: bool,
+    pub require_symbol: bool,
+    // directory of breached password hashes, in the format of the pwnedpasswords range api:
+    // one file per 5 character uppercase sha1 prefix, eg. `21BD1`, with a `SUFFIX:COUNT` line
+    // per password. Only the file of the prefix is read, the passwords never leave the server.
+    #[serde(skip_serializing)]
+    pub breached_passwords: Option<PathBuf>,
+}
+
+/// Why a password was refused, each one has its own error code on the forms
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PasswordViolation {
+    TooShort,
+    TooLong,
+    MissingLowercase,
+    MissingUppercase,
+    MissingDigit,
+    MissingSymbol,
+    ContainsEmail,
+    Breached,
+}
+
+impl PasswordViolation {
+    /// The `error` query parameter of the form, and the code api clients get back
+    pub fn code(&self) -> &'static str {
+        match self {
+            PasswordViolation::TooShort => "password_too_short",
+            PasswordViolation::TooLong => "password_too_long",
+            PasswordViolation::MissingLowercase => "password_missing_lowercase",
+            PasswordViolation::MissingUppercase => "password_missing_uppercase",
+            PasswordViolation::MissingDigit => "password_missing_digit",
+            PasswordViolation::MissingSymbol => "password_missing_symbol",
+            PasswordViolation::ContainsEmail => "password_contains_email",
+            PasswordViolation::Breached => "password_breached",
+        }
+    }
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        // length over composition, ref: https://pages.nist.gov/800-63-3/sp800-63b.html#memsecret
+        Self {
+            min_length: 8,
+            max_length: 128,
+            require_lowercase: false,
+            require_uppercase: false,
+            require_digit: false,
+            require_symbol: false,
+            breached_passwords: None,
+        }
+    }
+}
+
+impl PasswordPolicy {
+    /// Check a new password for the user with `email`, returning the first rule it breaks
+    pub fn check(&self, password: &str, email: &str) -> Result<(), PasswordViolation> {
+        // length is counted in characters, not bytes
+        let length = password.chars().count();
+
+        if length < self.min_length {
+            return Err(PasswordViolation::TooShort)
+        }
+
+        if length > self.max_length {
+            return Err(PasswordViolation::TooLong)
+        }
+
+        if self.require_lowercase && !password.chars().any(|c| c.is_lowercase()) {
+            return Err(PasswordViolation::MissingLowercase)
+        }
+
+        if self.require_uppercase && !password.chars().any(|c| c.is_uppercase()) {
+            return Err(PasswordViolation::MissingUppercase)
+        }
+
+        if self.require_digit && !password.chars().any(|c| c.is_numeric()) {
+            return Err(PasswordViolation::MissingDigit)
+        }
+
+        if self.require_symbol && password.chars().all(|c| c.is_alphanumeric()) {
+            return Err(PasswordViolation::MissingSymbol)
+        }
+
+        if contains_email(password, email) {
+            return Err(PasswordViolation::ContainsEmail)
+        }
+
+        if self.is_breached(password) {
+            return Err(PasswordViolation::Breached)
+        }
+
+        Ok(())
+    }
+
+    fn is_breached(&self, password: &str) -> bool {
+        let dir = match &self.breached_passwords {
+            Some(v) => v,
+            None => return false,
+        };
+
+        let hash: String = ring::digest::digest(&ring::digest::SHA1_FOR_LEGACY_USE_ONLY, password.as_bytes())
+            .as_ref()
+            .iter()
+            .map(|b| format!("{:02X}", b))
+            .collect();
+        let (prefix, suffix) = hash.split_at(PREFIX_LENGTH);
+
+        // a missing range file means no breached password has the prefix
+        let range = match fs::read_to_string(dir.join(prefix)) {
+            Ok(v) => v,
+            Err(_e) => return false,
+        };
+
+        range.lines().any(|line| {
+            let line_suffix = line.split(':').next().unwrap_or("").trim();
+            line_suffix.eq_ignore_ascii_case(suffix)
+        })
+    }
+}
+
+// the whole email, or its local part when it's long enough to mean something
+fn contains_email(password: &str, email: &str) -> bool {
+    let password = password.to_lowercase();
+    let email = email.trim().to_lowercase();
+    let local = email.split('@').next().unwrap_or("");
+
+    (!email.is_empty() && password.contains(&email)) || (local.len() >= 3 && password.contains(local))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strict() -> PasswordPolicy {
+        PasswordPolicy {
+            require_lowercase: true,
+            require_uppercase: true,
+            require_digit: true,
+            require_symbol: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn default_only_checks_length() {
+        let policy = PasswordPolicy::default();
+        assert_eq!(policy.check("correct horse", "jane@example.com"), Ok(()));
+        assert_eq!(policy.check("short", "jane@example.com"), Err(PasswordViolation::TooShort));
+        assert_eq!(policy.check(&"a".repeat(129), "jane@example.com"), Err(PasswordViolation::TooLong));
+    }
+
+    #[test]
+    fn length_is_counted_in_characters() {
+        // 8 characters, 16 bytes
+        assert_eq!(PasswordPolicy::default().check("ééééçççç", "jane@example.com"), Ok(()));
+    }
+
+    #[test]
+    fn composition_rules() {
+        let policy = strict();
+        assert_eq!(policy.check("ABCDEFG1!", "jane@example.com"), Err(PasswordViolation::MissingLowercase));
+        assert_eq!(policy.check("abcdefg1!", "jane@example.com"), Err(PasswordViolation::MissingUppercase));
+        assert_eq!(policy.check("abcdefgH!", "jane@example.com"), Err(PasswordViolation::MissingDigit));
+        assert_eq!(policy.check("abcdefgH1", "jane@example.com"), Err(PasswordViolation::MissingSymbol));
+        assert_eq!(policy.check("abcdefgH1!", "jane@example.com"), Ok(()));
+    }
+
+    #[test]
+    fn rejects_the_email() {
+        let policy = PasswordPolicy::default();
+        assert_eq!(policy.check("xxJane@Example.comxx", "jane@example.com"), Err(PasswordViolation::ContainsEmail));
+        assert_eq!(policy.check("jane12345", "jane@example.com"), Err(PasswordViolation::ContainsEmail));
+        // a local part this short would refuse too much
+        assert_eq!(policy.check("jo12345678", "jo@example.com"), Ok(()));
+    }
+
+    #[test]
+    fn rejects_breached_passwords() {
+        let dir = std::env::temp_dir().join(format!("breached-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        // sha1("password") is 5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8
+        fs::write(dir.join("5BAA6"), "0018A45C4D1DEF81644B54AB7F969B88D65:1\r\n1e4c9b93f3f0682250b6cf8331b7ee68fd8:3730471\r\n").unwrap();
+
+        let policy = PasswordPolicy { breached_passwords: Some(dir.clone()), ..Default::default() };
+        assert_eq!(policy.check("password", ""), Err(PasswordViolation::Breached));
+        assert_eq!(policy.check("not in the list", ""), Ok(()));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn codes_are_distinct() {
+        let violations = [
+            PasswordViolation::TooShort,
+            PasswordViolation::TooLong,
+            PasswordViolation::MissingLowercase,
+            PasswordViolation::MissingUppercase,
+            PasswordViolation::MissingDigit,
+            PasswordViolation::MissingSymbol,
+            PasswordViolation::ContainsEmail,
+            PasswordViolation::Breached,
+        ];
+        let codes: std::collections::HashSet<&str> = violations.iter().map(|v| v.code()).collect();
+        assert_eq!(codes.len(), violations.len());
+    }
+}
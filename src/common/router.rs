@@ -1,16 +1,39 @@
 use axum_session::{SessionStore, SessionPgPool, SessionLayer};
 use sqlx::postgres::PgPool;
-use axum::{Router, Extension};
-use crate::common::templates;
+use axum::{middleware, Router, Extension};
+use crate::common::{templates, keys::Keys, csrf::CsrfKeys, password_policy::PasswordPolicies};
+use crate::common::session::{self, SessionKeyRing};
 
-pub async fn new(pool: PgPool, session_store: SessionStore<SessionPgPool>) -> Router {
+pub async fn new(
+    pool: PgPool,
+    session_store: SessionStore<SessionPgPool>,
+    session_keys: SessionKeyRing,
+    keys: Keys,
+    password_policy: PasswordPolicies,
+    csrf_key: CsrfKeys,
+) -> Router {
     let html_templates = templates::new();
 
     Router::new() 
         .merge(crate::handler::signup::router())
         .merge(crate::handler::app::router())
         .merge(crate::handler::login::router())
+        .merge(crate::handler::logout::router())
+        .merge(crate::handler::password::router())
+        .merge(crate::handler::two_factor::router())
+        .merge(crate::handler::sessions::router())
+        .merge(crate::handler::token::router())
+        .merge(crate::handler::well_known::router())
+        .merge(crate::handler::policies::router())
+        .merge(crate::handler::users::router())
         .layer(Extension(html_templates))
         .layer(Extension(pool))
+        .layer(Extension(keys))
+        .layer(Extension(password_policy))
+        .layer(Extension(csrf_key))
         .layer(SessionLayer::new(session_store))
+        // cookies made with an old session key are moved to the current one before the
+        // session layer reads them
+        .layer(middleware::from_fn(session::rotate_cookie_key))
+        .layer(Extension(session_keys))
 }
\ No newline at end of file
@@ -1,5 +1,6 @@
-use std::net::{SocketAddr, IpAddr, Ipv4Addr};
-use std::env;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
 
 use dotenv::dotenv;
 use clap::{Parser, Subcommand};
@@ -10,24 +11,52 @@ use sqlx::{
 use axum_session::{SessionStore, SessionPgPool};
 
 use crate::common::router;
+use crate::common::client_ip;
+use crate::common::config::{self, Config};
+use crate::common::crypto;
 use crate::common::database;
-use crate::common::session;
+use crate::common::migrate;
+use crate::common::multiplex;
+use crate::common::session::{self, SessionKeyRing};
+use crate::common::keys::{self, Keys};
+use crate::common::csrf::{self, CsrfKeys};
+use crate::common::mailer::{self, Mailers};
+use crate::common::password_policy::PasswordPolicies;
+use crate::common::shutdown::{self, Shutdown};
+use crate::common::templates;
+use crate::common::tls::{self, Tls};
+use crate::controller::mail_outbox;
+use crate::client;
+use crate::service;
 
 ///////////////////////////////
-/// ******* RUNTIME ******* ///
+//  ******* RUNTIME *******  //
 ///////////////////////////////
 
-///
+/// Everything the server is started with, loaded by `server` and run by `execute`
 pub struct Runtime {
+    config: Option<Config>,
     socket_address: Option<SocketAddr>,
     database_connection: Option<Pool<Postgres>>,
     session_store: Option<SessionStore<SessionPgPool>>,
+    session_keys: Option<SessionKeyRing>,
+    keys: Option<Keys>,
+    mailer: Option<Mailers>,
+    password_policy: Option<PasswordPolicies>,
+    csrf_key: Option<CsrfKeys>,
+    tls: Option<Tls>,
 }
 
 type RuntimeResult<T> = std::result::Result<T, RuntimeError>;
 
 #[derive(Debug, Clone)]
-pub struct RuntimeError;
+pub struct RuntimeError(pub String);
+
+impl std::fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
@@ -38,64 +67,216 @@ struct Settings {
 
 #[derive(Subcommand, Debug)]
 enum Mode {
-  Server,
-  Client,
+  Server(config::ServerArgs),
+  Migrate(migrate::MigrateArgs),
+  Client(client::ClientArgs),
 }
 
 impl Runtime {
     pub fn new () -> Runtime {
         Runtime { 
+            config: None,
             socket_address: None, 
             database_connection: None,
             session_store: None,
+            session_keys: None,
+            keys: None,
+            mailer: None,
+            password_policy: None,
+            csrf_key: None,
+            tls: None,
         }
     }
 
-    // read default env vars
-    pub async fn default(&self) -> RuntimeResult<Runtime> {
+    // read default env vars, there's only a runtime left to execute for `server`
+    pub async fn default(&self) -> RuntimeResult<Option<Runtime>> {
         dotenv().ok();
 
         let args = Settings::parse();
 
         match args.runtime {
-            Mode::Server => {
-                Ok(self.server().await.unwrap())
+            Mode::Server(server_args) => {
+                self.server(server_args).await.map(Some)
             },
-            Mode::Client => {
-                // TODO ->  replace all of the make targets with commands in the client.
-                // this makes for an amazing dev experience.
-                Ok(self.client().unwrap())
+            Mode::Migrate(migrate_args) => {
+                self.migrate(migrate_args).await.map(|_v| None)
+            },
+            Mode::Client(client_args) => {
+                self.client(client_args).await.map(|_v| None)
             }
         }
     }
 
-    pub fn client(&self) -> RuntimeResult<Runtime> {
-        Err(RuntimeError)
+    // the client runs to completion, there is never a runtime left to execute
+    pub async fn client(&self, args: client::ClientArgs) -> RuntimeResult<()> {
+        client::run(args).await.map_err(|e| RuntimeError(e.to_string()))
     }
 
-    pub async fn server(&self) -> RuntimeResult<Runtime> {
-        let port = env::var("PORT").expect("PORT environment variable not set");
-        let ip = IpAddr::V4(Ipv4Addr::new(0,0,0,0));
-        let socket_address = SocketAddr::new(ip, port.parse::<u16>().unwrap());
-        let database_connection = database::connect().await;
-        let sessions = session::new(database_connection.clone()).await; 
-        
+    // migrations run to completion, like the client
+    pub async fn migrate(&self, args: migrate::MigrateArgs) -> RuntimeResult<()> {
+        migrate::run(args).await.map_err(|e| RuntimeError(e.to_string()))
+    }
+
+    // every setting is read and validated up front, a bad config never starts half a server
+    pub async fn server(&self, args: config::ServerArgs) -> RuntimeResult<Runtime> {
+        let config = config::load(&args).map_err(|e| RuntimeError(e.to_string()))?;
+
+        let socket_address = SocketAddr::new(config.server.host, config.server.port);
+        crypto::configure_argon2(&config.argon2);
+        let database_connection = database::connect(&config.database)
+            .await
+            .map_err(|e| RuntimeError(format!("failed to connect to the database: {}", e)))?;
+
+        if config.database.migrate {
+            database::migrate(&database_connection)
+                .await
+                .map_err(|e| RuntimeError(format!("failed to migrate: {}", e)))?;
+        }
+
+        let session_keys = session::load_keys(&config.session)
+            .map_err(|e| RuntimeError(format!("failed to load session keys: {}", e)))?;
+        // with HTTPS the session cookies are marked Secure, browsers never send them over plain HTTP
+        let sessions = session::new(database_connection.clone(), &session_keys, config.tls.enabled())
+            .await
+            .map_err(|e| RuntimeError(format!("failed to create the session table: {}", e)))?;
+        let keys = keys::load(&config.tokens)
+            .map_err(|e| RuntimeError(format!("failed to load signing keys: {}", e)))?;
+        let mailer = mailer::load(&config.mail)
+            .map_err(|e| RuntimeError(format!("failed to load the mailer: {}", e)))?;
+        let password_policy = Arc::new(config.password.clone());
+        let csrf_key = csrf::load(&config.csrf);
+        let tls = tls::load(&config.tls).map_err(|e| RuntimeError(e.to_string()))?;
+
         Ok(Runtime {
+            config: Some(config),
             socket_address: Some(socket_address), 
             database_connection: Some(database_connection),
             session_store: Some(sessions),
+            session_keys: Some(session_keys),
+            keys: Some(keys),
+            mailer: Some(mailer),
+            password_policy: Some(password_policy),
+            csrf_key: Some(csrf_key),
+            tls,
         })
     }
 
+    // runs until SIGTERM or SIGINT, or until the server fails, then drains and exits with one of the
+    // `shutdown::EXIT_*` codes
     pub async fn execute(self) {
+        let config = self.config.unwrap();
         let dbp = self.database_connection.unwrap();
         let ses = self.session_store.unwrap();
-        let app = router::new(dbp, ses.clone()).await;
-        let svc = app.into_make_service();
+        let session_keys = self.session_keys.unwrap();
+        let keys = self.keys.unwrap();
+        let password_policy = self.password_policy.unwrap();
+        let csrf_key = self.csrf_key.unwrap();
+        let app = router::new(dbp.clone(), ses.clone(), session_keys, keys.clone(), password_policy.clone(), csrf_key).await;
+        let grpc = service::routes(dbp.clone(), keys, password_policy, &config.server.grpc_web_origins);
         let lst = self.socket_address.unwrap();
+        let deadline = Duration::from_secs(config.server.shutdown_timeout_seconds);
+        let shutdown = Shutdown::new();
+
+        let make_service = multiplex::new(app, grpc, client_ip::load(&config.server));
+        let http_shutdown = shutdown.clone();
+
+        // HTTP/1.1 and HTTP/2 on one listener, gRPC is told apart by its content type. A server
+        // that stops on its own takes the workers down with it.
+        let http = match self.tls {
+            Some(tls) => {
+                let listener = match tokio::net::TcpListener::bind(&lst).await {
+                    Ok(v) => v,
+                    Err(e) => {
+                        println!("failed to listen on {}: {}", lst, e);
+                        std::process::exit(shutdown::EXIT_FAILED)
+                    }
+                };
+
+                tokio::spawn(tls::watch(tls.cert, Duration::from_secs(config.tls.reload_interval_seconds), shutdown.subscribe()));
+
+                let server = axum::Server::builder(tls::incoming(listener, tls.acceptor, shutdown.subscribe()))
+                    .serve(make_service)
+                    .with_graceful_shutdown(shutdown::triggered(shutdown.subscribe()));
+
+                tokio::spawn(async move {
+                    let result = server.await.map_err(|e| format!("server failed: {}", e));
+                    http_shutdown.trigger();
+                    result
+                })
+            },
+            None => {
+                let server = match axum::Server::try_bind(&lst) {
+                    Ok(v) => v.serve(make_service).with_graceful_shutdown(shutdown::triggered(shutdown.subscribe())),
+                    Err(e) => {
+                        println!("failed to listen on {}: {}", lst, e);
+                        std::process::exit(shutdown::EXIT_FAILED)
+                    }
+                };
 
-        let _ = axum::Server::bind(&lst).
-            serve(svc).
-            await;
-    }    
-}
\ No newline at end of file
+                tokio::spawn(async move {
+                    let result = server.await.map_err(|e| format!("server failed: {}", e));
+                    http_shutdown.trigger();
+                    result
+                })
+            },
+        };
+
+        // mail queued in the outbox is sent in the background
+        let worker = tokio::spawn(mail_outbox::run_worker(
+            dbp.clone(),
+            self.mailer.unwrap(),
+            templates::new(),
+            config.public_url(),
+            Duration::from_secs(5),
+            shutdown.subscribe(),
+        ));
+
+        tokio::select! {
+            name = shutdown::wait_for_signal() => println!("{} received, draining for up to {}s", name, deadline.as_secs()),
+            _ = shutdown::triggered(shutdown.subscribe()) => println!("the server stopped, shutting down"),
+        }
+
+        // stop accepting connections, requests in flight and the outbox get until the deadline
+        shutdown.trigger();
+
+        let tasks = [http.abort_handle(), worker.abort_handle()];
+        let drained = async { tokio::join!(http, worker) };
+
+        let mut code = tokio::select! {
+            result = tokio::time::timeout(deadline, drained) => match result {
+                Ok((http, _worker)) => match http {
+                    Ok(Ok(())) => shutdown::EXIT_OK,
+                    Ok(Err(e)) => {
+                        println!("{}", e);
+                        shutdown::EXIT_FAILED
+                    },
+                    Err(e) => {
+                        println!("server task failed: {}", e);
+                        shutdown::EXIT_FAILED
+                    },
+                },
+                Err(_e) => {
+                    println!("still running after {}s, stopping anyway", deadline.as_secs());
+                    shutdown::EXIT_DRAIN_INCOMPLETE
+                },
+            },
+            name = shutdown::wait_for_signal() => {
+                println!("{} received again, stopping now", name);
+                shutdown::EXIT_DRAIN_INCOMPLETE
+            },
+        };
+
+        // whatever didn't finish is dropped, so its connections go back to the pool
+        for task in tasks {
+            task.abort();
+        }
+
+        if tokio::time::timeout(Duration::from_secs(5), dbp.close()).await.is_err() {
+            println!("failed to close the database pool");
+            code = code.max(shutdown::EXIT_DRAIN_INCOMPLETE);
+        }
+
+        println!("server stopped");
+        std::process::exit(code)
+    }
+}
@@ -1,18 +1,345 @@
-use axum_session::{SessionStore, SessionPgPool, SessionConfig, Key, SecurityMode};
+use std::fmt;
+use std::fs;
+use std::sync::Arc;
+
+use aes_gcm::aead::{generic_array::GenericArray, Aead, KeyInit, Payload};
+use aes_gcm::Aes256Gcm;
+use axum::{
+    http::{header::COOKIE, HeaderValue, Request},
+    middleware::Next,
+    response::Response,
+};
+use axum_session::{SessionStore, SessionPgPool, SessionConfig, SessionError, Key, SecurityMode};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::Duration;
+use cookie::{Cookie, CookieJar};
+use rand::RngCore;
+use serde::Deserialize;
 use sqlx::{Pool, Postgres};
 
+use crate::common::config::SessionKeyConfig;
+
+/// how long a session lives without being used, sessions last seen longer ago are gone
+pub const SESSION_LIFESPAN_HOURS: i64 = 6;
+
+const TABLE_NAME: &str = "user_sessions";
+/// cookie with the id of the per session key, it's encrypted with `SessionKeys::key`
+const KEY_COOKIE_NAME: &str = "session_key";
+/// bytes of a key, the cookie crate splits them into a signing and an encryption key
+const KEY_LENGTH: usize = 64;
+/// per session keys are stored as `base64(nonce | ciphertext | tag)`, the same as axum_session
+const NONCE_LENGTH: usize = 12;
+
+#[derive(Debug)]
+pub struct SessionKeyError(pub String);
+
+impl fmt::Display for SessionKeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// SessionKeyRing is the shared handle to the session keys that is passed around as an `Extension`
+pub type SessionKeyRing = Arc<SessionKeys>;
+
+/// Keys sessions are encrypted with. The old keys are only used to read sessions made before
+/// a rotation, they're moved to the current keys as they're seen.
+pub struct SessionKeys {
+    // encrypts the key cookie
+    key: Key,
+    // encrypts the per session keys stored in the database
+    database_key: Key,
+    old_keys: Vec<Key>,
+    old_database_keys: Vec<Key>,
+}
+
+// shape of the file in `session.key_file`, see `session_keys.example.toml`
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SessionKeyFile {
+    key: String,
+    database_key: String,
+    #[serde(default)]
+    old_keys: Vec<String>,
+    #[serde(default)]
+    old_database_keys: Vec<String>,
+}
+
+/// Decode a base64 key of at least 64 bytes, whitespace is ignored so the output of
+/// `openssl rand -base64 64` can be pasted as is
+pub fn decode_key(value: &str) -> Result<Key, SessionKeyError> {
+    let value: String = value.chars().filter(|c| !c.is_whitespace()).collect();
+    let bytes = STANDARD.decode(value)
+        .map_err(|e| SessionKeyError(format!("key is not base64: {}", e)))?;
+
+    if bytes.len() < KEY_LENGTH {
+        return Err(SessionKeyError(format!("key is {} bytes, it has to be at least {}", bytes.len(), KEY_LENGTH)))
+    }
+
+    Ok(Key::from(&bytes))
+}
+
+/// Load the keys from `session.key_file`, or from the config itself. Without them new keys
+/// are generated, and every restart signs everyone out.
+pub fn load_keys(config: &SessionKeyConfig) -> Result<SessionKeyRing, SessionKeyError> {
+    let file = match &config.key_file {
+        Some(path) => {
+            let raw = fs::read_to_string(path)
+                .map_err(|e| SessionKeyError(format!("failed to read {}: {}", path.display(), e)))?;
+            let file = toml::from_str::<SessionKeyFile>(&raw)
+                .map_err(|e| SessionKeyError(format!("failed to parse {}: {}", path.display(), e)))?;
+            Some(file)
+        },
+        None => None,
+    };
+
+    let (key, database_key, old_keys, old_database_keys) = match (file, &config.key, &config.database_key) {
+        (Some(f), _, _) => (f.key, f.database_key, f.old_keys, f.old_database_keys),
+        (None, Some(k), Some(d)) => (k.clone(), d.clone(), config.old_keys.clone(), config.old_database_keys.clone()),
+        _ => {
+            println!("session keys not set, generating new ones, every restart signs everyone out");
+            return Ok(Arc::new(SessionKeys {
+                key: Key::generate(),
+                database_key: Key::generate(),
+                old_keys: Vec::new(),
+                old_database_keys: Vec::new(),
+            }))
+        }
+    };
 
-pub async fn new(pool: Pool<Postgres>) -> SessionStore<SessionPgPool> {
-    let key = Key::generate();
-    let key2 = Key::generate();
+    let decode_all = |keys: &[String]| keys.iter().map(|k| decode_key(k)).collect::<Result<Vec<Key>, SessionKeyError>>();
+
+    Ok(Arc::new(SessionKeys {
+        key: decode_key(&key)?,
+        database_key: decode_key(&database_key)?,
+        old_keys: decode_all(&old_keys)?,
+        old_database_keys: decode_all(&old_database_keys)?,
+    }))
+}
+
+pub async fn new(pool: Pool<Postgres>, keys: &SessionKeys, secure: bool) -> Result<SessionStore<SessionPgPool>, SessionError> {
     let session_config = SessionConfig::default()
-        .with_table_name("user_sessions")
-        .with_key(key)
-        .with_database_key(key2)
-        .with_security_mode(SecurityMode::PerSession);
+        .with_table_name(TABLE_NAME)
+        .with_key_cookie_name(KEY_COOKIE_NAME)
+        .with_lifetime(Duration::hours(SESSION_LIFESPAN_HOURS))
+        .with_key(keys.key.clone())
+        .with_database_key(keys.database_key.clone())
+        .with_security_mode(SecurityMode::PerSession)
+        .with_secure(secure);
     let session_store = SessionStore::<SessionPgPool>::new(Some(pool.clone().into()), session_config);
 
-    session_store.initiate().await.unwrap();
+    // creates the session table when it's missing
+    session_store.initiate().await?;
+
+    rewrap_database_keys(&pool, keys).await;
+
+    Ok(session_store)
+}
+
+/// Encrypt the per session keys that were stored with an old database key with the current one,
+/// it runs once at startup before the store reads any of them
+async fn rewrap_database_keys(pool: &Pool<Postgres>, keys: &SessionKeys) {
+    if keys.old_database_keys.is_empty() {
+        return
+    }
+
+    // the session data is json in the same table, the keys are base64
+    let rows = match sqlx::query_as::<_, (String, String)>(&format!("SELECT id, session FROM {} WHERE session NOT LIKE '{{%'", TABLE_NAME))
+        .fetch_all(pool)
+        .await {
+            Ok(v) => v,
+            Err(e) => {
+                println!("failed to read the session keys {}", e);
+                return
+            }
+        };
+
+    let mut rewrapped = 0;
+
+    for (id, value) in rows {
+        if open(&keys.database_key, &id, &value).is_some() {
+            continue
+        }
+
+        let session_key = match keys.old_database_keys.iter().find_map(|k| open(k, &id, &value)) {
+            Some(v) => v,
+            None => continue,
+        };
+
+        match sqlx::query(&format!("UPDATE {} SET session = $2 WHERE id = $1", TABLE_NAME))
+            .bind(&id)
+            .bind(seal(&keys.database_key, &id, &session_key))
+            .execute(pool)
+            .await {
+                Ok(_v) => rewrapped += 1,
+                Err(e) => println!("failed to update the session key {}", e),
+            }
+    }
+
+    println!("{} session keys moved to the current database key", rewrapped);
+}
+
+// aes-256-gcm with the row id as associated data, so a key can't be moved to another row
+fn open(key: &Key, id: &str, value: &str) -> Option<Vec<u8>> {
+    let data = STANDARD.decode(value).ok()?;
+    if data.len() <= NONCE_LENGTH {
+        return None
+    }
+
+    let (nonce, cipher) = data.split_at(NONCE_LENGTH);
+    let aead = Aes256Gcm::new(GenericArray::from_slice(key.encryption()));
+
+    aead.decrypt(GenericArray::from_slice(nonce), Payload { msg: cipher, aad: id.as_bytes() }).ok()
+}
+
+fn seal(key: &Key, id: &str, session_key: &[u8]) -> String {
+    let mut nonce = [0u8; NONCE_LENGTH];
+    rand::thread_rng().fill_bytes(&mut nonce);
+
+    let aead = Aes256Gcm::new(GenericArray::from_slice(key.encryption()));
+    let cipher = aead
+        .encrypt(GenericArray::from_slice(&nonce), Payload { msg: session_key, aad: id.as_bytes() })
+        .expect("failed to encrypt the session key");
+
+    STANDARD.encode([&nonce[..], &cipher[..]].concat())
+}
+
+/// Encrypt a key cookie that was made with an old key with the current one, before the session
+/// layer reads it. The session layer sets the cookie on every response, so the browser has
+/// moved to the current key after its first request.
+pub async fn rotate_cookie_key<B>(mut req: Request<B>, next: Next<B>) -> Response {
+    let keys = match req.extensions().get::<SessionKeyRing>() {
+        Some(v) if !v.old_keys.is_empty() => v.clone(),
+        _ => return next.run(req).await,
+    };
+
+    let mut jar = CookieJar::new();
+    let cookies = req.headers()
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .filter_map(|c| Cookie::parse_encoded(c.trim().to_owned()).ok());
+    for cookie in cookies {
+        jar.add_original(cookie);
+    }
+
+    if jar.get(KEY_COOKIE_NAME).is_none() || jar.private(&keys.key).get(KEY_COOKIE_NAME).is_some() {
+        return next.run(req).await
+    }
+
+    let value = match keys.old_keys.iter().find_map(|k| jar.private(k).get(KEY_COOKIE_NAME)) {
+        Some(v) => v.value().to_owned(),
+        None => return next.run(req).await,
+    };
+    jar.private_mut(&keys.key).add(Cookie::new(KEY_COOKIE_NAME, value));
+
+    let header = jar.iter().map(|c| c.encoded().to_string()).collect::<Vec<String>>().join("; ");
+    req.headers_mut().remove(COOKIE);
+    if let Ok(v) = HeaderValue::from_str(&header) {
+        req.headers_mut().insert(COOKIE, v);
+    }
+
+    next.run(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::{header::SET_COOKIE, Response as HttpResponse}, routing::get, Extension, Router};
+    use axum_session::{Session, SessionLayer};
+    use tower::ServiceExt;
+
+    use crate::common::database;
+
+    fn keys(key: &Key, database_key: &Key, old: Option<(&Key, &Key)>) -> SessionKeyRing {
+        Arc::new(SessionKeys {
+            key: key.clone(),
+            database_key: database_key.clone(),
+            old_keys: old.iter().map(|(k, _d)| (*k).clone()).collect(),
+            old_database_keys: old.iter().map(|(_k, d)| (*d).clone()).collect(),
+        })
+    }
+
+    // wired like `router::new`
+    fn app(store: SessionStore<SessionPgPool>, keys: SessionKeyRing) -> Router {
+        Router::new()
+            .route("/set", get(|session: Session<SessionPgPool>| async move {
+                session.set("probe", "kept");
+                session.get_session_id().await.inner()
+            }))
+            .route("/get", get(|session: Session<SessionPgPool>| async move {
+                session.get::<String>("probe").unwrap_or_default()
+            }))
+            .layer(SessionLayer::new(store))
+            .layer(axum::middleware::from_fn(rotate_cookie_key))
+            .layer(Extension(keys))
+    }
+
+    async fn call(app: Router, path: &str, cookie: &str) -> HttpResponse<String> {
+        let req = Request::builder().uri(path).header(COOKIE, cookie).body(Body::empty()).unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        let (parts, body) = res.into_parts();
+        let body = hyper::body::to_bytes(body).await.unwrap();
+        HttpResponse::from_parts(parts, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    // the `Set-Cookie` of a response as the `Cookie` of the next request
+    fn cookie_header<B>(res: &HttpResponse<B>) -> String {
+        res.headers()
+            .get_all(SET_COOKIE)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .filter_map(|v| v.split(';').next())
+            .filter(|v| !v.ends_with('='))
+            .collect::<Vec<&str>>()
+            .join("; ")
+    }
+
+    #[tokio::test]
+    async fn session_made_with_old_keys_is_read_with_the_new_ones() {
+        let Some(pool) = database::test_pool().await else { return };
+        let (old_key, old_database_key) = (Key::generate(), Key::generate());
+        let (new_key, new_database_key) = (Key::generate(), Key::generate());
+
+        let old = keys(&old_key, &old_database_key, None);
+        let old_store = new(pool.clone(), &old, false).await.unwrap();
+        let res = call(app(old_store, old), "/set", "").await;
+        let session_id = res.body().clone();
+        let cookie = cookie_header(&res);
+
+        let mut jar = CookieJar::new();
+        for c in cookie.split("; ") {
+            jar.add_original(Cookie::parse_encoded(c.to_owned()).unwrap());
+        }
+        let key_id = jar.private(&old_key).get(KEY_COOKIE_NAME).unwrap().value().to_owned();
+
+        // a restart with the keys rotated
+        let rotated = keys(&new_key, &new_database_key, Some((&old_key, &old_database_key)));
+        let new_store = new(pool.clone(), &rotated, false).await.unwrap();
+
+        let (stored,): (String,) = sqlx::query_as(&format!("SELECT session FROM {} WHERE id = $1", TABLE_NAME))
+            .bind(&key_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert!(open(&new_database_key, &key_id, &stored).is_some());
+        assert!(open(&old_database_key, &key_id, &stored).is_none());
+
+        let res = call(app(new_store.clone(), rotated), "/get", &cookie).await;
+        assert_eq!(res.body(), "kept");
+
+        new_store.destroy_session(&session_id).await.unwrap();
+        new_store.destroy_session(&key_id).await.unwrap();
+    }
+
+    #[test]
+    fn sealed_key_only_opens_with_its_key_and_row() {
+        let (key, other) = (Key::generate(), Key::generate());
+        let sealed = seal(&key, "row", b"session key");
 
-    session_store
-}
\ No newline at end of file
+        assert_eq!(open(&key, "row", &sealed), Some(b"session key".to_vec()));
+        assert!(open(&other, "row", &sealed).is_none());
+        assert!(open(&key, "other row", &sealed).is_none());
+    }
+}
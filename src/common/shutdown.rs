@@ -0,0 +1,62 @@
+use std::sync::Arc;
+
+use tokio::sync::watch;
+
+/// the servers drained and the workers flushed in time
+pub const EXIT_OK: i32 = 0;
+/// a server failed, eg. its port was taken
+pub const EXIT_FAILED: i32 = 1;
+/// requests or workers were still running at the deadline, or a second signal cut them off
+pub const EXIT_DRAIN_INCOMPLETE: i32 = 2;
+
+/// Tells the servers and background workers to stop, it's cloned into each of them and any of
+/// them can trigger it
+#[derive(Clone)]
+pub struct Shutdown(Arc<watch::Sender<bool>>);
+
+/// Handed to whatever has to stop on shutdown, see `triggered`
+pub type ShutdownSignal = watch::Receiver<bool>;
+
+impl Shutdown {
+    pub fn new() -> Shutdown {
+        let (sender, _receiver) = watch::channel(false);
+        Shutdown(Arc::new(sender))
+    }
+
+    pub fn subscribe(&self) -> ShutdownSignal {
+        self.0.subscribe()
+    }
+
+    pub fn trigger(&self) {
+        self.0.send_replace(true);
+    }
+}
+
+/// Resolves once shutdown is triggered, right away when it already was
+pub async fn triggered(mut signal: ShutdownSignal) {
+    while !*signal.borrow_and_update() {
+        if signal.changed().await.is_err() {
+            return
+        }
+    }
+}
+
+/// Resolves on the next SIGTERM or SIGINT, with the name of the signal
+#[cfg(unix)]
+pub async fn wait_for_signal() -> &'static str {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate()).expect("failed to listen for SIGTERM");
+    let mut interrupt = signal(SignalKind::interrupt()).expect("failed to listen for SIGINT");
+
+    tokio::select! {
+        _ = terminate.recv() => "SIGTERM",
+        _ = interrupt.recv() => "SIGINT",
+    }
+}
+
+#[cfg(not(unix))]
+pub async fn wait_for_signal() -> &'static str {
+    tokio::signal::ctrl_c().await.expect("failed to listen for ctrl-c");
+    "ctrl-c"
+}
@@ -14,8 +14,22 @@ pub fn new() -> Templates {
             ("hello", include_str!("../../templates/hello.html")),
             ("signup_page", include_str!("../../templates/signup.html")),
             ("login_page", include_str!("../../templates/login.html")),
+            ("signup_confirm_page", include_str!("../../templates/signup_confirm.html")),
+            ("password_forgot_page", include_str!("../../templates/password_forgot.html")),
+            ("password_reset_page", include_str!("../../templates/password_reset.html")),
+            ("login_two_factor_page", include_str!("../../templates/login_two_factor.html")),
+            ("account_two_factor_page", include_str!("../../templates/account_two_factor.html")),
+            ("logout_page", include_str!("../../templates/logout.html")),
+            ("account_sessions_page", include_str!("../../templates/account_sessions.html")),
+            ("admin_sessions_page", include_str!("../../templates/admin_sessions.html")),
             ("app", include_str!("../../templates/app.html")),
             ("sidebar", include_str!("../../templates/components/sidebar.html")),
+            ("password_errors", include_str!("../../templates/components/password_errors.html")),
+            ("password_help", include_str!("../../templates/components/password_help.html")),
+            ("mail/confirmation_code.html", include_str!("../../templates/mail/confirmation_code.html")),
+            ("mail/confirmation_code.txt", include_str!("../../templates/mail/confirmation_code.txt")),
+            ("mail/password_reset.html", include_str!("../../templates/mail/password_reset.html")),
+            ("mail/password_reset.txt", include_str!("../../templates/mail/password_reset.txt")),
         ]).unwrap();
 
     Arc::new(tera)
@@ -0,0 +1,279 @@
+use std::fmt;
+use std::fs::{self, File};
+use std::future::{ready, Ready};
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, RwLock};
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime};
+
+use rustls::crypto::ring;
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::ServerConfig;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+use tower::Service;
+
+use crate::common::config::TlsConfig;
+use crate::common::multiplex::{MakeMultiplex, Multiplex};
+use crate::common::shutdown::{self, ShutdownSignal};
+
+/// a client that hasn't finished its handshake by then is dropped
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// connections that finished their handshake, waiting for the server to pick them up
+const ACCEPT_BACKLOG: usize = 128;
+
+#[derive(Debug)]
+pub struct TlsError(pub String);
+
+pub type TlsResult<T> = Result<T, TlsError>;
+
+impl fmt::Display for TlsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Serves the certificate that was last loaded, `watch` swaps it when the files change
+#[derive(Debug)]
+pub struct ReloadingCert {
+    cert_file: PathBuf,
+    key_file: PathBuf,
+    current: RwLock<Arc<CertifiedKey>>,
+}
+
+impl ResolvesServerCert for ReloadingCert {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.read().unwrap().clone())
+    }
+}
+
+/// The acceptor and the certificate behind it
+pub struct Tls {
+    pub acceptor: TlsAcceptor,
+    pub cert: Arc<ReloadingCert>,
+}
+
+/// Load the certificate, `None` when TLS isn't configured. HTTP/2 is offered before HTTP/1.1
+/// with ALPN, gRPC clients need it.
+pub fn load(config: &TlsConfig) -> TlsResult<Option<Tls>> {
+    let (cert_file, key_file) = match (&config.cert_file, &config.key_file) {
+        (Some(c), Some(k)) => (c.clone(), k.clone()),
+        _ => return Ok(None),
+    };
+
+    let certified_key = load_certified_key(&cert_file, &key_file)?;
+    let cert = Arc::new(ReloadingCert { cert_file, key_file, current: RwLock::new(Arc::new(certified_key)) });
+
+    let mut server_config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(|e| TlsError(format!("failed to configure TLS: {}", e)))?
+        .with_no_client_auth()
+        .with_cert_resolver(cert.clone());
+    server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+    println!("serving HTTPS with {}", cert.cert_file.display());
+    Ok(Some(Tls { acceptor: TlsAcceptor::from(Arc::new(server_config)), cert }))
+}
+
+// the whole chain from `cert_file`, and the first key in `key_file`, they have to match
+fn load_certified_key(cert_file: &Path, key_file: &Path) -> TlsResult<CertifiedKey> {
+    let mut reader = BufReader::new(File::open(cert_file)
+        .map_err(|e| TlsError(format!("failed to read {}: {}", cert_file.display(), e)))?);
+    let certs = rustls_pemfile::certs(&mut reader)
+        .collect::<Result<Vec<_>, io::Error>>()
+        .map_err(|e| TlsError(format!("failed to parse {}: {}", cert_file.display(), e)))?;
+
+    if certs.is_empty() {
+        return Err(TlsError(format!("no certificate in {}", cert_file.display())))
+    }
+
+    let mut reader = BufReader::new(File::open(key_file)
+        .map_err(|e| TlsError(format!("failed to read {}: {}", key_file.display(), e)))?);
+    let key = match rustls_pemfile::private_key(&mut reader) {
+        Ok(Some(v)) => v,
+        Ok(None) => return Err(TlsError(format!("no private key in {}", key_file.display()))),
+        Err(e) => return Err(TlsError(format!("failed to parse {}: {}", key_file.display(), e))),
+    };
+
+    let signing_key = ring::sign::any_supported_type(&key)
+        .map_err(|e| TlsError(format!("unsupported private key in {}: {}", key_file.display(), e)))?;
+    let certified_key = CertifiedKey::new(certs, signing_key);
+
+    // a renewal caught halfway, with the new certificate and the old key, is refused
+    certified_key.keys_match()
+        .map_err(|e| TlsError(format!("the key in {} isn't the key of the certificate: {}", key_file.display(), e)))?;
+
+    Ok(certified_key)
+}
+
+// when either file was last written, following symlinks like the ones of a mounted secret
+fn modified(cert: &ReloadingCert) -> Option<(SystemTime, SystemTime)> {
+    let cert_modified = fs::metadata(&cert.cert_file).and_then(|m| m.modified()).ok()?;
+    let key_modified = fs::metadata(&cert.key_file).and_then(|m| m.modified()).ok()?;
+    Some((cert_modified, key_modified))
+}
+
+/// Check the files every `interval` until shutdown, and serve the certificate again when they
+/// change. A certificate that fails to load is logged and the one before it is kept.
+pub async fn watch(cert: Arc<ReloadingCert>, interval: Duration, signal: ShutdownSignal) {
+    let mut last_modified = modified(&cert);
+    let mut ticker = tokio::time::interval(interval);
+
+    loop {
+        tokio::select! {
+            biased;
+            _ = shutdown::triggered(signal.clone()) => return,
+            _ = ticker.tick() => {},
+        }
+
+        let now_modified = modified(&cert);
+        if now_modified.is_none() || now_modified == last_modified {
+            continue
+        }
+
+        last_modified = now_modified;
+
+        match load_certified_key(&cert.cert_file, &cert.key_file) {
+            Ok(v) => {
+                *cert.current.write().unwrap() = Arc::new(v);
+                println!("reloaded the TLS certificate from {}", cert.cert_file.display());
+            },
+            Err(e) => println!("failed to reload the TLS certificate, the old one is still served: {}", e),
+        }
+    }
+}
+
+/// Connections that finished their TLS handshake, for `axum::Server::builder`
+pub struct TlsIncoming {
+    connections: mpsc::Receiver<TlsStream<TcpStream>>,
+}
+
+/// Accept connections on `listener` until shutdown. Every handshake runs on its own task, so a
+/// slow client doesn't hold up the others.
+pub fn incoming(listener: TcpListener, acceptor: TlsAcceptor, signal: ShutdownSignal) -> TlsIncoming {
+    let (sender, connections) = mpsc::channel(ACCEPT_BACKLOG);
+
+    tokio::spawn(async move {
+        loop {
+            let (stream, remote_addr) = tokio::select! {
+                biased;
+                _ = shutdown::triggered(signal.clone()) => return,
+                result = listener.accept() => match result {
+                    Ok(v) => v,
+                    Err(e) => {
+                        // eg. out of file descriptors, give it a moment instead of spinning
+                        println!("failed to accept a connection: {}", e);
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                        continue
+                    }
+                },
+            };
+
+            let acceptor = acceptor.clone();
+            let sender = sender.clone();
+
+            tokio::spawn(async move {
+                match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                    Ok(Ok(v)) => {
+                        let _e = sender.send(v).await;
+                    },
+                    Ok(Err(e)) => println!("TLS handshake with {} failed: {}", remote_addr, e),
+                    Err(_e) => println!("TLS handshake with {} timed out", remote_addr),
+                }
+            });
+        }
+    });
+
+    TlsIncoming { connections }
+}
+
+impl hyper::server::accept::Accept for TlsIncoming {
+    type Conn = TlsStream<TcpStream>;
+    type Error = io::Error;
+
+    fn poll_accept(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Self::Conn, Self::Error>>> {
+        self.get_mut().connections.poll_recv(cx).map(|v| v.map(Ok))
+    }
+}
+
+impl<G: Clone> Service<&TlsStream<TcpStream>> for MakeMultiplex<G> {
+    type Response = Multiplex<G>;
+    type Error = io::Error;
+    type Future = Ready<Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, conn: &TlsStream<TcpStream>) -> Self::Future {
+        ready(conn.get_ref().0.peer_addr().map(|addr| self.connection(addr)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::shutdown::Shutdown;
+
+    const INTERVAL: Duration = Duration::from_millis(10);
+
+    // a self signed certificate for localhost, its PEM and DER, and the PEM of its key
+    fn certificate() -> (String, Vec<u8>, String) {
+        let key = rcgen::KeyPair::generate().unwrap();
+        let cert = rcgen::CertificateParams::new(vec![String::from("localhost")]).unwrap().self_signed(&key).unwrap();
+        (cert.pem(), cert.der().to_vec(), key.serialize_pem())
+    }
+
+    fn served(cert: &ReloadingCert) -> Vec<u8> {
+        cert.current.read().unwrap().cert[0].to_vec()
+    }
+
+    // a couple of checks of the files later
+    async fn wait_for_reload() {
+        tokio::time::sleep(INTERVAL * 5).await;
+    }
+
+    #[tokio::test]
+    async fn reload_keeps_the_old_certificate_when_the_new_one_is_bad() {
+        let dir = std::env::temp_dir().join(format!("tls-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let (cert_file, key_file) = (dir.join("cert.pem"), dir.join("key.pem"));
+
+        let (first_pem, first_der, first_key) = certificate();
+        fs::write(&cert_file, &first_pem).unwrap();
+        fs::write(&key_file, &first_key).unwrap();
+
+        let config = TlsConfig { cert_file: Some(cert_file.clone()), key_file: Some(key_file.clone()), reload_interval_seconds: 1 };
+        let tls = load(&config).unwrap().unwrap();
+        let shutdown = Shutdown::new();
+        let watcher = tokio::spawn(watch(tls.cert.clone(), INTERVAL, shutdown.subscribe()));
+        assert_eq!(served(&tls.cert), first_der);
+        // it has to have seen the first files before they change
+        wait_for_reload().await;
+
+        // not a certificate
+        fs::write(&cert_file, "garbage").unwrap();
+        wait_for_reload().await;
+        assert_eq!(served(&tls.cert), first_der);
+
+        // a renewal caught halfway, the new certificate with the old key
+        let (second_pem, second_der, second_key) = certificate();
+        fs::write(&cert_file, &second_pem).unwrap();
+        wait_for_reload().await;
+        assert_eq!(served(&tls.cert), first_der);
+
+        // and once the key is there too
+        fs::write(&key_file, &second_key).unwrap();
+        wait_for_reload().await;
+        assert_eq!(served(&tls.cert), second_der);
+
+        shutdown.trigger();
+        watcher.await.unwrap();
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,172 @@
+use chrono::Utc;
+use qrcode::{render::svg, QrCode};
+use ring::hmac;
+
+/// RFC 6238 time-based one time passwords, with the parameters every authenticator app
+/// supports: HMAC-SHA1, 6 digits, a 30 second step.
+const DIGITS: u32 = 6;
+const STEP_SECONDS: i64 = 30;
+/// steps before and after the current one that are accepted, for clocks that drift
+const SKEW_STEPS: i64 = 1;
+/// 160 bits, the length of a SHA1 block recommended by RFC 4226
+const SECRET_BYTES: usize = 20;
+
+const BASE32: base32::Alphabet = base32::Alphabet::RFC4648 { padding: false };
+
+/// A new random secret, base32 encoded the way it's shown to the user and stored
+pub fn generate_secret() -> String {
+    let secret: [u8; SECRET_BYTES] = rand::random();
+    base32::encode(BASE32, &secret)
+}
+
+/// The time step of a unix timestamp
+pub fn step_at(timestamp: i64) -> i64 {
+    timestamp / STEP_SECONDS
+}
+
+pub fn current_step() -> i64 {
+    step_at(Utc::now().timestamp())
+}
+
+/// The code for a base32 `secret` at a time step, None if the secret can't be decoded
+pub fn code_at(secret: &str, step: i64) -> Option<String> {
+    let key = base32::decode(BASE32, secret)?;
+    let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, &key);
+    let digest = hmac::sign(&key, &step.to_be_bytes());
+    let digest = digest.as_ref();
+
+    // dynamic truncation, RFC 4226 section 5.3
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([digest[offset] & 0x7f, digest[offset + 1], digest[offset + 2], digest[offset + 3]]);
+
+    Some(format!("{:0width$}", binary % 10u32.pow(DIGITS), width = DIGITS as usize))
+}
+
+/// Check a code against the steps around `step`, returning the step it matched so it can't be
+/// used again. Steps up to `last_used_step` are rejected.
+pub fn verify(secret: &str, code: &str, step: i64, last_used_step: Option<i64>) -> Option<i64> {
+    let code = code.trim();
+
+    if code.len() != DIGITS as usize || !code.chars().all(|c| c.is_ascii_digit()) {
+        return None
+    }
+
+    // every step is checked so the time it takes doesn't tell which one matched
+    let mut matched = None;
+    for candidate in (step - SKEW_STEPS)..=(step + SKEW_STEPS) {
+        if let Some(expected) = code_at(secret, candidate) {
+            let equal = ring::constant_time::verify_slices_are_equal(expected.as_bytes(), code.as_bytes()).is_ok();
+            if equal && last_used_step.is_none_or(|last| candidate > last) {
+                matched = Some(candidate);
+            }
+        }
+    }
+
+    matched
+}
+
+/// The `otpauth://` uri authenticator apps enroll from, ref: https://github.com/google/google-authenticator/wiki/Key-Uri-Format
+pub fn otpauth_uri(issuer: &str, account: &str, secret: &str) -> String {
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        encode_uri_component(issuer),
+        encode_uri_component(account),
+        secret,
+        encode_uri_component(issuer),
+        DIGITS,
+        STEP_SECONDS,
+    )
+}
+
+/// The uri as an svg QR code, to scan with the authenticator app
+pub fn qr_code_svg(uri: &str) -> Option<String> {
+    match QrCode::new(uri.as_bytes()) {
+        Ok(code) => Some(code.render::<svg::Color>().min_dimensions(200, 200).build()),
+        Err(e) => {
+            println!("failed to render the qr code {}", e);
+            None
+        }
+    }
+}
+
+fn encode_uri_component(value: &str) -> String {
+    value.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // "12345678901234567890", the SHA1 secret of the RFC 6238 test vectors
+    const SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+    #[test]
+    fn codes_match_the_rfc_test_vectors() {
+        // the last 6 of the 8 digits in appendix B
+        assert_eq!(code_at(SECRET, step_at(59)).as_deref(), Some("287082"));
+        assert_eq!(code_at(SECRET, step_at(1111111109)).as_deref(), Some("081804"));
+        assert_eq!(code_at(SECRET, step_at(1234567890)).as_deref(), Some("005924"));
+    }
+
+    #[test]
+    fn steps_are_30_seconds() {
+        assert_eq!(step_at(0), 0);
+        assert_eq!(step_at(29), 0);
+        assert_eq!(step_at(30), 1);
+    }
+
+    #[test]
+    fn undecodable_secret_has_no_code() {
+        assert_eq!(code_at("not base32!", 1), None);
+    }
+
+    #[test]
+    fn accepts_one_step_of_skew() {
+        let step = 1000;
+        for matched in [step - 1, step, step + 1] {
+            let code = code_at(SECRET, matched).unwrap();
+            assert_eq!(verify(SECRET, &code, step, None), Some(matched));
+        }
+
+        for outside in [step - 2, step + 2] {
+            let code = code_at(SECRET, outside).unwrap();
+            assert_eq!(verify(SECRET, &code, step, None), None);
+        }
+    }
+
+    #[test]
+    fn rejects_a_replayed_step() {
+        let step = 1000;
+        let code = code_at(SECRET, step).unwrap();
+
+        assert_eq!(verify(SECRET, &code, step, Some(step - 1)), Some(step));
+        assert_eq!(verify(SECRET, &code, step, Some(step)), None);
+        // a later step was used, an earlier code can't be used after it
+        let earlier = code_at(SECRET, step - 1).unwrap();
+        assert_eq!(verify(SECRET, &earlier, step, Some(step)), None);
+    }
+
+    #[test]
+    fn rejects_malformed_codes() {
+        assert_eq!(verify(SECRET, "12345", 1000, None), None);
+        assert_eq!(verify(SECRET, "12345a", 1000, None), None);
+        assert_eq!(verify(SECRET, "1234567", 1000, None), None);
+    }
+
+    #[test]
+    fn trims_the_code() {
+        let code = code_at(SECRET, 1000).unwrap();
+        assert_eq!(verify(SECRET, &format!(" {} ", code), 1000, None), Some(1000));
+    }
+
+    #[test]
+    fn otpauth_uri_encodes_the_account() {
+        let uri = otpauth_uri("Acme Inc", "jane@example.com", SECRET);
+        assert_eq!(uri, format!("otpauth://totp/Acme%20Inc:jane%40example.com?secret={}&issuer=Acme%20Inc&algorithm=SHA1&digits=6&period=30", SECRET));
+    }
+}
@@ -0,0 +1,314 @@
+use chrono::{DateTime, Duration, Utc};
+use rand::Rng;
+use uuid::Uuid;
+use sqlx::postgres::PgPool;
+use crate::common::crypto;
+use crate::controller::mail_outbox;
+
+/// how long a confirmation code can be used for
+const CODE_TTL_MINUTES: i64 = 30;
+/// failed attempts before a code stops working. A new code carries the attempts of the code it
+/// replaces until that one expires, so resending doesn't buy more guesses.
+const MAX_ATTEMPTS: i32 = 5;
+/// codes that can be sent to one email, and from one client ip, in `SEND_WINDOW_MINUTES`
+const MAX_SENDS_PER_EMAIL: i64 = 5;
+const MAX_SENDS_PER_IP: i64 = 20;
+const SEND_WINDOW_MINUTES: i64 = 60;
+
+#[derive(Debug)]
+pub enum ConfirmationCodesError {
+    FailedInsert,
+    FailedLookup,
+    FailedConfirm,
+    FailedTransactionCommit,
+    // no unconfirmed user with the email
+    NotFound,
+    AlreadyConfirmed,
+    InvalidCode,
+    Expired,
+    TooManyAttempts,
+    // too many codes were sent to the email lately
+    TooManySends,
+    // too many codes were sent for the client ip lately
+    IpTooManySends,
+}
+
+#[derive(sqlx::FromRow, Debug)]
+struct UnconfirmedUser {
+    id: Uuid,
+    confirmed_at: Option<DateTime<Utc>>,
+}
+
+#[derive(sqlx::FromRow, Debug)]
+struct ConfirmationCode {
+    id: Uuid,
+    code_hash: String,
+    attempts: i32,
+    expires_at: DateTime<Utc>,
+}
+
+/// Create a new confirmation code for the user and queue the mail that sends it to `email`,
+/// any code sent before it stops working. `ip` is the client that asked for it, codes are
+/// counted against it. Returns the code, only its hash is stored.
+pub async fn create_confirmation_code(
+    pool: &PgPool,
+    user_id: Uuid,
+    email: &str,
+    ip: &str,
+) -> Result<String, ConfirmationCodesError> {
+    let mut tx = pool.begin().await.map_err(|_e| ConfirmationCodesError::FailedInsert)?;
+
+    let code = match create_confirmation_code_tx(&mut tx, user_id, email, ip).await {
+        Ok(v) => v,
+        Err(e) => {
+            let _e = tx.rollback().await;
+            return Err(e)
+        }
+    };
+
+    match tx.commit().await {
+        Ok(_v) => Ok(code),
+        Err(_e) => Err(ConfirmationCodesError::FailedTransactionCommit),
+    }
+}
+
+/// Send a new code to a user that hasn't confirmed their email yet. The ip is checked before
+/// the email is looked up, so its limit is the same for emails that aren't users.
+pub async fn resend_confirmation_code(
+    pool: &PgPool,
+    email: &str,
+    ip: &str,
+) -> Result<(Uuid, String), ConfirmationCodesError> {
+    let mut tx = pool.begin().await.map_err(|_e| ConfirmationCodesError::FailedInsert)?;
+
+    let result = resend_confirmation_code_tx(&mut tx, email, ip).await;
+    if result.is_err() {
+        let _e = tx.rollback().await;
+        return result
+    }
+
+    match tx.commit().await {
+        Ok(_v) => result,
+        Err(_e) => Err(ConfirmationCodesError::FailedTransactionCommit),
+    }
+}
+
+async fn resend_confirmation_code_tx(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    email: &str,
+    ip: &str,
+) -> Result<(Uuid, String), ConfirmationCodesError> {
+    if count_ip_sends_tx(tx, ip).await? >= MAX_SENDS_PER_IP {
+        return Err(ConfirmationCodesError::IpTooManySends)
+    }
+
+    // the user row is locked so concurrent resends are counted one after the other
+    let user = find_user_tx(tx, email).await?;
+
+    if user.confirmed_at.is_some() {
+        return Err(ConfirmationCodesError::AlreadyConfirmed)
+    }
+
+    if count_user_sends_tx(tx, user.id).await? >= MAX_SENDS_PER_EMAIL {
+        return Err(ConfirmationCodesError::TooManySends)
+    }
+
+    let code = create_confirmation_code_tx(tx, user.id, email, ip).await?;
+    Ok((user.id, code))
+}
+
+/// codes sent to the user in the last `SEND_WINDOW_MINUTES`
+async fn count_user_sends_tx(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    user_id: Uuid,
+) -> Result<i64, ConfirmationCodesError> {
+    match sqlx::query_as::<_, (i64,)>("SELECT count(*) FROM confirmation_codes WHERE user_id = $1 AND created_at > now() - make_interval(mins => $2)")
+        .bind(user_id)
+        .bind(SEND_WINDOW_MINUTES as i32)
+        .fetch_one(&mut **tx)
+        .await {
+            Ok(v) => Ok(v.0),
+            Err(e) => {
+                println!("{}", e);
+                Err(ConfirmationCodesError::FailedLookup)
+            }
+        }
+}
+
+/// codes the client ip asked for in the last `SEND_WINDOW_MINUTES`
+async fn count_ip_sends_tx(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    ip: &str,
+) -> Result<i64, ConfirmationCodesError> {
+    match sqlx::query_as::<_, (i64,)>("SELECT count(*) FROM confirmation_codes WHERE requested_ip = $1 AND created_at > now() - make_interval(mins => $2)")
+        .bind(ip)
+        .bind(SEND_WINDOW_MINUTES as i32)
+        .fetch_one(&mut **tx)
+        .await {
+            Ok(v) => Ok(v.0),
+            Err(e) => {
+                println!("{}", e);
+                Err(ConfirmationCodesError::FailedLookup)
+            }
+        }
+}
+
+async fn create_confirmation_code_tx(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    user_id: Uuid,
+    email: &str,
+    ip: &str,
+) -> Result<String, ConfirmationCodesError> {
+    let code = format!("{:06}", rand::thread_rng().gen_range(0..1_000_000));
+    let expires_at = Utc::now() + Duration::minutes(CODE_TTL_MINUTES);
+
+    // the wrong guesses against a code that's still live carry over to its replacement
+    let attempts = match sqlx::query_as::<_, (Option<i32>,)>("SELECT max(attempts) FROM confirmation_codes WHERE user_id = $1 AND used_at IS NULL AND expires_at > now()")
+        .bind(user_id)
+        .fetch_one(&mut **tx)
+        .await {
+            Ok(v) => v.0.unwrap_or(0),
+            Err(e) => {
+                println!("{}", e);
+                return Err(ConfirmationCodesError::FailedInsert)
+            }
+        };
+
+    match sqlx::query("UPDATE confirmation_codes SET used_at = now() WHERE user_id = $1 AND used_at IS NULL")
+        .bind(user_id)
+        .execute(&mut **tx)
+        .await {
+            Ok(_v) => {},
+            Err(e) => {
+                println!("{}", e);
+                return Err(ConfirmationCodesError::FailedInsert)
+            }
+        }
+
+    match sqlx::query("INSERT INTO confirmation_codes (user_id, code_hash, expires_at, attempts, requested_ip) VALUES ($1, $2, $3, $4, $5)")
+        .bind(user_id)
+        .bind(crypto::hash_token(&code))
+        .bind(expires_at)
+        .bind(attempts)
+        .bind(ip)
+        .execute(&mut **tx)
+        .await {
+            Ok(_v) => {},
+            Err(e) => {
+                println!("{}", e);
+                return Err(ConfirmationCodesError::FailedInsert)
+            }
+        }
+
+    let context = serde_json::json!({
+        "email": email,
+        "code": code,
+        "expires_in_minutes": CODE_TTL_MINUTES,
+    });
+
+    match mail_outbox::enqueue_tx(tx, email, "Confirm your email", "confirmation_code", &context).await {
+        Ok(_v) => Ok(code),
+        Err(e) => {
+            println!("{:?}", e);
+            Err(ConfirmationCodesError::FailedInsert)
+        }
+    }
+}
+
+/// Confirm the email of the user with the code that was sent to them, returning their id.
+/// Every wrong code counts against the current code, after `MAX_ATTEMPTS` no code works until
+/// it expires and a new one is sent.
+pub async fn confirm_with_code(
+    pool: &PgPool,
+    email: &str,
+    code: &str,
+) -> Result<Uuid, ConfirmationCodesError> {
+    let mut tx = pool.begin().await.map_err(|_e| ConfirmationCodesError::FailedConfirm)?;
+
+    let result = confirm_with_code_tx(&mut tx, email, code).await;
+
+    // failed attempts are committed too, so they keep counting
+    match tx.commit().await {
+        Ok(_v) => result,
+        Err(_e) => Err(ConfirmationCodesError::FailedTransactionCommit),
+    }
+}
+
+async fn confirm_with_code_tx(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    email: &str,
+    code: &str,
+) -> Result<Uuid, ConfirmationCodesError> {
+    let user = find_user_tx(tx, email).await?;
+
+    if user.confirmed_at.is_some() {
+        return Err(ConfirmationCodesError::AlreadyConfirmed)
+    }
+
+    let current = match sqlx::query_as::<_, ConfirmationCode>("SELECT id, code_hash, attempts, expires_at FROM confirmation_codes WHERE user_id = $1 AND used_at IS NULL ORDER BY created_at DESC LIMIT 1 FOR UPDATE")
+        .bind(user.id)
+        .fetch_optional(&mut **tx)
+        .await {
+            Ok(Some(v)) => v,
+            Ok(None) => return Err(ConfirmationCodesError::InvalidCode),
+            Err(e) => {
+                println!("{}", e);
+                return Err(ConfirmationCodesError::FailedLookup)
+            }
+        };
+
+    if current.attempts >= MAX_ATTEMPTS {
+        return Err(ConfirmationCodesError::TooManyAttempts)
+    }
+
+    if current.expires_at < Utc::now() {
+        return Err(ConfirmationCodesError::Expired)
+    }
+
+    if !crypto::hashes_match(&current.code_hash, &crypto::hash_token(code.trim())) {
+        let _e = sqlx::query("UPDATE confirmation_codes SET attempts = attempts + 1 WHERE id = $1")
+            .bind(current.id)
+            .execute(&mut **tx)
+            .await;
+        return Err(ConfirmationCodesError::InvalidCode)
+    }
+
+    match sqlx::query("UPDATE confirmation_codes SET used_at = now() WHERE id = $1")
+        .bind(current.id)
+        .execute(&mut **tx)
+        .await {
+            Ok(_v) => {},
+            Err(e) => {
+                println!("{}", e);
+                return Err(ConfirmationCodesError::FailedConfirm)
+            }
+        }
+
+    match sqlx::query("UPDATE users SET confirmed_at = now() WHERE id = $1")
+        .bind(user.id)
+        .execute(&mut **tx)
+        .await {
+            Ok(_v) => Ok(user.id),
+            Err(e) => {
+                println!("{}", e);
+                Err(ConfirmationCodesError::FailedConfirm)
+            }
+        }
+}
+
+async fn find_user_tx(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    email: &str,
+) -> Result<UnconfirmedUser, ConfirmationCodesError> {
+    match sqlx::query_as::<_, UnconfirmedUser>("SELECT id, confirmed_at FROM users WHERE email = $1 FOR UPDATE")
+        .bind(email)
+        .fetch_optional(&mut **tx)
+        .await {
+            Ok(Some(v)) => Ok(v),
+            Ok(None) => Err(ConfirmationCodesError::NotFound),
+            Err(e) => {
+                println!("{}", e);
+                Err(ConfirmationCodesError::FailedLookup)
+            }
+        }
+}
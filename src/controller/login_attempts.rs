@@ -0,0 +1,401 @@
+use chrono::{DateTime, Duration, Utc};
+use sqlx::postgres::PgPool;
+
+/// How failed logins are limited for one kind of key
+struct Limits {
+    kind: &'static str,
+    // failures before the backoff starts
+    free_attempts: i32,
+    // failures before the key is locked out
+    lockout_threshold: i32,
+    lockout_minutes: i64,
+}
+
+/// an account is locked after a handful of guesses
+const EMAIL_LIMITS: Limits = Limits {
+    kind: "email",
+    free_attempts: 3,
+    lockout_threshold: 10,
+    lockout_minutes: 15,
+};
+
+/// many users can share an ip behind a NAT, so it gets more room before it's locked
+const IP_LIMITS: Limits = Limits {
+    kind: "ip",
+    free_attempts: 10,
+    lockout_threshold: 100,
+    lockout_minutes: 60,
+};
+
+/// longest backoff between two attempts before the lockout
+const MAX_BACKOFF_SECONDS: i64 = 300;
+/// failures older than this are forgotten
+const FAILURE_WINDOW_HOURS: i64 = 1;
+
+#[derive(Debug)]
+pub enum LoginAttemptsError {
+    FailedLookup,
+    FailedUpdate,
+    FailedTransactionCommit,
+    NotFound,
+    // too many failures for the account
+    AccountLocked(DateTime<Utc>),
+    // too many failures from the client ip
+    IpLocked(DateTime<Utc>),
+    // the last failure of the account was too recent, try again after the backoff
+    Throttled(DateTime<Utc>),
+    // the last failure from the client ip was too recent
+    IpThrottled(DateTime<Utc>),
+}
+
+#[derive(sqlx::FromRow, Debug)]
+struct LoginAttempt {
+    kind: String,
+    next_attempt_at: Option<DateTime<Utc>>,
+    locked_until: Option<DateTime<Utc>>,
+}
+
+#[derive(sqlx::FromRow, Debug)]
+pub struct Lockout {
+    pub kind: String,
+    pub key: String,
+    pub failures: i32,
+    pub last_failure_at: DateTime<Utc>,
+    pub locked_until: DateTime<Utc>,
+}
+
+/// A login attempt that was counted as a failure before the credentials were checked,
+/// `release_login_attempt` gives it back when they turn out to be right
+#[derive(Debug)]
+pub struct ReservedAttempt {
+    email: String,
+    ip: String,
+    // failures of the account and the ip once this attempt was counted
+    email_failures: i32,
+    ip_failures: i32,
+}
+
+/// Check whether a login for `email` from `ip` may be attempted, and count it as a failure
+/// before the password is checked. Both happen in one transaction with the rows locked, so
+/// concurrent guesses can't all pass the check before any of them is counted. A lockout is
+/// reported before a backoff.
+pub async fn reserve_login_attempt(pool: &PgPool, email: &str, ip: &str) -> Result<ReservedAttempt, LoginAttemptsError> {
+    let mut tx = pool.begin().await.map_err(|_e| LoginAttemptsError::FailedUpdate)?;
+
+    let attempt = match reserve_login_attempt_tx(&mut tx, &normalize_email(email), ip).await {
+        Ok(v) => v,
+        Err(e) => {
+            let _e = tx.rollback().await;
+            return Err(e)
+        }
+    };
+
+    match tx.commit().await {
+        Ok(_v) => Ok(attempt),
+        Err(_e) => Err(LoginAttemptsError::FailedTransactionCommit),
+    }
+}
+
+async fn reserve_login_attempt_tx(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    email: &str,
+    ip: &str,
+) -> Result<ReservedAttempt, LoginAttemptsError> {
+    // a key that never failed has no row yet, and there has to be one to lock
+    if let Err(e) = sqlx::query("INSERT INTO login_attempts (kind, key) VALUES ($1, $2), ($3, $4) ON CONFLICT (kind, key) DO NOTHING")
+        .bind(EMAIL_LIMITS.kind)
+        .bind(email)
+        .bind(IP_LIMITS.kind)
+        .bind(ip)
+        .execute(&mut **tx)
+        .await {
+            println!("{}", e);
+            return Err(LoginAttemptsError::FailedUpdate)
+        }
+
+    // always locked in the same order, so two attempts can't wait on each other
+    let attempts = match sqlx::query_as::<_, LoginAttempt>("SELECT kind, next_attempt_at, locked_until FROM login_attempts WHERE (kind = $1 AND key = $2) OR (kind = $3 AND key = $4) ORDER BY kind FOR UPDATE")
+        .bind(EMAIL_LIMITS.kind)
+        .bind(email)
+        .bind(IP_LIMITS.kind)
+        .bind(ip)
+        .fetch_all(&mut **tx)
+        .await {
+            Ok(v) => v,
+            Err(e) => {
+                println!("{}", e);
+                return Err(LoginAttemptsError::FailedLookup)
+            }
+        };
+
+    check_attempts(&attempts, Utc::now())?;
+
+    Ok(ReservedAttempt {
+        email_failures: record_failure_tx(tx, &EMAIL_LIMITS, email).await?,
+        ip_failures: record_failure_tx(tx, &IP_LIMITS, ip).await?,
+        email: email.to_owned(),
+        ip: ip.to_owned(),
+    })
+}
+
+// whether another attempt is allowed at `now`
+fn check_attempts(attempts: &[LoginAttempt], now: DateTime<Utc>) -> Result<(), LoginAttemptsError> {
+    for attempt in attempts {
+        match attempt.locked_until {
+            Some(until) if until > now && attempt.kind == EMAIL_LIMITS.kind => return Err(LoginAttemptsError::AccountLocked(until)),
+            Some(until) if until > now => return Err(LoginAttemptsError::IpLocked(until)),
+            _ => {},
+        }
+    }
+
+    // the account and the ip back off on their own, a busy ip doesn't slow down an account
+    // that is signed into from elsewhere
+    for attempt in attempts {
+        match attempt.next_attempt_at {
+            Some(at) if at > now && attempt.kind == EMAIL_LIMITS.kind => return Err(LoginAttemptsError::Throttled(at)),
+            Some(at) if at > now => return Err(LoginAttemptsError::IpThrottled(at)),
+            _ => {},
+        }
+    }
+
+    Ok(())
+}
+
+/// Give back a reserved attempt once the password or second factor turned out to be right.
+/// The backoff it started is lifted too, unless another failure was counted since.
+pub async fn release_login_attempt(pool: &PgPool, attempt: ReservedAttempt) -> Result<(), LoginAttemptsError> {
+    let mut tx = pool.begin().await.map_err(|_e| LoginAttemptsError::FailedUpdate)?;
+
+    for (limits, key, failures) in [(&EMAIL_LIMITS, &attempt.email, attempt.email_failures), (&IP_LIMITS, &attempt.ip, attempt.ip_failures)] {
+        if let Err(e) = sqlx::query("UPDATE login_attempts SET failures = GREATEST(failures - 1, 0), next_attempt_at = CASE WHEN failures = $3 THEN NULL ELSE next_attempt_at END, locked_until = CASE WHEN failures = $3 THEN NULL ELSE locked_until END WHERE kind = $1 AND key = $2")
+            .bind(limits.kind)
+            .bind(key)
+            .bind(failures)
+            .execute(&mut *tx)
+            .await {
+                println!("{}", e);
+                let _e = tx.rollback().await;
+                return Err(LoginAttemptsError::FailedUpdate)
+            }
+    }
+
+    match tx.commit().await {
+        Ok(_v) => Ok(()),
+        Err(_e) => Err(LoginAttemptsError::FailedTransactionCommit),
+    }
+}
+
+// count a failure against the key, returning how many there are now
+async fn record_failure_tx(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    limits: &Limits,
+    key: &str,
+) -> Result<i32, LoginAttemptsError> {
+    // the count starts over when the last failure is old enough, or a lockout has passed
+    let failures = match sqlx::query_as::<_, (i32,)>("INSERT INTO login_attempts (kind, key, failures) VALUES ($1, $2, 1) ON CONFLICT (kind, key) DO UPDATE SET failures = CASE WHEN login_attempts.last_failure_at < now() - make_interval(hours => $3) OR login_attempts.locked_until < now() THEN 1 ELSE login_attempts.failures + 1 END, last_failure_at = now(), locked_until = NULL RETURNING failures")
+        .bind(limits.kind)
+        .bind(key)
+        .bind(FAILURE_WINDOW_HOURS as i32)
+        .fetch_one(&mut **tx)
+        .await {
+            Ok(v) => v.0,
+            Err(e) => {
+                println!("{}", e);
+                return Err(LoginAttemptsError::FailedUpdate)
+            }
+        };
+
+    let (next_attempt_at, locked_until) = backoff(limits, failures, Utc::now());
+    if locked_until.is_some() {
+        println!("login {} {} locked until {:?}", limits.kind, key, locked_until);
+    }
+
+    match sqlx::query("UPDATE login_attempts SET next_attempt_at = $3, locked_until = $4 WHERE kind = $1 AND key = $2")
+        .bind(limits.kind)
+        .bind(key)
+        .bind(next_attempt_at)
+        .bind(locked_until)
+        .execute(&mut **tx)
+        .await {
+            Ok(_v) => Ok(failures),
+            Err(e) => {
+                println!("{}", e);
+                Err(LoginAttemptsError::FailedUpdate)
+            }
+        }
+}
+
+// when the next attempt is allowed after `failures`, and until when the key is locked out.
+// The delay doubles with every failure past the free attempts.
+fn backoff(limits: &Limits, failures: i32, now: DateTime<Utc>) -> (Option<DateTime<Utc>>, Option<DateTime<Utc>>) {
+    if failures >= limits.lockout_threshold {
+        let until = now + Duration::minutes(limits.lockout_minutes);
+        (Some(until), Some(until))
+    } else if failures > limits.free_attempts {
+        let exponent = (failures - limits.free_attempts - 1).min(16) as u32;
+        let delay = 2i64.pow(exponent).min(MAX_BACKOFF_SECONDS);
+        (Some(now + Duration::seconds(delay)), None)
+    } else {
+        (None, None)
+    }
+}
+
+/// Forget the failures of an account after it signed in. The failures of the ip are kept,
+/// a valid login to one account shouldn't let it keep guessing at others.
+pub async fn record_login_success(pool: &PgPool, email: &str) -> Result<(), LoginAttemptsError> {
+    unlock_key(pool, EMAIL_LIMITS.kind, &normalize_email(email)).await.map(|_v| ())
+}
+
+/// Lift the lockout and backoff of an account, for admins
+pub async fn unlock_account(pool: &PgPool, email: &str) -> Result<(), LoginAttemptsError> {
+    match unlock_key(pool, EMAIL_LIMITS.kind, &normalize_email(email)).await {
+        Ok(0) => Err(LoginAttemptsError::NotFound),
+        Ok(_v) => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Accounts and ips that are locked out right now
+pub async fn find_lockouts(pool: &PgPool) -> Result<Vec<Lockout>, LoginAttemptsError> {
+    match sqlx::query_as::<_, Lockout>("SELECT kind, key, failures, last_failure_at, locked_until FROM login_attempts WHERE locked_until > now() ORDER BY locked_until DESC")
+        .fetch_all(pool)
+        .await {
+            Ok(v) => Ok(v),
+            Err(e) => {
+                println!("{}", e);
+                Err(LoginAttemptsError::FailedLookup)
+            }
+        }
+}
+
+async fn unlock_key(pool: &PgPool, kind: &str, key: &str) -> Result<u64, LoginAttemptsError> {
+    match sqlx::query("DELETE FROM login_attempts WHERE kind = $1 AND key = $2")
+        .bind(kind)
+        .bind(key)
+        .execute(pool)
+        .await {
+            Ok(v) => Ok(v.rows_affected()),
+            Err(e) => {
+                println!("{}", e);
+                Err(LoginAttemptsError::FailedUpdate)
+            }
+        }
+}
+
+// `Jane@example.com` and `jane@example.com ` are the same account to an attacker
+fn normalize_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+    use crate::common::database;
+
+    fn attempt(kind: &str, next_attempt_at: Option<DateTime<Utc>>, locked_until: Option<DateTime<Utc>>) -> LoginAttempt {
+        LoginAttempt { kind: kind.to_owned(), next_attempt_at, locked_until }
+    }
+
+    async fn failures(pool: &PgPool, kind: &str, key: &str) -> (i32, Option<DateTime<Utc>>) {
+        sqlx::query_as::<_, (i32, Option<DateTime<Utc>>)>("SELECT failures, next_attempt_at FROM login_attempts WHERE kind = $1 AND key = $2")
+            .bind(kind)
+            .bind(key)
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    async fn forget(pool: &PgPool, email: &str, ip: &str) {
+        sqlx::query("DELETE FROM login_attempts WHERE (kind = 'email' AND key = $1) OR (kind = 'ip' AND key = $2)")
+            .bind(email)
+            .bind(ip)
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+
+    #[test]
+    fn backoff_doubles_after_the_free_attempts_until_the_lockout() {
+        let now = Utc::now();
+        assert_eq!(backoff(&EMAIL_LIMITS, 3, now), (None, None));
+        assert_eq!(backoff(&EMAIL_LIMITS, 4, now), (Some(now + Duration::seconds(1)), None));
+        assert_eq!(backoff(&EMAIL_LIMITS, 6, now), (Some(now + Duration::seconds(4)), None));
+        let until = now + Duration::minutes(EMAIL_LIMITS.lockout_minutes);
+        assert_eq!(backoff(&EMAIL_LIMITS, 10, now), (Some(until), Some(until)));
+    }
+
+    #[test]
+    fn backoff_is_capped() {
+        let now = Utc::now();
+        let (next, _locked) = backoff(&IP_LIMITS, 99, now);
+        assert_eq!(next, Some(now + Duration::seconds(MAX_BACKOFF_SECONDS)));
+    }
+
+    #[test]
+    fn lockouts_are_reported_before_backoffs() {
+        let now = Utc::now();
+        let later = Some(now + Duration::minutes(1));
+        let attempts = [attempt("email", later, None), attempt("ip", later, later)];
+        assert!(matches!(check_attempts(&attempts, now), Err(LoginAttemptsError::IpLocked(_))));
+    }
+
+    #[test]
+    fn the_account_and_the_ip_back_off_separately() {
+        let now = Utc::now();
+        let later = Some(now + Duration::minutes(1));
+        let earlier = Some(now - Duration::minutes(1));
+        assert!(matches!(check_attempts(&[attempt("email", later, None)], now), Err(LoginAttemptsError::Throttled(_))));
+        assert!(matches!(check_attempts(&[attempt("ip", later, None)], now), Err(LoginAttemptsError::IpThrottled(_))));
+        assert!(check_attempts(&[attempt("email", earlier, earlier), attempt("ip", None, None)], now).is_ok());
+    }
+
+    #[tokio::test]
+    async fn concurrent_attempts_are_counted_before_the_next_is_checked() {
+        let Some(pool) = database::test_pool().await else { return };
+        let email = format!("test-{}@example.com", Uuid::new_v4());
+        let ip = format!("test-{}", Uuid::new_v4());
+
+        let handles: Vec<_> = (0..10)
+            .map(|_| {
+                let (pool, email, ip) = (pool.clone(), email.clone(), ip.clone());
+                tokio::spawn(async move { reserve_login_attempt(&pool, &email, &ip).await.is_ok() })
+            })
+            .collect();
+
+        let mut allowed = 0;
+        for handle in handles {
+            if handle.await.unwrap() {
+                allowed += 1;
+            }
+        }
+
+        // the free attempts, and the one that starts the backoff
+        assert_eq!(allowed, EMAIL_LIMITS.free_attempts + 1);
+        assert_eq!(failures(&pool, "email", &email).await.0, EMAIL_LIMITS.free_attempts + 1);
+
+        forget(&pool, &email, &ip).await;
+    }
+
+    #[tokio::test]
+    async fn a_released_attempt_is_not_counted() {
+        let Some(pool) = database::test_pool().await else { return };
+        let email = format!("test-{}@example.com", Uuid::new_v4());
+        let ip = format!("test-{}", Uuid::new_v4());
+
+        for _ in 0..EMAIL_LIMITS.free_attempts {
+            reserve_login_attempt(&pool, &email, &ip).await.unwrap();
+        }
+
+        // this one starts the backoff, until it's given back
+        let attempt = reserve_login_attempt(&pool, &email, &ip).await.unwrap();
+        assert!(failures(&pool, "email", &email).await.1.is_some());
+
+        release_login_attempt(&pool, attempt).await.unwrap();
+        assert_eq!(failures(&pool, "email", &email).await, (EMAIL_LIMITS.free_attempts, None));
+        assert_eq!(failures(&pool, "ip", &ip).await, (EMAIL_LIMITS.free_attempts, None));
+        assert!(reserve_login_attempt(&pool, &email, &ip).await.is_ok());
+
+        forget(&pool, &email, &ip).await;
+    }
+}
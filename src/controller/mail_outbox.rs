@@ -0,0 +1,179 @@
+use chrono::{Duration, Utc};
+use serde::Serialize;
+use uuid::Uuid;
+use sqlx::postgres::PgPool;
+use crate::common::{mailer::{self, Mailers}, shutdown::{self, ShutdownSignal}, templates::{self, Templates}};
+
+/// attempts before a mail is given up on
+const MAX_ATTEMPTS: i32 = 8;
+/// delay before the first retry, it doubles with every failed attempt
+const RETRY_BASE_SECONDS: i64 = 30;
+/// mails claimed at a time
+const BATCH_SIZE: i64 = 20;
+/// other workers leave a claimed mail alone this long, it's picked up again if the worker
+/// that claimed it stopped before sending it
+const CLAIM_SECONDS: i64 = 300;
+
+#[derive(Debug)]
+pub enum MailOutboxError {
+    FailedInsert,
+    FailedLookup,
+    FailedUpdate,
+    InvalidContext,
+}
+
+#[derive(sqlx::FromRow, Debug)]
+struct OutboxMail {
+    id: Uuid,
+    to_address: String,
+    subject: String,
+    template: String,
+    context: String,
+    attempts: i32,
+}
+
+/// Queue a mail in the transaction of the change it's about, so it's only sent if the change
+/// is committed. The body is rendered from `mail/<template>` with `context` when it's delivered.
+pub async fn enqueue_tx<C: Serialize>(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    to: &str,
+    subject: &str,
+    template: &str,
+    context: &C,
+) -> Result<Uuid, MailOutboxError> {
+    let context = serde_json::to_string(context).map_err(|_e| MailOutboxError::InvalidContext)?;
+
+    match sqlx::query_as::<_, (Uuid,)>("INSERT INTO mail_outbox (to_address, subject, template, context) VALUES ($1, $2, $3, $4::jsonb) RETURNING id")
+        .bind(to)
+        .bind(subject)
+        .bind(template)
+        .bind(context)
+        .fetch_one(&mut **tx)
+        .await {
+            Ok(v) => Ok(v.0),
+            Err(e) => {
+                println!("{}", e);
+                Err(MailOutboxError::FailedInsert)
+            }
+        }
+}
+
+/// Deliver the outbox every `interval` until shutdown, links in the mail point to `public_url`.
+/// On shutdown the mail that is due is delivered one last time before it returns.
+pub async fn run_worker(pool: PgPool, mailer: Mailers, templates: Templates, public_url: String, interval: std::time::Duration, signal: ShutdownSignal) {
+    let mut ticker = tokio::time::interval(interval);
+
+    loop {
+        let stopping = tokio::select! {
+            biased;
+            _ = shutdown::triggered(signal.clone()) => true,
+            _ = ticker.tick() => false,
+        };
+
+        // keep going while there's a full batch of mail waiting
+        loop {
+            match deliver_pending(&pool, &mailer, &templates, &public_url, BATCH_SIZE).await {
+                Ok(v) if v as i64 == BATCH_SIZE => continue,
+                Ok(_v) => break,
+                Err(e) => {
+                    println!("failed to deliver the mail outbox {:?}", e);
+                    break
+                }
+            }
+        }
+
+        if stopping {
+            println!("mail outbox flushed");
+            return
+        }
+    }
+}
+
+/// Deliver up to `limit` mails that are due, returning how many were sent. Mails are claimed
+/// first and sent outside of any transaction, so several workers can share the outbox and a
+/// slow SMTP server never holds locks. A failed mail is retried with an exponential backoff
+/// until `MAX_ATTEMPTS`. Once a mail is sent or given up on its context is cleared, it can hold
+/// reset tokens and confirmation codes.
+pub async fn deliver_pending(
+    pool: &PgPool,
+    mailer: &Mailers,
+    templates: &Templates,
+    public_url: &str,
+    limit: i64,
+) -> Result<usize, MailOutboxError> {
+    let mails = claim(pool, limit).await?;
+    let mut sent = 0;
+
+    for mail in mails {
+        let result = match render(templates, public_url, &mail) {
+            Ok(message) => mailer.send(&message).await,
+            Err(e) => Err(e),
+        };
+
+        let update = match result {
+            Ok(_v) => {
+                sent += 1;
+                sqlx::query("UPDATE mail_outbox SET sent_at = now(), attempts = attempts + 1, last_error = NULL, context = '{}' WHERE id = $1")
+                    .bind(mail.id)
+                    .execute(pool)
+                    .await
+            },
+            Err(e) => {
+                let attempts = mail.attempts + 1;
+                println!("failed to send mail {} to {} (attempt {}): {}", mail.id, mail.to_address, attempts, e.0);
+
+                if attempts >= MAX_ATTEMPTS {
+                    sqlx::query("UPDATE mail_outbox SET failed_at = now(), attempts = $2, last_error = $3, context = '{}' WHERE id = $1")
+                        .bind(mail.id)
+                        .bind(attempts)
+                        .bind(e.0)
+                        .execute(pool)
+                        .await
+                } else {
+                    let next_attempt_at = Utc::now() + Duration::seconds(RETRY_BASE_SECONDS << (attempts - 1));
+                    sqlx::query("UPDATE mail_outbox SET next_attempt_at = $2, attempts = $3, last_error = $4 WHERE id = $1")
+                        .bind(mail.id)
+                        .bind(next_attempt_at)
+                        .bind(attempts)
+                        .bind(e.0)
+                        .execute(pool)
+                        .await
+                }
+            }
+        };
+
+        // the rest of the batch stays claimed, it's sent again once the claim runs out
+        if let Err(e) = update {
+            println!("{}", e);
+            return Err(MailOutboxError::FailedUpdate)
+        }
+    }
+
+    Ok(sent)
+}
+
+// push the due mails out by `CLAIM_SECONDS` and hand them back, the row locks are only held
+// for this one statement
+async fn claim(pool: &PgPool, limit: i64) -> Result<Vec<OutboxMail>, MailOutboxError> {
+    match sqlx::query_as::<_, OutboxMail>("UPDATE mail_outbox SET next_attempt_at = $2 WHERE id IN (SELECT id FROM mail_outbox WHERE sent_at IS NULL AND failed_at IS NULL AND next_attempt_at <= now() ORDER BY next_attempt_at LIMIT $1 FOR UPDATE SKIP LOCKED) RETURNING id, to_address, subject, template, context::text AS context, attempts")
+        .bind(limit)
+        .bind(Utc::now() + Duration::seconds(CLAIM_SECONDS))
+        .fetch_all(pool)
+        .await {
+            Ok(v) => Ok(v),
+            Err(e) => {
+                println!("{}", e);
+                Err(MailOutboxError::FailedLookup)
+            }
+        }
+}
+
+fn render(templates: &Templates, public_url: &str, mail: &OutboxMail) -> mailer::MailResult<mailer::Message> {
+    let value = serde_json::from_str::<serde_json::Value>(&mail.context)
+        .map_err(|e| mailer::MailError(format!("invalid context: {}", e)))?;
+    let mut context = templates::Context::from_value(value)
+        .map_err(|e| mailer::MailError(format!("invalid context: {}", e)))?;
+    context.insert("public_url", public_url);
+
+    mailer::render(templates, &mail.template, &mail.to_address, &mail.subject, &context)
+}
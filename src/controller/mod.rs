@@ -1 +1,11 @@
-pub mod users;
\ No newline at end of file
+pub mod users;
+pub mod refresh_tokens;
+pub mod policies;
+pub mod confirmation_codes;
+pub mod password_resets;
+pub mod mail_outbox;
+pub mod two_factor;
+pub mod login_attempts;
+pub mod revoked_tokens;
+pub mod sessions;
+pub mod roles;
@@ -0,0 +1,302 @@
+use chrono::{DateTime, Duration, Utc};
+use rand::distributions::{Alphanumeric, DistString};
+use uuid::Uuid;
+use sqlx::postgres::PgPool;
+use crate::common::crypto;
+use crate::controller::{mail_outbox, users};
+
+/// how long a reset link can be used for
+const TOKEN_TTL_MINUTES: i64 = 60;
+
+#[derive(Debug)]
+pub enum PasswordResetsError {
+    FailedInsert,
+    FailedLookup,
+    FailedReset,
+    FailedTransactionCommit,
+    // no user with the email
+    NotFound,
+    // unknown, or already used token
+    InvalidToken,
+    Expired,
+}
+
+#[derive(sqlx::FromRow, Debug)]
+struct ResetToken {
+    id: Uuid,
+    user_id: Uuid,
+    expires_at: DateTime<Utc>,
+}
+
+#[derive(sqlx::FromRow, Debug)]
+struct ResetTokenEmail {
+    expires_at: DateTime<Utc>,
+    email: String,
+}
+
+/// Create a reset token for the user with `email` and queue the mail with the reset link, any
+/// token sent before it stops working. Returns the token, only its hash is stored.
+pub async fn create_reset_token(pool: &PgPool, email: &str) -> Result<String, PasswordResetsError> {
+    let user_id = match sqlx::query_as::<_, (Uuid,)>("SELECT id FROM users WHERE email = $1")
+        .bind(email)
+        .fetch_optional(pool)
+        .await {
+            Ok(Some(v)) => v.0,
+            Ok(None) => return Err(PasswordResetsError::NotFound),
+            Err(e) => {
+                println!("{}", e);
+                return Err(PasswordResetsError::FailedLookup)
+            }
+        };
+
+    let token = Alphanumeric.sample_string(&mut rand::thread_rng(), 43);
+    let expires_at = Utc::now() + Duration::minutes(TOKEN_TTL_MINUTES);
+    let mut tx = pool.begin().await.map_err(|_e| PasswordResetsError::FailedInsert)?;
+
+    let result = async {
+        sqlx::query("UPDATE password_reset_tokens SET used_at = now() WHERE user_id = $1 AND used_at IS NULL")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query("INSERT INTO password_reset_tokens (user_id, token_hash, expires_at) VALUES ($1, $2, $3)")
+            .bind(user_id)
+            .bind(crypto::hash_token(&token))
+            .bind(expires_at)
+            .execute(&mut *tx)
+            .await
+    }.await;
+
+    if let Err(e) = result {
+        println!("{}", e);
+        let _e = tx.rollback().await;
+        return Err(PasswordResetsError::FailedInsert)
+    }
+
+    let context = serde_json::json!({
+        "email": email,
+        "token": token,
+        "expires_in_minutes": TOKEN_TTL_MINUTES,
+    });
+
+    if let Err(e) = mail_outbox::enqueue_tx(&mut tx, email, "Reset your password", "password_reset", &context).await {
+        println!("{:?}", e);
+        let _e = tx.rollback().await;
+        return Err(PasswordResetsError::FailedInsert)
+    }
+
+    match tx.commit().await {
+        Ok(_v) => Ok(token),
+        Err(_e) => Err(PasswordResetsError::FailedTransactionCommit),
+    }
+}
+
+/// Check a reset token without using it, so the reset form is only shown for a usable link.
+/// Returns the email of the user, the new password is checked against it.
+pub async fn verify_reset_token(pool: &PgPool, token: &str) -> Result<String, PasswordResetsError> {
+    let reset = match sqlx::query_as::<_, ResetTokenEmail>("SELECT password_reset_tokens.expires_at, users.email FROM password_reset_tokens JOIN users ON users.id = password_reset_tokens.user_id WHERE password_reset_tokens.token_hash = $1 AND password_reset_tokens.used_at IS NULL")
+        .bind(crypto::hash_token(token))
+        .fetch_optional(pool)
+        .await {
+            Ok(Some(v)) => v,
+            Ok(None) => return Err(PasswordResetsError::InvalidToken),
+            Err(e) => {
+                println!("{}", e);
+                return Err(PasswordResetsError::FailedLookup)
+            }
+        };
+
+    if reset.expires_at < Utc::now() {
+        return Err(PasswordResetsError::Expired)
+    }
+
+    Ok(reset.email)
+}
+
+/// Use the reset token to set a new password. Every session and refresh token of the user is
+/// revoked, and since the link proves they own the email, it's confirmed if it wasn't yet.
+pub async fn reset_password(
+    pool: &PgPool,
+    token: &str,
+    password: &str,
+) -> Result<Uuid, PasswordResetsError> {
+    let mut tx = pool.begin().await.map_err(|_e| PasswordResetsError::FailedReset)?;
+
+    let result = reset_password_tx(&mut tx, token, password).await;
+
+    match result {
+        Ok(user_id) => match tx.commit().await {
+            Ok(_v) => Ok(user_id),
+            Err(_e) => Err(PasswordResetsError::FailedTransactionCommit),
+        },
+        Err(e) => {
+            let _e = tx.rollback().await;
+            Err(e)
+        }
+    }
+}
+
+async fn reset_password_tx(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    token: &str,
+    password: &str,
+) -> Result<Uuid, PasswordResetsError> {
+    // lock the token so it can only be used once
+    let reset = match sqlx::query_as::<_, ResetToken>("SELECT id, user_id, expires_at FROM password_reset_tokens WHERE token_hash = $1 AND used_at IS NULL FOR UPDATE")
+        .bind(crypto::hash_token(token))
+        .fetch_optional(&mut **tx)
+        .await {
+            Ok(Some(v)) => v,
+            Ok(None) => return Err(PasswordResetsError::InvalidToken),
+            Err(e) => {
+                println!("{}", e);
+                return Err(PasswordResetsError::FailedLookup)
+            }
+        };
+
+    if reset.expires_at < Utc::now() {
+        return Err(PasswordResetsError::Expired)
+    }
+
+    match sqlx::query("UPDATE password_reset_tokens SET used_at = now() WHERE id = $1")
+        .bind(reset.id)
+        .execute(&mut **tx)
+        .await {
+            Ok(_v) => {},
+            Err(e) => {
+                println!("{}", e);
+                return Err(PasswordResetsError::FailedReset)
+            }
+        }
+
+    if let Err(e) = users::update_password_tx(tx, reset.user_id, password).await {
+        println!("{:?}", e);
+        return Err(PasswordResetsError::FailedReset)
+    }
+
+    if let Err(e) = users::revoke_sessions_tx(tx, reset.user_id).await {
+        println!("{:?}", e);
+        return Err(PasswordResetsError::FailedReset)
+    }
+
+    match sqlx::query("UPDATE users SET confirmed_at = COALESCE(confirmed_at, now()) WHERE id = $1")
+        .bind(reset.user_id)
+        .execute(&mut **tx)
+        .await {
+            Ok(_v) => Ok(reset.user_id),
+            Err(e) => {
+                println!("{}", e);
+                Err(PasswordResetsError::FailedReset)
+            }
+        }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::{database, jwt, keys::KeyStore};
+    use crate::controller::{refresh_tokens, revoked_tokens, sessions};
+    use crate::controller::users::{delete_test_user, insert_test_user};
+
+    async fn cleanup(pool: &PgPool, user_id: Uuid, email: &str) {
+        sqlx::query("DELETE FROM mail_outbox WHERE to_address = $1")
+            .bind(email)
+            .execute(pool)
+            .await
+            .unwrap();
+        delete_test_user(pool, user_id).await;
+    }
+
+    #[tokio::test]
+    async fn reset_token_is_single_use() {
+        let Some(pool) = database::test_pool().await else { return };
+        let (user_id, email) = insert_test_user(&pool).await;
+
+        let token = create_reset_token(&pool, &email).await.unwrap();
+        assert_eq!(verify_reset_token(&pool, &token).await.unwrap(), email);
+        assert_eq!(reset_password(&pool, &token, "a new password").await.unwrap(), user_id);
+
+        assert!(matches!(verify_reset_token(&pool, &token).await, Err(PasswordResetsError::InvalidToken)));
+        assert!(matches!(reset_password(&pool, &token, "another password").await, Err(PasswordResetsError::InvalidToken)));
+
+        cleanup(&pool, user_id, &email).await;
+    }
+
+    #[tokio::test]
+    async fn newer_token_replaces_the_older_one() {
+        let Some(pool) = database::test_pool().await else { return };
+        let (user_id, email) = insert_test_user(&pool).await;
+
+        let older = create_reset_token(&pool, &email).await.unwrap();
+        let newer = create_reset_token(&pool, &email).await.unwrap();
+
+        assert!(matches!(reset_password(&pool, &older, "a new password").await, Err(PasswordResetsError::InvalidToken)));
+        assert!(reset_password(&pool, &newer, "a new password").await.is_ok());
+
+        cleanup(&pool, user_id, &email).await;
+    }
+
+    #[tokio::test]
+    async fn expired_token_is_rejected() {
+        let Some(pool) = database::test_pool().await else { return };
+        let (user_id, email) = insert_test_user(&pool).await;
+
+        let token = create_reset_token(&pool, &email).await.unwrap();
+        sqlx::query("UPDATE password_reset_tokens SET expires_at = now() - interval '1 minute' WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        assert!(matches!(verify_reset_token(&pool, &token).await, Err(PasswordResetsError::Expired)));
+        assert!(matches!(reset_password(&pool, &token, "a new password").await, Err(PasswordResetsError::Expired)));
+
+        cleanup(&pool, user_id, &email).await;
+    }
+
+    #[tokio::test]
+    async fn reset_revokes_every_session() {
+        let Some(pool) = database::test_pool().await else { return };
+        let (user_id, email) = insert_test_user(&pool).await;
+
+        let tokens = jwt::ForgeOptions::new()
+            .offline(Some(true))
+            .subject(email.clone())
+            .forge(&KeyStore::ephemeral())
+            .unwrap();
+        let refresh_claims = tokens.refresh_token_claims.unwrap();
+        let family_id = refresh_tokens::insert_refresh_token(&pool, user_id, &refresh_claims).await.unwrap();
+
+        let session_id = Uuid::new_v4().to_string();
+        let device = sessions::Device { user_agent: String::from("test"), ip: String::from("127.0.0.1") };
+        sessions::start_session(&pool, &session_id, user_id, Some(family_id), &device).await.unwrap();
+
+        // signed in a while before the reset
+        let access_claims: jwt::AccessTokenClaims = serde_json::from_value(serde_json::json!({
+            "iss": "test",
+            "sub": email,
+            "aud": ["test"],
+            "azp": "test",
+            "exp": Utc::now().timestamp() + 3600,
+            "iat": Utc::now().timestamp() - 60,
+            "scope": [],
+            "jti": Uuid::new_v4().to_string(),
+        })).unwrap();
+        assert!(!revoked_tokens::is_access_token_revoked(&pool, &access_claims).await.unwrap());
+
+        let token = create_reset_token(&pool, &email).await.unwrap();
+        reset_password(&pool, &token, "a new password").await.unwrap();
+
+        assert!(revoked_tokens::is_access_token_revoked(&pool, &access_claims).await.unwrap());
+        assert!(sessions::find_active_sessions(&pool, user_id, &session_id).await.unwrap().is_empty());
+
+        let (revoked,): (bool,) = sqlx::query_as("SELECT bool_and(revoked_at IS NOT NULL) FROM refresh_tokens WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert!(revoked);
+
+        cleanup(&pool, user_id, &email).await;
+    }
+}
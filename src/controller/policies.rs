@@ -0,0 +1,845 @@
+use std::collections::HashMap;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+use sqlx::postgres::PgPool;
+use crate::controller::users;
+use crate::api::draft::access_controls::v1::{
+    AccessControlPolicy,
+    EvaluatePolicyRequest,
+    LookupObjectKey,
+    Operation,
+    Outcome,
+    PolicyDecisionTrace,
+    PolicyEvaluation,
+    PolicySetVersion,
+    SkipReason,
+    Subject,
+};
+
+/// data_type that the CHANGE_PERMISSION operation is checked against to administer policies
+pub const POLICY_DATA_TYPE: &str = "access_control_policies";
+
+#[derive(Debug)]
+pub enum PoliciesError {
+    FailedLookup,
+    FailedInsert,
+    FailedUpdate,
+    FailedDelete,
+    FailedVersion,
+    FailedTransactionCommit,
+    NotFound,
+    UnknownVersion,
+    // the policy is missing a required field, or has an invalid value
+    InvalidPolicy(&'static str),
+}
+
+/// Filters for `find_policies`, empty fields are not filtered on
+#[derive(Debug, Default)]
+pub struct PolicyFilter {
+    pub data_type: String,
+    pub subject_user_id: String,
+    pub subject_group_id: String,
+    pub operation: i32,
+}
+
+#[derive(sqlx::FromRow)]
+struct PolicyRow {
+    id: Uuid,
+    data_type: String,
+    operation: i32,
+    outcome: i32,
+    subject_user_id: String,
+    subject_group_ids: Vec<String>,
+}
+
+#[derive(sqlx::FromRow)]
+struct ObjectKeyRow {
+    policy_id: Uuid,
+    key: String,
+    value: String,
+}
+
+/// A row of `access_control_policy_versions`, the state of a policy after a change
+#[derive(sqlx::FromRow)]
+struct VersionRow {
+    version: i64,
+    change: String,
+    policy_id: Uuid,
+    deleted: bool,
+    data_type: String,
+    operation: i32,
+    outcome: i32,
+    subject_user_id: String,
+    subject_group_ids: Vec<String>,
+    object_keys: Vec<String>,
+    object_values: Vec<String>,
+    changed_by: String,
+    created_at: DateTime<Utc>,
+}
+
+impl VersionRow {
+    fn policy(&self) -> Option<AccessControlPolicy> {
+        if self.deleted {
+            return None
+        }
+
+        Some(AccessControlPolicy {
+            id: self.policy_id.to_string(),
+            lookup_object_key: self.object_keys.iter().zip(self.object_values.iter()).map(|(k, v)| LookupObjectKey {
+                key: k.clone(),
+                value: v.clone(),
+            }).collect(),
+            data_type: self.data_type.clone(),
+            operation: self.operation,
+            subject: Some(Subject {
+                user_id: self.subject_user_id.clone(),
+                group_ids: self.subject_group_ids.clone(),
+            }),
+            outcome: self.outcome,
+        })
+    }
+}
+
+/// Load the policies that match the filter, with their subjects and object key matchers
+pub async fn find_policies(
+    pool: &PgPool,
+    filter: &PolicyFilter,
+) -> Result<Vec<AccessControlPolicy>, PoliciesError> {
+    let rows = match sqlx::query_as::<_, PolicyRow>("SELECT id, data_type, operation, outcome, subject_user_id, subject_group_ids FROM access_control_policies WHERE ($1 = '' OR data_type = $1) AND ($2 = '' OR subject_user_id = $2) AND ($3 = '' OR $3 = ANY(subject_group_ids)) AND ($4 = 0 OR operation = $4) ORDER BY created_at")
+        .bind(&filter.data_type)
+        .bind(&filter.subject_user_id)
+        .bind(&filter.subject_group_id)
+        .bind(filter.operation)
+        .fetch_all(pool)
+        .await {
+            Ok(v) => v,
+            Err(e) => {
+                println!("{}", e);
+                return Err(PoliciesError::FailedLookup)
+            }
+        };
+
+    let ids: Vec<Uuid> = rows.iter().map(|r| r.id).collect();
+    let mut object_keys = find_object_keys(pool, &ids).await?;
+
+    Ok(rows.into_iter().map(|row| AccessControlPolicy {
+        id: row.id.to_string(),
+        lookup_object_key: object_keys.remove(&row.id).unwrap_or_default(),
+        data_type: row.data_type,
+        operation: row.operation,
+        subject: Some(Subject {
+            user_id: row.subject_user_id,
+            group_ids: row.subject_group_ids,
+        }),
+        outcome: row.outcome,
+    }).collect())
+}
+
+async fn find_object_keys(
+    pool: &PgPool,
+    policy_ids: &[Uuid],
+) -> Result<HashMap<Uuid, Vec<LookupObjectKey>>, PoliciesError> {
+    let rows = match sqlx::query_as::<_, ObjectKeyRow>("SELECT policy_id, key, value FROM access_control_policy_object_keys WHERE policy_id = ANY($1) ORDER BY key")
+        .bind(policy_ids)
+        .fetch_all(pool)
+        .await {
+            Ok(v) => v,
+            Err(e) => {
+                println!("{}", e);
+                return Err(PoliciesError::FailedLookup)
+            }
+        };
+
+    let mut keys: HashMap<Uuid, Vec<LookupObjectKey>> = HashMap::new();
+    for row in rows {
+        keys.entry(row.policy_id).or_default().push(LookupObjectKey {
+            key: row.key,
+            value: row.value,
+        });
+    }
+
+    Ok(keys)
+}
+
+/// Policy decision point. Loads the policies for the requested `data_type` and `operation`,
+/// keeps the ones that apply to the subject and object, and combines their outcomes.
+pub async fn evaluate_policy(
+    pool: &PgPool,
+    req: &EvaluatePolicyRequest,
+) -> Result<Outcome, PoliciesError> {
+    let filter = PolicyFilter {
+        data_type: req.data_type.clone(),
+        operation: req.operation,
+        ..Default::default()
+    };
+    let policies = find_policies(pool, &filter).await?;
+
+    Ok(decide(&policies, req))
+}
+
+/// The outcome of the policies that apply to the subject and object, combined with deny-overrides
+pub fn decide(policies: &[AccessControlPolicy], req: &EvaluatePolicyRequest) -> Outcome {
+    deny_overrides(policies
+        .iter()
+        .filter(|policy| applies_to_subject(policy, req) && applies_to_object(policy, req))
+        .map(|policy| policy.outcome()))
+}
+
+/// Same decision as `evaluate_policy`, with a trace of every policy that was loaded, why the
+/// ones that didn't apply were skipped, and how the outcomes were combined.
+pub async fn explain_policy(
+    pool: &PgPool,
+    req: &EvaluatePolicyRequest,
+) -> Result<(Outcome, PolicyDecisionTrace), PoliciesError> {
+    let filter = PolicyFilter {
+        data_type: req.data_type.clone(),
+        operation: req.operation,
+        ..Default::default()
+    };
+    let policies = find_policies(pool, &filter).await?;
+
+    Ok(explain(policies, req))
+}
+
+/// `decide` with the trace of how every policy was evaluated
+pub fn explain(policies: Vec<AccessControlPolicy>, req: &EvaluatePolicyRequest) -> (Outcome, PolicyDecisionTrace) {
+    let evaluations: Vec<PolicyEvaluation> = policies.into_iter().map(|policy| {
+        let (skip_reason, detail) = if !applies_to_subject(&policy, req) {
+            (SkipReason::SubjectMismatch, subject_mismatch(&policy, req))
+        } else if !applies_to_object(&policy, req) {
+            (SkipReason::ObjectMismatch, object_mismatch(&policy, req))
+        } else {
+            (SkipReason::Unspecified, format!("matched, outcome {}", policy.outcome().as_str_name()))
+        };
+
+        PolicyEvaluation {
+            matched: skip_reason == SkipReason::Unspecified,
+            skip_reason: skip_reason as i32,
+            detail,
+            policy: Some(policy),
+        }
+    }).collect();
+
+    let outcomes: Vec<Outcome> = evaluations.iter()
+        .filter(|e| e.matched)
+        .filter_map(|e| e.policy.as_ref().map(|p| p.outcome()))
+        .collect();
+    let outcome = deny_overrides(outcomes.iter().copied());
+
+    let allowed = outcomes.iter().filter(|o| **o == Outcome::Allowed).count();
+    let denied = outcomes.iter().filter(|o| **o == Outcome::Denied).count();
+    let combining_step = match (allowed, denied) {
+        (0, 0) => String::from("no policy matched, DENIED by default"),
+        (_, 0) => format!("{} ALLOWED and no DENIED, ALLOWED", allowed),
+        (_, _) => format!("{} DENIED overrides {} ALLOWED, DENIED", denied, allowed),
+    };
+
+    (outcome, PolicyDecisionTrace {
+        policies: evaluations,
+        combining_algorithm: String::from("deny-overrides"),
+        combining_step,
+    })
+}
+
+fn subject_mismatch(policy: &AccessControlPolicy, req: &EvaluatePolicyRequest) -> String {
+    let policy_subject = policy.subject.clone().unwrap_or_default();
+    let subject = req.subject.clone().unwrap_or_default();
+
+    format!(
+        "policy subject user {:?} groups {:?} does not match user {:?} groups {:?} role {:?}",
+        policy_subject.user_id, policy_subject.group_ids, subject.user_id, subject.group_ids, req.role,
+    )
+}
+
+fn object_mismatch(policy: &AccessControlPolicy, req: &EvaluatePolicyRequest) -> String {
+    let missing: Vec<String> = policy.lookup_object_key.iter()
+        .filter(|matcher| !matches_object_key(matcher, req))
+        .map(|matcher| {
+            match req.lookup_object_key.iter().find(|k| k.key == matcher.key) {
+                Some(k) => format!("{} is {:?}, policy requires {:?}", matcher.key, k.value, matcher.value),
+                None => format!("{} is missing from the request", matcher.key),
+            }
+        })
+        .collect();
+
+    missing.join(", ")
+}
+
+/// Is the subject allowed to administer policies
+pub async fn can_change_permission(pool: &PgPool, subject: Subject) -> Result<bool, PoliciesError> {
+    let req = EvaluatePolicyRequest {
+        data_type: String::from(POLICY_DATA_TYPE),
+        operation: Operation::ChangePermission as i32,
+        subject: Some(subject),
+        ..Default::default()
+    };
+
+    Ok(evaluate_policy(pool, &req).await? == Outcome::Allowed)
+}
+
+/// Subject of a signed in user, their email is the user id and their roles are their groups
+pub async fn subject_for_user(pool: &PgPool, email: &str) -> Result<Subject, PoliciesError> {
+    match users::find_role_names(pool, email).await {
+        Ok(group_ids) => Ok(Subject {
+            user_id: String::from(email),
+            group_ids,
+        }),
+        Err(e) => {
+            println!("{:?}", e);
+            Err(PoliciesError::FailedLookup)
+        }
+    }
+}
+
+/// A policy applies when its user matches the subject, or one of its groups is a group of
+/// the subject or the requested role. A policy without a user or groups applies to everyone.
+pub fn applies_to_subject(policy: &AccessControlPolicy, req: &EvaluatePolicyRequest) -> bool {
+    let policy_subject = match &policy.subject {
+        Some(s) if !s.user_id.is_empty() || !s.group_ids.is_empty() => s,
+        _ => return true,
+    };
+
+    let empty = Subject::default();
+    let subject = req.subject.as_ref().unwrap_or(&empty);
+
+    if !policy_subject.user_id.is_empty() && policy_subject.user_id == subject.user_id {
+        return true
+    }
+
+    policy_subject.group_ids.iter().any(|g| {
+        subject.group_ids.contains(g) || (!req.role.is_empty() && g == &req.role)
+    })
+}
+
+/// Every `LookupObjectKey` of the policy has to be one of the object keys of the request.
+/// A value of `*` matches any value for the key, and a policy without keys matches every object.
+pub fn applies_to_object(policy: &AccessControlPolicy, req: &EvaluatePolicyRequest) -> bool {
+    policy.lookup_object_key.iter().all(|matcher| matches_object_key(matcher, req))
+}
+
+// one `LookupObjectKey` of a policy against the object keys of the request, explain reports
+// the ones that fail it
+fn matches_object_key(matcher: &LookupObjectKey, req: &EvaluatePolicyRequest) -> bool {
+    req.lookup_object_key.iter().any(|k| {
+        k.key == matcher.key && (matcher.value == "*" || k.value == matcher.value)
+    })
+}
+
+/// Deny-overrides combining, a single DENIED wins over any number of ALLOWED.
+/// When no policy applies the request is DENIED.
+pub fn deny_overrides(outcomes: impl Iterator<Item = Outcome>) -> Outcome {
+    let mut allowed = false;
+
+    for outcome in outcomes {
+        match outcome {
+            Outcome::Denied => return Outcome::Denied,
+            Outcome::Allowed => allowed = true,
+            Outcome::Unspecifield => {},
+        }
+    }
+
+    if allowed { Outcome::Allowed } else { Outcome::Denied }
+}
+
+/// Check the required fields of a policy, and put it in the form it's stored in so
+/// versions of the same policy can be compared
+fn normalize_policy(mut policy: AccessControlPolicy) -> Result<AccessControlPolicy, PoliciesError> {
+    if policy.data_type.is_empty() {
+        return Err(PoliciesError::InvalidPolicy("data_type"))
+    }
+
+    match Operation::from_i32(policy.operation) {
+        None | Some(Operation::DataOperationUnspecified) => return Err(PoliciesError::InvalidPolicy("operation")),
+        Some(_v) => {},
+    }
+
+    match Outcome::from_i32(policy.outcome) {
+        Some(Outcome::Allowed) | Some(Outcome::Denied) => {},
+        _ => return Err(PoliciesError::InvalidPolicy("outcome")),
+    }
+
+    policy.lookup_object_key.sort_by(|a, b| a.key.cmp(&b.key));
+    for (i, k) in policy.lookup_object_key.iter().enumerate() {
+        if k.key.is_empty() {
+            return Err(PoliciesError::InvalidPolicy("lookup_object_key"))
+        }
+        if i > 0 && policy.lookup_object_key[i - 1].key == k.key {
+            return Err(PoliciesError::InvalidPolicy("lookup_object_key"))
+        }
+    }
+
+    if policy.subject.is_none() {
+        policy.subject = Some(Subject::default());
+    }
+
+    Ok(policy)
+}
+
+fn parse_id(id: &str) -> Result<Uuid, PoliciesError> {
+    Uuid::parse_str(id).map_err(|_e| PoliciesError::NotFound)
+}
+
+/// Current version of the policy set, 0 when nothing has been recorded
+pub async fn current_version(pool: &PgPool) -> Result<i64, PoliciesError> {
+    match sqlx::query_as::<_, (i64,)>("SELECT COALESCE(MAX(version), 0) FROM access_control_policy_versions")
+        .fetch_one(pool)
+        .await {
+            Ok(v) => Ok(v.0),
+            Err(e) => {
+                println!("{}", e);
+                Err(PoliciesError::FailedLookup)
+            }
+        }
+}
+
+/// Create a new policy, returning it with its generated id and the version that recorded it
+pub async fn create_policy(
+    pool: &PgPool,
+    policy: AccessControlPolicy,
+    changed_by: &str,
+) -> Result<(AccessControlPolicy, i64), PoliciesError> {
+    let mut policy = normalize_policy(policy)?;
+    policy.id = Uuid::new_v4().to_string();
+
+    let mut tx = pool.begin().await.map_err(|_e| PoliciesError::FailedInsert)?;
+
+    let result = async {
+        lock_versions_tx(&mut tx).await?;
+        insert_policy_tx(&mut tx, &policy).await?;
+        record_version_tx(&mut tx, "create", parse_id(&policy.id)?, Some(&policy), changed_by).await
+    }.await;
+
+    finish_tx(tx, result).await.map(|version| (policy, version))
+}
+
+/// Replace the policy with the same id
+pub async fn update_policy(
+    pool: &PgPool,
+    policy: AccessControlPolicy,
+    changed_by: &str,
+) -> Result<(AccessControlPolicy, i64), PoliciesError> {
+    let policy = normalize_policy(policy)?;
+    let id = parse_id(&policy.id)?;
+
+    let mut tx = pool.begin().await.map_err(|_e| PoliciesError::FailedUpdate)?;
+
+    let result = async {
+        lock_versions_tx(&mut tx).await?;
+        update_policy_tx(&mut tx, &policy).await?;
+        record_version_tx(&mut tx, "update", id, Some(&policy), changed_by).await
+    }.await;
+
+    finish_tx(tx, result).await.map(|version| (policy, version))
+}
+
+/// Delete a policy, returning the version that recorded it
+pub async fn delete_policy(
+    pool: &PgPool,
+    id: &str,
+    changed_by: &str,
+) -> Result<i64, PoliciesError> {
+    let id = parse_id(id)?;
+
+    let mut tx = pool.begin().await.map_err(|_e| PoliciesError::FailedDelete)?;
+
+    let result = async {
+        lock_versions_tx(&mut tx).await?;
+        if !delete_policy_tx(&mut tx, id).await? {
+            return Err(PoliciesError::NotFound)
+        }
+        record_version_tx(&mut tx, "delete", id, None, changed_by).await
+    }.await;
+
+    finish_tx(tx, result).await
+}
+
+/// Every recorded version of the policy set, newest first
+pub async fn find_versions(
+    pool: &PgPool,
+    policy_id: Option<&str>,
+) -> Result<Vec<PolicySetVersion>, PoliciesError> {
+    let policy_id = match policy_id {
+        Some(id) => Some(parse_id(id)?),
+        None => None,
+    };
+
+    let rows = match sqlx::query_as::<_, VersionRow>("SELECT * FROM access_control_policy_versions WHERE ($1::uuid IS NULL OR policy_id = $1) ORDER BY version DESC")
+        .bind(policy_id)
+        .fetch_all(pool)
+        .await {
+            Ok(v) => v,
+            Err(e) => {
+                println!("{}", e);
+                return Err(PoliciesError::FailedLookup)
+            }
+        };
+
+    Ok(rows.into_iter().map(|row| PolicySetVersion {
+        version: row.version,
+        change: row.change.clone(),
+        policy_id: row.policy_id.to_string(),
+        policy: row.policy(),
+        changed_by: row.changed_by.clone(),
+        created_at: row.created_at.to_rfc3339(),
+    }).collect())
+}
+
+/// Restore every policy to its state at `version`. Each policy that changes is recorded as a
+/// `rollback`, so the rollback itself can be rolled back. Returns the new current version.
+pub async fn rollback_policies(
+    pool: &PgPool,
+    version: i64,
+    changed_by: &str,
+) -> Result<i64, PoliciesError> {
+    let mut tx = pool.begin().await.map_err(|_e| PoliciesError::FailedUpdate)?;
+
+    let result = async {
+        lock_versions_tx(&mut tx).await?;
+
+        let exists = sqlx::query_as::<_, (i64,)>("SELECT version FROM access_control_policy_versions WHERE version = $1")
+            .bind(version)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(|_e| PoliciesError::FailedLookup)?;
+        if exists.is_none() {
+            return Err(PoliciesError::UnknownVersion)
+        }
+
+        // state of every policy at the requested version, and as it is now
+        let target = latest_versions_tx(&mut tx, Some(version)).await?;
+        let current = latest_versions_tx(&mut tx, None).await?;
+
+        let mut latest = sqlx::query_as::<_, (i64,)>("SELECT COALESCE(MAX(version), 0) FROM access_control_policy_versions")
+            .fetch_one(&mut *tx)
+            .await
+            .map_err(|_e| PoliciesError::FailedLookup)?
+            .0;
+
+        for (policy_id, row) in current.iter() {
+            let wanted = target.get(policy_id).and_then(|r| r.policy());
+            if wanted == row.policy() {
+                continue
+            }
+
+            delete_policy_tx(&mut tx, *policy_id).await?;
+            if let Some(policy) = &wanted {
+                insert_policy_tx(&mut tx, policy).await?;
+            }
+
+            latest = record_version_tx(&mut tx, "rollback", *policy_id, wanted.as_ref(), changed_by).await?;
+        }
+
+        Ok(latest)
+    }.await;
+
+    finish_tx(tx, result).await
+}
+
+// commit when the work succeeded, roll back otherwise
+async fn finish_tx<T>(
+    tx: sqlx::Transaction<'_, sqlx::Postgres>,
+    result: Result<T, PoliciesError>,
+) -> Result<T, PoliciesError> {
+    match result {
+        Ok(v) => match tx.commit().await {
+            Ok(_v) => Ok(v),
+            Err(_e) => Err(PoliciesError::FailedTransactionCommit),
+        },
+        Err(e) => {
+            let _e = tx.rollback().await;
+            Err(e)
+        }
+    }
+}
+
+// serialize writers so versions are recorded in the order the changes were made
+async fn lock_versions_tx(tx: &mut sqlx::Transaction<'_, sqlx::Postgres>) -> Result<(), PoliciesError> {
+    match sqlx::query("LOCK TABLE access_control_policy_versions IN SHARE ROW EXCLUSIVE MODE")
+        .execute(&mut **tx)
+        .await {
+            Ok(_v) => Ok(()),
+            Err(e) => {
+                println!("{}", e);
+                Err(PoliciesError::FailedVersion)
+            }
+        }
+}
+
+// latest recorded state of every policy, up to and including `version` when it's set
+async fn latest_versions_tx(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    version: Option<i64>,
+) -> Result<HashMap<Uuid, VersionRow>, PoliciesError> {
+    let rows = match sqlx::query_as::<_, VersionRow>("SELECT DISTINCT ON (policy_id) * FROM access_control_policy_versions WHERE ($1::bigint IS NULL OR version <= $1) ORDER BY policy_id, version DESC")
+        .bind(version)
+        .fetch_all(&mut **tx)
+        .await {
+            Ok(v) => v,
+            Err(e) => {
+                println!("{}", e);
+                return Err(PoliciesError::FailedLookup)
+            }
+        };
+
+    Ok(rows.into_iter().map(|r| (r.policy_id, r)).collect())
+}
+
+async fn insert_policy_tx(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    policy: &AccessControlPolicy,
+) -> Result<(), PoliciesError> {
+    let id = parse_id(&policy.id)?;
+    let subject = policy.subject.clone().unwrap_or_default();
+
+    match sqlx::query("INSERT INTO access_control_policies (id, data_type, operation, outcome, subject_user_id, subject_group_ids) VALUES ($1, $2, $3, $4, $5, $6)")
+        .bind(id)
+        .bind(&policy.data_type)
+        .bind(policy.operation)
+        .bind(policy.outcome)
+        .bind(&subject.user_id)
+        .bind(&subject.group_ids)
+        .execute(&mut **tx)
+        .await {
+            Ok(_v) => {},
+            Err(e) => {
+                println!("{}", e);
+                return Err(PoliciesError::FailedInsert)
+            }
+        }
+
+    insert_object_keys_tx(tx, id, &policy.lookup_object_key).await
+}
+
+async fn update_policy_tx(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    policy: &AccessControlPolicy,
+) -> Result<(), PoliciesError> {
+    let id = parse_id(&policy.id)?;
+    let subject = policy.subject.clone().unwrap_or_default();
+
+    match sqlx::query("UPDATE access_control_policies SET data_type = $2, operation = $3, outcome = $4, subject_user_id = $5, subject_group_ids = $6 WHERE id = $1")
+        .bind(id)
+        .bind(&policy.data_type)
+        .bind(policy.operation)
+        .bind(policy.outcome)
+        .bind(&subject.user_id)
+        .bind(&subject.group_ids)
+        .execute(&mut **tx)
+        .await {
+            Ok(v) if v.rows_affected() == 0 => return Err(PoliciesError::NotFound),
+            Ok(_v) => {},
+            Err(e) => {
+                println!("{}", e);
+                return Err(PoliciesError::FailedUpdate)
+            }
+        }
+
+    match sqlx::query("DELETE FROM access_control_policy_object_keys WHERE policy_id = $1")
+        .bind(id)
+        .execute(&mut **tx)
+        .await {
+            Ok(_v) => {},
+            Err(e) => {
+                println!("{}", e);
+                return Err(PoliciesError::FailedUpdate)
+            }
+        }
+
+    insert_object_keys_tx(tx, id, &policy.lookup_object_key).await
+}
+
+async fn insert_object_keys_tx(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    policy_id: Uuid,
+    keys: &[LookupObjectKey],
+) -> Result<(), PoliciesError> {
+    for k in keys {
+        match sqlx::query("INSERT INTO access_control_policy_object_keys (policy_id, key, value) VALUES ($1, $2, $3)")
+            .bind(policy_id)
+            .bind(&k.key)
+            .bind(&k.value)
+            .execute(&mut **tx)
+            .await {
+                Ok(_v) => {},
+                Err(e) => {
+                    println!("{}", e);
+                    return Err(PoliciesError::FailedInsert)
+                }
+            }
+    }
+
+    Ok(())
+}
+
+// returns false when there was no policy to delete
+async fn delete_policy_tx(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    id: Uuid,
+) -> Result<bool, PoliciesError> {
+    match sqlx::query("DELETE FROM access_control_policies WHERE id = $1")
+        .bind(id)
+        .execute(&mut **tx)
+        .await {
+            Ok(v) => Ok(v.rows_affected() > 0),
+            Err(e) => {
+                println!("{}", e);
+                Err(PoliciesError::FailedDelete)
+            }
+        }
+}
+
+async fn record_version_tx(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    change: &str,
+    policy_id: Uuid,
+    policy: Option<&AccessControlPolicy>,
+    changed_by: &str,
+) -> Result<i64, PoliciesError> {
+    let state = policy.cloned().unwrap_or_default();
+    let subject = state.subject.clone().unwrap_or_default();
+    let keys: Vec<String> = state.lookup_object_key.iter().map(|k| k.key.clone()).collect();
+    let values: Vec<String> = state.lookup_object_key.iter().map(|k| k.value.clone()).collect();
+
+    match sqlx::query_as::<_, (i64,)>("INSERT INTO access_control_policy_versions (change, policy_id, deleted, data_type, operation, outcome, subject_user_id, subject_group_ids, object_keys, object_values, changed_by) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11) RETURNING version")
+        .bind(change)
+        .bind(policy_id)
+        .bind(policy.is_none())
+        .bind(&state.data_type)
+        .bind(state.operation)
+        .bind(state.outcome)
+        .bind(&subject.user_id)
+        .bind(&subject.group_ids)
+        .bind(&keys)
+        .bind(&values)
+        .bind(changed_by)
+        .fetch_one(&mut **tx)
+        .await {
+            Ok(v) => Ok(v.0),
+            Err(e) => {
+                println!("{}", e);
+                Err(PoliciesError::FailedVersion)
+            }
+        }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(id: &str, outcome: Outcome, groups: &[&str], keys: &[(&str, &str)]) -> AccessControlPolicy {
+        AccessControlPolicy {
+            id: id.to_owned(),
+            data_type: String::from("orders"),
+            operation: Operation::Read as i32,
+            outcome: outcome as i32,
+            subject: Some(Subject { user_id: String::new(), group_ids: groups.iter().map(|g| g.to_string()).collect() }),
+            lookup_object_key: keys.iter().map(|(k, v)| LookupObjectKey { key: k.to_string(), value: v.to_string() }).collect(),
+        }
+    }
+
+    fn request(user: &str, groups: &[&str], keys: &[(&str, &str)]) -> EvaluatePolicyRequest {
+        EvaluatePolicyRequest {
+            data_type: String::from("orders"),
+            operation: Operation::Read as i32,
+            subject: Some(Subject { user_id: user.to_owned(), group_ids: groups.iter().map(|g| g.to_string()).collect() }),
+            lookup_object_key: keys.iter().map(|(k, v)| LookupObjectKey { key: k.to_string(), value: v.to_string() }).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn deny_overrides_allow() {
+        assert_eq!(deny_overrides([Outcome::Allowed, Outcome::Denied, Outcome::Allowed].into_iter()), Outcome::Denied);
+        assert_eq!(deny_overrides([Outcome::Allowed, Outcome::Unspecifield].into_iter()), Outcome::Allowed);
+    }
+
+    #[test]
+    fn denied_when_nothing_applies() {
+        assert_eq!(deny_overrides(std::iter::empty()), Outcome::Denied);
+        assert_eq!(decide(&[], &request("jane", &[], &[])), Outcome::Denied);
+    }
+
+    #[test]
+    fn only_policies_that_apply_are_combined() {
+        let policies = [
+            policy("allow-drivers", Outcome::Allowed, &["drivers"], &[]),
+            // applies to another group, it doesn't deny drivers
+            policy("deny-guests", Outcome::Denied, &["guests"], &[]),
+        ];
+
+        assert_eq!(decide(&policies, &request("jane", &["drivers"], &[])), Outcome::Allowed);
+        assert_eq!(decide(&policies, &request("joe", &["guests", "drivers"], &[])), Outcome::Denied);
+        assert_eq!(decide(&policies, &request("ann", &["admins"], &[])), Outcome::Denied);
+    }
+
+    #[test]
+    fn subject_matches_user_group_or_role() {
+        let mut by_user = policy("jane", Outcome::Allowed, &[], &[]);
+        by_user.subject = Some(Subject { user_id: String::from("jane"), group_ids: vec![] });
+        assert!(applies_to_subject(&by_user, &request("jane", &[], &[])));
+        assert!(!applies_to_subject(&by_user, &request("joe", &[], &[])));
+
+        let by_group = policy("drivers", Outcome::Allowed, &["drivers"], &[]);
+        let mut with_role = request("joe", &[], &[]);
+        with_role.role = String::from("drivers");
+        assert!(applies_to_subject(&by_group, &with_role));
+
+        let everyone = policy("everyone", Outcome::Allowed, &[], &[]);
+        assert!(applies_to_subject(&everyone, &request("", &[], &[])));
+    }
+
+    #[test]
+    fn object_keys_have_to_match() {
+        let policies = [policy("order-b", Outcome::Allowed, &[], &[("uuid", "b")])];
+
+        assert_eq!(decide(&policies, &request("jane", &[], &[("uuid", "b"), ("region", "eu")])), Outcome::Allowed);
+        assert_eq!(decide(&policies, &request("jane", &[], &[("uuid", "c")])), Outcome::Denied);
+        assert_eq!(decide(&policies, &request("jane", &[], &[])), Outcome::Denied);
+
+        let any = [policy("any-order", Outcome::Allowed, &[], &[("uuid", "*")])];
+        assert_eq!(decide(&any, &request("jane", &[], &[("uuid", "c")])), Outcome::Allowed);
+        assert_eq!(decide(&any, &request("jane", &[], &[("region", "eu")])), Outcome::Denied);
+    }
+
+    #[test]
+    fn explain_agrees_with_decide() {
+        let policies = vec![
+            policy("allow-drivers", Outcome::Allowed, &["drivers"], &[]),
+            policy("deny-order-b", Outcome::Denied, &[], &[("uuid", "b")]),
+            policy("deny-guests", Outcome::Denied, &["guests"], &[]),
+        ];
+
+        for req in [
+            request("jane", &["drivers"], &[("uuid", "a")]),
+            request("jane", &["drivers"], &[("uuid", "b")]),
+            request("joe", &["guests"], &[]),
+            request("ann", &[], &[]),
+        ] {
+            let (outcome, trace) = explain(policies.clone(), &req);
+            assert_eq!(outcome, decide(&policies, &req));
+            assert_eq!(trace.combining_algorithm, "deny-overrides");
+            assert_eq!(trace.policies.len(), policies.len());
+        }
+    }
+
+    #[test]
+    fn explain_says_why_a_policy_was_skipped() {
+        let policies = vec![
+            policy("deny-guests", Outcome::Denied, &["guests"], &[]),
+            policy("allow-order-b", Outcome::Allowed, &[], &[("uuid", "b")]),
+            policy("allow-drivers", Outcome::Allowed, &["drivers"], &[]),
+        ];
+
+        let (outcome, trace) = explain(policies, &request("jane", &["drivers"], &[("uuid", "c")]));
+        assert_eq!(outcome, Outcome::Allowed);
+
+        let reasons: Vec<(bool, SkipReason)> = trace.policies.iter().map(|e| (e.matched, e.skip_reason())).collect();
+        assert_eq!(reasons, vec![
+            (false, SkipReason::SubjectMismatch),
+            (false, SkipReason::ObjectMismatch),
+            (true, SkipReason::Unspecified),
+        ]);
+        assert_eq!(trace.policies[1].detail, "uuid is \"c\", policy requires \"b\"");
+        assert_eq!(trace.combining_step, "1 ALLOWED and no DENIED, ALLOWED");
+    }
+}
@@ -0,0 +1,436 @@
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use uuid::Uuid;
+use sqlx::postgres::PgPool;
+use crate::common::{jwt, keys::KeyStore};
+use crate::controller::users;
+
+/// how long a rotated token can still be presented, concurrent requests that all read the
+/// token before the first one rotated it get the child it was rotated to
+const ROTATION_GRACE_SECONDS: i64 = 30;
+
+#[derive(Debug)]
+pub enum RefreshTokensError {
+    FailedInsert,
+    FailedLookup,
+    FailedRetire,
+    FailedRevoke,
+    FailedForge,
+    FailedTransactionCommit,
+    NotFound,
+    Expired,
+    Revoked,
+    // a token that was already rotated was presented again, the whole family has been revoked
+    Reused,
+}
+
+/// A row of the `refresh_tokens` table. Every refresh token belongs to a family that
+/// starts at login, each rotation adds a child that points at the token it replaced.
+#[derive(sqlx::FromRow, Debug)]
+pub struct RefreshToken {
+    pub id: Uuid,
+    pub family_id: Uuid,
+    pub user_id: Uuid,
+    pub client_id: String,
+    pub expires_at: DateTime<Utc>,
+    // set once the token has been exchanged for a new pair
+    pub retired_at: Option<DateTime<Utc>>,
+    // set when the family is revoked, the token can never be used again
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+/// Save the refresh token forged at login as the first token of a new family
+pub async fn insert_refresh_token(
+    pool: &PgPool,
+    user_id: Uuid,
+    claims: &jwt::RefreshTokenClaims,
+) -> Result<Uuid, RefreshTokensError> {
+    let family_id = Uuid::new_v4();
+    let mut tx = pool.begin().await.map_err(|_e| RefreshTokensError::FailedInsert)?;
+
+    if let Err(e) = save_refresh_token_tx(&mut tx, user_id, family_id, None, claims).await {
+        let _e = tx.rollback().await;
+        return Err(e)
+    }
+
+    match tx.commit().await {
+        Ok(_v) => Ok(family_id),
+        Err(_e) => Err(RefreshTokensError::FailedTransactionCommit),
+    }
+}
+
+pub async fn save_refresh_token_tx(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    user_id: Uuid,
+    family_id: Uuid,
+    parent_id: Option<Uuid>,
+    claims: &jwt::RefreshTokenClaims,
+) -> Result<(), RefreshTokensError> {
+    let id = parse_jti(claims)?;
+    let expires_at = Utc.timestamp_opt(claims.exp, 0)
+        .single()
+        .ok_or(RefreshTokensError::FailedInsert)?;
+
+    match sqlx::query("INSERT INTO refresh_tokens (id, family_id, parent_id, user_id, client_id, expires_at) VALUES ($1, $2, $3, $4, $5, $6)")
+        .bind(id)
+        .bind(family_id)
+        .bind(parent_id)
+        .bind(user_id)
+        .bind(&claims.client_id)
+        .bind(expires_at)
+        .execute(&mut **tx)
+        .await {
+            Ok(_v) => Ok(()),
+            Err(e) => {
+                println!("{}", e);
+                Err(RefreshTokensError::FailedInsert)
+            }
+        }
+}
+
+/// Check a decoded refresh token against the table. Presenting a token that was already
+/// rotated means it has leaked, so the family it belongs to is revoked. Within
+/// `ROTATION_GRACE_SECONDS` of the rotation the child it was rotated to is returned instead.
+pub async fn verify_refresh_token(
+    pool: &PgPool,
+    claims: &jwt::RefreshTokenClaims,
+) -> Result<RefreshToken, RefreshTokensError> {
+    let mut tx = pool.begin().await.map_err(|_e| RefreshTokensError::FailedLookup)?;
+
+    let result = verify_refresh_token_tx(&mut tx, claims).await;
+
+    match tx.commit().await {
+        Ok(_v) => result,
+        Err(_e) => Err(RefreshTokensError::FailedTransactionCommit),
+    }
+}
+
+async fn verify_refresh_token_tx(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    claims: &jwt::RefreshTokenClaims,
+) -> Result<RefreshToken, RefreshTokensError> {
+    let id = parse_jti(claims)?;
+
+    // lock the row so two concurrent refreshes can't both rotate the same token
+    let token = match sqlx::query_as::<_, RefreshToken>("SELECT id, family_id, user_id, client_id, expires_at, retired_at, revoked_at FROM refresh_tokens WHERE id = $1 FOR UPDATE")
+        .bind(id)
+        .fetch_optional(&mut **tx)
+        .await {
+            Ok(Some(v)) => v,
+            Ok(None) => return Err(RefreshTokensError::NotFound),
+            Err(e) => {
+                println!("{}", e);
+                return Err(RefreshTokensError::FailedLookup)
+            }
+        };
+
+    if token.client_id != claims.client_id {
+        return Err(RefreshTokensError::NotFound)
+    }
+
+    if token.revoked_at.is_some() {
+        return Err(RefreshTokensError::Revoked)
+    }
+
+    if let Some(retired_at) = token.retired_at {
+        if retired_at > Utc::now() - Duration::seconds(ROTATION_GRACE_SECONDS) {
+            if let Some(child) = find_current_child_tx(tx, token.id).await? {
+                println!("refresh token {} was rotated {}s ago, returning its child {}", token.id, (Utc::now() - retired_at).num_seconds(), child.id);
+                return Ok(child)
+            }
+        }
+
+        println!("refresh token {} was reused, revoking family {}", token.id, token.family_id);
+        revoke_family_tx(tx, token.family_id).await?;
+        return Err(RefreshTokensError::Reused)
+    }
+
+    if token.expires_at < Utc::now() {
+        return Err(RefreshTokensError::Expired)
+    }
+
+    Ok(token)
+}
+
+// the token a retired token was rotated to, as long as it hasn't been rotated itself
+async fn find_current_child_tx(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    parent_id: Uuid,
+) -> Result<Option<RefreshToken>, RefreshTokensError> {
+    match sqlx::query_as::<_, RefreshToken>("SELECT id, family_id, user_id, client_id, expires_at, retired_at, revoked_at FROM refresh_tokens WHERE parent_id = $1 AND retired_at IS NULL AND revoked_at IS NULL AND expires_at > now() FOR UPDATE")
+        .bind(parent_id)
+        .fetch_optional(&mut **tx)
+        .await {
+            Ok(v) => Ok(v),
+            Err(e) => {
+                println!("{}", e);
+                Err(RefreshTokensError::FailedLookup)
+            }
+        }
+}
+
+/// Exchange a refresh token for a new access, id and refresh token. The presented token
+/// is retired, and the new refresh token joins the same family. A token that was rotated
+/// within the grace period gets the refresh token it was already rotated to, signed again.
+pub async fn rotate_refresh_token(
+    pool: &PgPool,
+    keys: &KeyStore,
+    claims: &jwt::RefreshTokenClaims,
+) -> Result<jwt::Tokens, RefreshTokensError> {
+    let mut tx = pool.begin().await.map_err(|_e| RefreshTokensError::FailedLookup)?;
+
+    let token = match verify_refresh_token_tx(&mut tx, claims).await {
+        Ok(v) => v,
+        Err(e) => {
+            // commit so a revoked family stays revoked
+            let _e = tx.commit().await;
+            return Err(e)
+        }
+    };
+
+    // the token was already rotated, its child is handed out again rather than retired
+    let rotated = token.id.to_string() != claims.jti;
+
+    if !rotated {
+        match sqlx::query("UPDATE refresh_tokens SET retired_at = now() WHERE id = $1")
+            .bind(token.id)
+            .execute(&mut *tx)
+            .await {
+                Ok(_v) => println!("refresh token {} retired", token.id),
+                Err(e) => {
+                    println!("{}", e);
+                    let _e = tx.rollback().await;
+                    return Err(RefreshTokensError::FailedRetire)
+                }
+            }
+    }
+
+    // scopes are looked up again, so a change to the users roles takes effect on renewal
+    let scopes = match users::find_scopes(pool, token.user_id).await {
+        Ok(v) => v,
+        Err(e) => {
+            println!("{:?}", e);
+            let _e = tx.rollback().await;
+            return Err(RefreshTokensError::FailedLookup)
+        }
+    };
+
+    let mut options = jwt::ForgeOptions::new()
+        .offline(Some(true))
+        .subject(claims.sub.clone())
+        .issuer(claims.iss.clone())
+        .audience(claims.aud.clone())
+        .authorized_parties(claims.client_id.clone())
+        .scopes(scopes)
+        .authentication_methods(claims.amr.clone());
+
+    if rotated {
+        options = options.reissue_refresh_token(token.id.to_string(), token.expires_at.timestamp());
+    }
+
+    let tokens = match options.forge(keys) {
+            Ok(v) => v,
+            Err(e) => {
+                println!("and error occurred when forging the tokens {:?}", e);
+                let _e = tx.rollback().await;
+                return Err(RefreshTokensError::FailedForge)
+            }
+        };
+
+    let new_claims = match &tokens.refresh_token_claims {
+        Some(v) => v,
+        None => {
+            let _e = tx.rollback().await;
+            return Err(RefreshTokensError::FailedForge)
+        }
+    };
+
+    if !rotated {
+        if let Err(e) = save_refresh_token_tx(&mut tx, token.user_id, token.family_id, Some(token.id), new_claims).await {
+            let _e = tx.rollback().await;
+            return Err(e)
+        }
+    }
+
+    match tx.commit().await {
+        Ok(_v) => Ok(tokens),
+        Err(_e) => Err(RefreshTokensError::FailedTransactionCommit),
+    }
+}
+
+/// Revoke every token in a family, used on reuse and when a session ends
+pub async fn revoke_family(pool: &PgPool, family_id: Uuid) -> Result<(), RefreshTokensError> {
+    let mut tx = pool.begin().await.map_err(|_e| RefreshTokensError::FailedRevoke)?;
+
+    revoke_family_tx(&mut tx, family_id).await?;
+
+    match tx.commit().await {
+        Ok(_v) => Ok(()),
+        Err(_e) => Err(RefreshTokensError::FailedTransactionCommit),
+    }
+}
+
+/// Revoke the family of a refresh token, so it can't be used to renew a session that ended
+pub async fn revoke_family_of(pool: &PgPool, claims: &jwt::RefreshTokenClaims) -> Result<(), RefreshTokensError> {
+    let id = parse_jti(claims)?;
+
+    let family_id = match sqlx::query_as::<_, (Uuid,)>("SELECT family_id FROM refresh_tokens WHERE id = $1")
+        .bind(id)
+        .fetch_optional(pool)
+        .await {
+            Ok(Some(v)) => v.0,
+            Ok(None) => return Err(RefreshTokensError::NotFound),
+            Err(e) => {
+                println!("{}", e);
+                return Err(RefreshTokensError::FailedLookup)
+            }
+        };
+
+    revoke_family(pool, family_id).await
+}
+
+async fn revoke_family_tx(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    family_id: Uuid,
+) -> Result<(), RefreshTokensError> {
+    match sqlx::query("UPDATE refresh_tokens SET revoked_at = now() WHERE family_id = $1 AND revoked_at IS NULL")
+        .bind(family_id)
+        .execute(&mut **tx)
+        .await {
+            Ok(_v) => Ok(()),
+            Err(e) => {
+                println!("{}", e);
+                Err(RefreshTokensError::FailedRevoke)
+            }
+        }
+}
+
+fn parse_jti(claims: &jwt::RefreshTokenClaims) -> Result<Uuid, RefreshTokensError> {
+    Uuid::parse_str(&claims.jti).map_err(|_e| RefreshTokensError::NotFound)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::{database, keys::KeyStore};
+    use crate::controller::users::{delete_test_user, insert_test_user};
+
+    // a login for the user, the first token of a new family
+    async fn login(pool: &PgPool, keys: &KeyStore, user_id: Uuid, email: &str) -> jwt::RefreshTokenClaims {
+        let tokens = jwt::ForgeOptions::new()
+            .offline(Some(true))
+            .subject(email.to_owned())
+            .authorized_parties(String::from("test"))
+            .forge(keys)
+            .unwrap();
+        let claims = tokens.refresh_token_claims.unwrap();
+
+        insert_refresh_token(pool, user_id, &claims).await.unwrap();
+        claims
+    }
+
+    async fn find(pool: &PgPool, claims: &jwt::RefreshTokenClaims) -> RefreshToken {
+        sqlx::query_as::<_, RefreshToken>("SELECT id, family_id, user_id, client_id, expires_at, retired_at, revoked_at FROM refresh_tokens WHERE id = $1")
+            .bind(parse_jti(claims).unwrap())
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    // move the rotation of the token out of the grace period
+    async fn age_rotation(pool: &PgPool, claims: &jwt::RefreshTokenClaims) {
+        sqlx::query("UPDATE refresh_tokens SET retired_at = now() - make_interval(secs => $2) WHERE id = $1")
+            .bind(parse_jti(claims).unwrap())
+            .bind((ROTATION_GRACE_SECONDS + 1) as f64)
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn rotation_retires_the_token_and_adds_a_child_to_the_family() {
+        let Some(pool) = database::test_pool().await else { return };
+        let keys = KeyStore::ephemeral();
+        let (user_id, email) = insert_test_user(&pool).await;
+
+        let parent = login(&pool, &keys, user_id, &email).await;
+        let tokens = rotate_refresh_token(&pool, &keys, &parent).await.unwrap();
+        let child = tokens.refresh_token_claims.unwrap();
+
+        assert_ne!(child.jti, parent.jti);
+        let parent_row = find(&pool, &parent).await;
+        let child_row = find(&pool, &child).await;
+        assert!(parent_row.retired_at.is_some());
+        assert!(child_row.retired_at.is_none());
+        assert_eq!(child_row.family_id, parent_row.family_id);
+        assert!(verify_refresh_token(&pool, &child).await.is_ok());
+
+        delete_test_user(&pool, user_id).await;
+    }
+
+    #[tokio::test]
+    async fn reuse_within_the_grace_period_returns_the_child() {
+        let Some(pool) = database::test_pool().await else { return };
+        let keys = KeyStore::ephemeral();
+        let (user_id, email) = insert_test_user(&pool).await;
+
+        let parent = login(&pool, &keys, user_id, &email).await;
+        let first = rotate_refresh_token(&pool, &keys, &parent).await.unwrap();
+        let second = rotate_refresh_token(&pool, &keys, &parent).await.unwrap();
+
+        let child = first.refresh_token_claims.unwrap();
+        assert_eq!(second.refresh_token_claims.unwrap().jti, child.jti);
+        assert!(find(&pool, &child).await.revoked_at.is_none());
+        assert!(verify_refresh_token(&pool, &child).await.is_ok());
+
+        delete_test_user(&pool, user_id).await;
+    }
+
+    #[tokio::test]
+    async fn reuse_after_the_grace_period_revokes_the_family() {
+        let Some(pool) = database::test_pool().await else { return };
+        let keys = KeyStore::ephemeral();
+        let (user_id, email) = insert_test_user(&pool).await;
+
+        let parent = login(&pool, &keys, user_id, &email).await;
+        let child = rotate_refresh_token(&pool, &keys, &parent).await.unwrap().refresh_token_claims.unwrap();
+        age_rotation(&pool, &parent).await;
+
+        assert!(matches!(rotate_refresh_token(&pool, &keys, &parent).await, Err(RefreshTokensError::Reused)));
+        assert!(find(&pool, &child).await.revoked_at.is_some());
+        assert!(matches!(verify_refresh_token(&pool, &child).await, Err(RefreshTokensError::Revoked)));
+
+        delete_test_user(&pool, user_id).await;
+    }
+
+    #[tokio::test]
+    async fn reuse_of_a_token_whose_child_was_rotated_revokes_the_family() {
+        let Some(pool) = database::test_pool().await else { return };
+        let keys = KeyStore::ephemeral();
+        let (user_id, email) = insert_test_user(&pool).await;
+
+        let parent = login(&pool, &keys, user_id, &email).await;
+        let child = rotate_refresh_token(&pool, &keys, &parent).await.unwrap().refresh_token_claims.unwrap();
+        let grandchild = rotate_refresh_token(&pool, &keys, &child).await.unwrap().refresh_token_claims.unwrap();
+
+        assert!(matches!(rotate_refresh_token(&pool, &keys, &parent).await, Err(RefreshTokensError::Reused)));
+        assert!(matches!(verify_refresh_token(&pool, &grandchild).await, Err(RefreshTokensError::Revoked)));
+
+        delete_test_user(&pool, user_id).await;
+    }
+
+    #[tokio::test]
+    async fn logout_revokes_the_family_of_the_refresh_token() {
+        let Some(pool) = database::test_pool().await else { return };
+        let keys = KeyStore::ephemeral();
+        let (user_id, email) = insert_test_user(&pool).await;
+
+        let parent = login(&pool, &keys, user_id, &email).await;
+        let child = rotate_refresh_token(&pool, &keys, &parent).await.unwrap().refresh_token_claims.unwrap();
+        revoke_family_of(&pool, &child).await.unwrap();
+
+        assert!(matches!(verify_refresh_token(&pool, &child).await, Err(RefreshTokensError::Revoked)));
+        assert!(matches!(rotate_refresh_token(&pool, &keys, &child).await, Err(RefreshTokensError::Revoked)));
+
+        delete_test_user(&pool, user_id).await;
+    }
+}
@@ -0,0 +1,147 @@
+use sqlx::postgres::PgPool;
+use crate::common::jwt;
+
+#[derive(Debug)]
+pub enum RevokedTokensError {
+    FailedInsert,
+    FailedLookup,
+}
+
+/// Revoke an access token before it expires, it's rejected by the authentication middleware
+/// from now on. Tokens forged before `jti` was added can't be revoked one by one.
+pub async fn revoke_access_token(pool: &PgPool, claims: &jwt::AccessTokenClaims) -> Result<(), RevokedTokensError> {
+    if claims.jti.is_empty() {
+        return Ok(())
+    }
+
+    match sqlx::query("INSERT INTO revoked_access_tokens (jti, user_id, expires_at) VALUES ($1, (SELECT id FROM users WHERE email = $2), to_timestamp($3)) ON CONFLICT (jti) DO NOTHING")
+        .bind(&claims.jti)
+        .bind(&claims.sub)
+        .bind(claims.exp as f64)
+        .execute(pool)
+        .await {
+            Ok(_v) => {},
+            Err(e) => {
+                println!("{}", e);
+                return Err(RevokedTokensError::FailedInsert)
+            }
+        }
+
+    // expired tokens are rejected anyway, they don't need to stay on the list
+    if let Err(e) = sqlx::query("DELETE FROM revoked_access_tokens WHERE expires_at < now()")
+        .execute(pool)
+        .await {
+            println!("failed to clean up the revoked access tokens {}", e);
+        }
+
+    Ok(())
+}
+
+/// Whether the access token was revoked, on its own at logout, or with every session of the
+/// user because they logged out everywhere or reset their password. `iat` only has whole
+/// seconds, so a token issued in the same second as the revocation is revoked with it.
+pub async fn is_access_token_revoked(pool: &PgPool, claims: &jwt::AccessTokenClaims) -> Result<bool, RevokedTokensError> {
+    match sqlx::query_as::<_, (bool, Option<bool>)>("SELECT EXISTS (SELECT 1 FROM revoked_access_tokens WHERE jti = $1), (SELECT COALESCE(to_timestamp($3) <= date_trunc('second', sessions_revoked_at), false) FROM users WHERE email = $2)")
+        .bind(&claims.jti)
+        .bind(&claims.sub)
+        .bind(claims.iat as f64)
+        .fetch_one(pool)
+        .await {
+            Ok((true, _)) => Ok(true),
+            Ok((false, Some(revoked))) => Ok(revoked),
+            // the user is gone, so is their session
+            Ok((false, None)) => Ok(true),
+            Err(e) => {
+                println!("{}", e);
+                Err(RevokedTokensError::FailedLookup)
+            }
+        }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{DateTime, Duration, TimeZone, Utc};
+    use uuid::Uuid;
+    use crate::common::database;
+    use crate::controller::users::{delete_test_user, insert_test_user};
+
+    fn claims(email: &str, jti: &str, iat: i64) -> jwt::AccessTokenClaims {
+        serde_json::from_value(serde_json::json!({
+            "iss": "test",
+            "sub": email,
+            "aud": ["test"],
+            "azp": "test",
+            "exp": iat + 3600,
+            "iat": iat,
+            "scope": [],
+            "jti": jti,
+        })).unwrap()
+    }
+
+    async fn revoke_sessions_at(pool: &PgPool, user_id: Uuid, at: DateTime<Utc>) {
+        sqlx::query("UPDATE users SET sessions_revoked_at = $2 WHERE id = $1")
+            .bind(user_id)
+            .bind(at)
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn only_the_revoked_access_token_is_rejected() {
+        let Some(pool) = database::test_pool().await else { return };
+        let (user_id, email) = insert_test_user(&pool).await;
+        let now = Utc::now().timestamp();
+
+        let revoked = claims(&email, &Uuid::new_v4().to_string(), now);
+        let other = claims(&email, &Uuid::new_v4().to_string(), now);
+        revoke_access_token(&pool, &revoked).await.unwrap();
+
+        assert!(is_access_token_revoked(&pool, &revoked).await.unwrap());
+        assert!(!is_access_token_revoked(&pool, &other).await.unwrap());
+
+        delete_test_user(&pool, user_id).await;
+    }
+
+    #[tokio::test]
+    async fn access_tokens_without_a_jti_are_not_listed() {
+        let Some(pool) = database::test_pool().await else { return };
+        let (user_id, email) = insert_test_user(&pool).await;
+
+        let token = claims(&email, "", Utc::now().timestamp());
+        revoke_access_token(&pool, &token).await.unwrap();
+
+        assert!(!is_access_token_revoked(&pool, &token).await.unwrap());
+
+        delete_test_user(&pool, user_id).await;
+    }
+
+    #[tokio::test]
+    async fn revoking_sessions_rejects_tokens_issued_up_to_the_same_second() {
+        let Some(pool) = database::test_pool().await else { return };
+        let (user_id, email) = insert_test_user(&pool).await;
+
+        // half way through a second, a token issued earlier in it has the same iat
+        let second = Utc::now().timestamp() - 60;
+        let revoked_at = Utc.timestamp_opt(second, 500_000_000).unwrap();
+        revoke_sessions_at(&pool, user_id, revoked_at).await;
+
+        let jti = Uuid::new_v4().to_string();
+        assert!(is_access_token_revoked(&pool, &claims(&email, &jti, second - 10)).await.unwrap());
+        assert!(is_access_token_revoked(&pool, &claims(&email, &jti, second)).await.unwrap());
+        assert!(!is_access_token_revoked(&pool, &claims(&email, &jti, (revoked_at + Duration::seconds(1)).timestamp())).await.unwrap());
+
+        delete_test_user(&pool, user_id).await;
+    }
+
+    #[tokio::test]
+    async fn access_tokens_of_a_deleted_user_are_revoked() {
+        let Some(pool) = database::test_pool().await else { return };
+        let (user_id, email) = insert_test_user(&pool).await;
+        delete_test_user(&pool, user_id).await;
+
+        let token = claims(&email, &Uuid::new_v4().to_string(), Utc::now().timestamp());
+        assert!(is_access_token_revoked(&pool, &token).await.unwrap());
+    }
+}
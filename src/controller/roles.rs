@@ -0,0 +1,54 @@
+use sqlx::postgres::PgPool;
+use uuid::Uuid;
+
+// named like the `Failed*` variants of the other controller errors
+#[allow(clippy::enum_variant_names)]
+#[derive(Debug)]
+pub enum RolesError {
+    FailedInsert,
+    FailedInsertUniqueName,
+    FailedLookup,
+}
+
+#[derive(sqlx::FromRow, Debug)]
+pub struct Role {
+    pub name: String,
+    pub description: String,
+    // scopes granted to every user with the role
+    pub scopes: Vec<String>,
+    // how many users have the role
+    pub users: i64,
+}
+
+/// Create a role, the name is unique
+pub async fn insert_role(pool: &PgPool, name: &str, description: &str, scopes: &[String]) -> Result<Uuid, RolesError> {
+    match sqlx::query_as::<_, (Uuid,)>("INSERT INTO roles (name, description, scopes) VALUES ($1, $2, $3) RETURNING id")
+        .bind(name)
+        .bind(description)
+        .bind(scopes)
+        .fetch_one(pool)
+        .await {
+            Ok(v) => Ok(v.0),
+            Err(err) => {
+                if let Some("roles_name_key") = err.as_database_error().and_then(|e| e.constraint()) {
+                    return Err(RolesError::FailedInsertUniqueName)
+                }
+
+                println!("{}", err);
+                Err(RolesError::FailedInsert)
+            }
+        }
+}
+
+/// Every role, with the number of users that have it
+pub async fn find_roles(pool: &PgPool) -> Result<Vec<Role>, RolesError> {
+    match sqlx::query_as::<_, Role>("SELECT roles.name, roles.description, roles.scopes, COUNT(user_roles.user_id) AS users FROM roles LEFT JOIN user_roles ON user_roles.role_id = roles.id GROUP BY roles.id ORDER BY roles.name")
+        .fetch_all(pool)
+        .await {
+            Ok(v) => Ok(v),
+            Err(e) => {
+                println!("{}", e);
+                Err(RolesError::FailedLookup)
+            }
+        }
+}
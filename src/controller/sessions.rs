@@ -0,0 +1,165 @@
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+use sqlx::postgres::PgPool;
+use crate::common::session::SESSION_LIFESPAN_HOURS;
+use crate::controller::refresh_tokens;
+
+/// last_seen_at is only written when it's older than this, not on every request
+const LAST_SEEN_RESOLUTION_SECONDS: i32 = 60;
+
+#[derive(Debug)]
+pub enum SessionsError {
+    FailedInsert,
+    FailedLookup,
+    FailedUpdate,
+    FailedRevoke,
+    NotFound,
+}
+
+/// The browser or app a user signed in from
+#[derive(Debug, Clone)]
+pub struct Device {
+    pub user_agent: String,
+    pub ip: String,
+}
+
+/// A session of a user that hasn't ended, `current` is the session of the request that
+/// listed it
+#[derive(sqlx::FromRow, Debug)]
+pub struct ActiveSession {
+    pub id: Uuid,
+    pub user_agent: String,
+    pub ip: String,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    pub current: bool,
+}
+
+#[derive(sqlx::FromRow, Debug)]
+struct SessionState {
+    revoked: bool,
+    stale: bool,
+}
+
+/// Tag the session with the user that signed in to it, and the device they signed in from.
+/// A session id that is signed in to again starts over.
+pub async fn start_session(
+    pool: &PgPool,
+    session_id: &str,
+    user_id: Uuid,
+    refresh_family_id: Option<Uuid>,
+    device: &Device,
+) -> Result<(), SessionsError> {
+    match sqlx::query("INSERT INTO user_session_details (session_id, user_id, refresh_family_id, user_agent, ip) VALUES ($1, $2, $3, $4, $5) ON CONFLICT (session_id) DO UPDATE SET id = uuid_generate_v4(), user_id = EXCLUDED.user_id, refresh_family_id = EXCLUDED.refresh_family_id, user_agent = EXCLUDED.user_agent, ip = EXCLUDED.ip, created_at = now(), last_seen_at = now(), revoked_at = NULL")
+        .bind(session_id)
+        .bind(user_id)
+        .bind(refresh_family_id)
+        .bind(&device.user_agent)
+        .bind(&device.ip)
+        .execute(pool)
+        .await {
+            Ok(_v) => Ok(()),
+            Err(e) => {
+                println!("{}", e);
+                Err(SessionsError::FailedInsert)
+            }
+        }
+}
+
+/// Record that the session was used from `ip`, and check it wasn't revoked. Sessions signed
+/// in to before they were tagged aren't known, they're let through.
+pub async fn touch_session(pool: &PgPool, session_id: &str, ip: &str) -> Result<bool, SessionsError> {
+    let state = match sqlx::query_as::<_, SessionState>("SELECT revoked_at IS NOT NULL AS revoked, (last_seen_at < now() - make_interval(secs => $2) OR ip <> $3) AS stale FROM user_session_details WHERE session_id = $1")
+        .bind(session_id)
+        .bind(LAST_SEEN_RESOLUTION_SECONDS as f64)
+        .bind(ip)
+        .fetch_optional(pool)
+        .await {
+            Ok(Some(v)) => v,
+            Ok(None) => return Ok(true),
+            Err(e) => {
+                println!("{}", e);
+                return Err(SessionsError::FailedLookup)
+            }
+        };
+
+    if state.revoked {
+        return Ok(false)
+    }
+
+    if state.stale {
+        match sqlx::query("UPDATE user_session_details SET last_seen_at = now(), ip = $2 WHERE session_id = $1")
+            .bind(session_id)
+            .bind(ip)
+            .execute(pool)
+            .await {
+                Ok(_v) => {},
+                Err(e) => {
+                    println!("{}", e);
+                    return Err(SessionsError::FailedUpdate)
+                }
+            }
+    }
+
+    Ok(true)
+}
+
+/// Sessions of the user that are still signed in, most recently used first
+pub async fn find_active_sessions(
+    pool: &PgPool,
+    user_id: Uuid,
+    current_session_id: &str,
+) -> Result<Vec<ActiveSession>, SessionsError> {
+    match sqlx::query_as::<_, ActiveSession>("SELECT id, user_agent, ip, created_at, last_seen_at, session_id = $2 AS current FROM user_session_details WHERE user_id = $1 AND revoked_at IS NULL AND last_seen_at > now() - make_interval(hours => $3) ORDER BY last_seen_at DESC")
+        .bind(user_id)
+        .bind(current_session_id)
+        .bind(SESSION_LIFESPAN_HOURS as i32)
+        .fetch_all(pool)
+        .await {
+            Ok(v) => Ok(v),
+            Err(e) => {
+                println!("{}", e);
+                Err(SessionsError::FailedLookup)
+            }
+        }
+}
+
+/// Revoke one session of the user, it's signed out on its next request and the refresh token
+/// family it was signed in with is revoked
+pub async fn revoke_session(pool: &PgPool, user_id: Uuid, id: Uuid) -> Result<(), SessionsError> {
+    let refresh_family_id = match sqlx::query_as::<_, (Option<Uuid>,)>("UPDATE user_session_details SET revoked_at = now() WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL RETURNING refresh_family_id")
+        .bind(id)
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await {
+            Ok(Some(v)) => v.0,
+            Ok(None) => return Err(SessionsError::NotFound),
+            Err(e) => {
+                println!("{}", e);
+                return Err(SessionsError::FailedRevoke)
+            }
+        };
+
+    if let Some(family_id) = refresh_family_id {
+        if let Err(e) = refresh_tokens::revoke_family(pool, family_id).await {
+            println!("failed to revoke the refresh token family {:?}", e);
+            return Err(SessionsError::FailedRevoke)
+        }
+    }
+
+    Ok(())
+}
+
+/// Forget the session once it's logged out
+pub async fn end_session(pool: &PgPool, session_id: &str) -> Result<(), SessionsError> {
+    match sqlx::query("DELETE FROM user_session_details WHERE session_id = $1")
+        .bind(session_id)
+        .execute(pool)
+        .await {
+            Ok(_v) => Ok(()),
+            Err(e) => {
+                println!("{}", e);
+                Err(SessionsError::FailedUpdate)
+            }
+        }
+}
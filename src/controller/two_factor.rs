@@ -0,0 +1,274 @@
+use rand::Rng;
+use uuid::Uuid;
+use sqlx::postgres::PgPool;
+use crate::common::{crypto, totp};
+
+/// recovery codes handed out when enrollment is confirmed
+const RECOVERY_CODE_COUNT: usize = 10;
+/// no 0/o or 1/l, so a code written down on paper reads back the same
+const RECOVERY_CODE_ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+
+#[derive(Debug)]
+pub enum TwoFactorError {
+    FailedInsert,
+    FailedLookup,
+    FailedUpdate,
+    FailedTransactionCommit,
+    // no confirmed TOTP secret, or no pending enrollment to confirm
+    NotEnrolled,
+    AlreadyEnrolled,
+    InvalidCode,
+}
+
+/// The second factor a user signed in with
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SecondFactor {
+    Totp,
+    RecoveryCode,
+}
+
+#[derive(sqlx::FromRow, Debug)]
+struct TotpCredential {
+    secret: String,
+    last_used_step: Option<i64>,
+}
+
+#[derive(sqlx::FromRow, Debug)]
+pub struct TwoFactorStatus {
+    pub enrolled: bool,
+    pub recovery_codes_left: i64,
+}
+
+/// Whether the user has to enter a second factor when they login
+pub async fn is_enrolled(pool: &PgPool, user_id: Uuid) -> Result<bool, TwoFactorError> {
+    Ok(find_status(pool, user_id).await?.enrolled)
+}
+
+pub async fn find_status(pool: &PgPool, user_id: Uuid) -> Result<TwoFactorStatus, TwoFactorError> {
+    match sqlx::query_as::<_, TwoFactorStatus>("SELECT EXISTS (SELECT 1 FROM totp_credentials WHERE user_id = $1 AND confirmed_at IS NOT NULL) AS enrolled, (SELECT count(*) FROM recovery_codes WHERE user_id = $1 AND used_at IS NULL) AS recovery_codes_left")
+        .bind(user_id)
+        .fetch_one(pool)
+        .await {
+            Ok(v) => Ok(v),
+            Err(e) => {
+                println!("{}", e);
+                Err(TwoFactorError::FailedLookup)
+            }
+        }
+}
+
+/// Start enrolling the user with a new TOTP secret, replacing an enrollment that was never
+/// confirmed. Returns the base32 secret to show the user.
+pub async fn begin_enrollment(pool: &PgPool, user_id: Uuid) -> Result<String, TwoFactorError> {
+    let secret = totp::generate_secret();
+
+    // a confirmed secret is never replaced here
+    match sqlx::query("INSERT INTO totp_credentials (user_id, secret) VALUES ($1, $2) ON CONFLICT (user_id) DO UPDATE SET secret = EXCLUDED.secret, created_at = now() WHERE totp_credentials.confirmed_at IS NULL")
+        .bind(user_id)
+        .bind(&secret)
+        .execute(pool)
+        .await {
+            Ok(v) if v.rows_affected() == 1 => Ok(secret),
+            Ok(_v) => Err(TwoFactorError::AlreadyEnrolled),
+            Err(e) => {
+                println!("{}", e);
+                Err(TwoFactorError::FailedInsert)
+            }
+        }
+}
+
+/// The secret of an enrollment that hasn't been confirmed yet
+pub async fn find_pending_secret(pool: &PgPool, user_id: Uuid) -> Result<String, TwoFactorError> {
+    match sqlx::query_as::<_, (String,)>("SELECT secret FROM totp_credentials WHERE user_id = $1 AND confirmed_at IS NULL")
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await {
+            Ok(Some(v)) => Ok(v.0),
+            Ok(None) => Err(TwoFactorError::NotEnrolled),
+            Err(e) => {
+                println!("{}", e);
+                Err(TwoFactorError::FailedLookup)
+            }
+        }
+}
+
+/// Confirm the enrollment with a code from the authenticator, proving it has the secret.
+/// Returns the recovery codes, they're only shown this once and stored as a sha256 hash.
+pub async fn confirm_enrollment(
+    pool: &PgPool,
+    user_id: Uuid,
+    code: &str,
+) -> Result<Vec<String>, TwoFactorError> {
+    let mut tx = pool.begin().await.map_err(|_e| TwoFactorError::FailedUpdate)?;
+
+    let result = confirm_enrollment_tx(&mut tx, user_id, code).await;
+
+    match result {
+        Ok(codes) => match tx.commit().await {
+            Ok(_v) => Ok(codes),
+            Err(_e) => Err(TwoFactorError::FailedTransactionCommit),
+        },
+        Err(e) => {
+            let _e = tx.rollback().await;
+            Err(e)
+        }
+    }
+}
+
+async fn confirm_enrollment_tx(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    user_id: Uuid,
+    code: &str,
+) -> Result<Vec<String>, TwoFactorError> {
+    let credential = match sqlx::query_as::<_, TotpCredential>("SELECT secret, last_used_step FROM totp_credentials WHERE user_id = $1 AND confirmed_at IS NULL FOR UPDATE")
+        .bind(user_id)
+        .fetch_optional(&mut **tx)
+        .await {
+            Ok(Some(v)) => v,
+            Ok(None) => return Err(TwoFactorError::NotEnrolled),
+            Err(e) => {
+                println!("{}", e);
+                return Err(TwoFactorError::FailedLookup)
+            }
+        };
+
+    let step = match totp::verify(&credential.secret, code, totp::current_step(), credential.last_used_step) {
+        Some(v) => v,
+        None => return Err(TwoFactorError::InvalidCode),
+    };
+
+    match sqlx::query("UPDATE totp_credentials SET confirmed_at = now(), last_used_step = $2 WHERE user_id = $1")
+        .bind(user_id)
+        .bind(step)
+        .execute(&mut **tx)
+        .await {
+            Ok(_v) => {},
+            Err(e) => {
+                println!("{}", e);
+                return Err(TwoFactorError::FailedUpdate)
+            }
+        }
+
+    replace_recovery_codes_tx(tx, user_id).await
+}
+
+async fn replace_recovery_codes_tx(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    user_id: Uuid,
+) -> Result<Vec<String>, TwoFactorError> {
+    match sqlx::query("DELETE FROM recovery_codes WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut **tx)
+        .await {
+            Ok(_v) => {},
+            Err(e) => {
+                println!("{}", e);
+                return Err(TwoFactorError::FailedInsert)
+            }
+        }
+
+    let codes: Vec<String> = (0..RECOVERY_CODE_COUNT).map(|_| generate_recovery_code()).collect();
+
+    for code in &codes {
+        match sqlx::query("INSERT INTO recovery_codes (user_id, code_hash) VALUES ($1, $2)")
+            .bind(user_id)
+            .bind(crypto::hash_token(&normalize_recovery_code(code)))
+            .execute(&mut **tx)
+            .await {
+                Ok(_v) => {},
+                Err(e) => {
+                    println!("{}", e);
+                    return Err(TwoFactorError::FailedInsert)
+                }
+            }
+    }
+
+    Ok(codes)
+}
+
+/// Check the second factor of a user that entered the right password, either a code from
+/// their authenticator or one of their recovery codes. Both can only be used once.
+pub async fn verify_second_factor(
+    pool: &PgPool,
+    user_id: Uuid,
+    code: &str,
+) -> Result<SecondFactor, TwoFactorError> {
+    let mut tx = pool.begin().await.map_err(|_e| TwoFactorError::FailedLookup)?;
+
+    let result = verify_second_factor_tx(&mut tx, user_id, code).await;
+
+    match result {
+        Ok(factor) => match tx.commit().await {
+            Ok(_v) => Ok(factor),
+            Err(_e) => Err(TwoFactorError::FailedTransactionCommit),
+        },
+        Err(e) => {
+            let _e = tx.rollback().await;
+            Err(e)
+        }
+    }
+}
+
+async fn verify_second_factor_tx(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    user_id: Uuid,
+    code: &str,
+) -> Result<SecondFactor, TwoFactorError> {
+    // lock the credential so the same code can't be used by two requests at once
+    let credential = match sqlx::query_as::<_, TotpCredential>("SELECT secret, last_used_step FROM totp_credentials WHERE user_id = $1 AND confirmed_at IS NOT NULL FOR UPDATE")
+        .bind(user_id)
+        .fetch_optional(&mut **tx)
+        .await {
+            Ok(Some(v)) => v,
+            Ok(None) => return Err(TwoFactorError::NotEnrolled),
+            Err(e) => {
+                println!("{}", e);
+                return Err(TwoFactorError::FailedLookup)
+            }
+        };
+
+    if let Some(step) = totp::verify(&credential.secret, code, totp::current_step(), credential.last_used_step) {
+        return match sqlx::query("UPDATE totp_credentials SET last_used_step = $2 WHERE user_id = $1")
+            .bind(user_id)
+            .bind(step)
+            .execute(&mut **tx)
+            .await {
+                Ok(_v) => Ok(SecondFactor::Totp),
+                Err(e) => {
+                    println!("{}", e);
+                    Err(TwoFactorError::FailedUpdate)
+                }
+            }
+    }
+
+    match sqlx::query("UPDATE recovery_codes SET used_at = now() WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL")
+        .bind(user_id)
+        .bind(crypto::hash_token(&normalize_recovery_code(code)))
+        .execute(&mut **tx)
+        .await {
+            Ok(v) if v.rows_affected() > 0 => Ok(SecondFactor::RecoveryCode),
+            Ok(_v) => Err(TwoFactorError::InvalidCode),
+            Err(e) => {
+                println!("{}", e);
+                Err(TwoFactorError::FailedUpdate)
+            }
+        }
+}
+
+// two groups of 5, eg. `k7mqa-2xh9p`
+fn generate_recovery_code() -> String {
+    let mut rng = rand::thread_rng();
+    let mut code: String = (0..10)
+        .map(|_| RECOVERY_CODE_ALPHABET[rng.gen_range(0..RECOVERY_CODE_ALPHABET.len())] as char)
+        .collect();
+    code.insert(5, '-');
+    code
+}
+
+// recovery codes are accepted without the dash, in any case
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
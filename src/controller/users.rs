@@ -1,3 +1,4 @@
+use chrono::{DateTime, Utc};
 use uuid::Uuid;
 use sqlx::postgres::PgPool;
 use crate::common::crypto;
@@ -6,11 +7,23 @@ use crate::common::crypto;
 pub enum UsersError{
     FailedCount,
     FailedRoleNameLookup,
+    FailedScopeLookup,
     FailedUserInsert,
     FailedUserInsertUniqueEmail,
     FailedUserRoleInsert,
     FailedUserTransactionCommit,
     FailedLogin,
+    FailedPasswordUpdate,
+    FailedSessionRevoke,
+    // the stored password hash couldn't be read
+    FailedPasswordHash,
+    FailedLookup,
+    NotFound,
+    // the password is right, but the email hasn't been confirmed yet
+    Unconfirmed,
+    // the password is right, but an admin disabled the user
+    Disabled,
+    FailedUpdate,
 }
 
 /// Count the number of users that are in the system
@@ -56,8 +69,8 @@ pub async fn insert_user(pool: &PgPool, params: &InsertUserParams) -> Result<Uui
     println!("did we make it through the tx");
 
     match tx.commit().await {
-        Ok(_v) => return Ok(id),
-        Err(_e) => return Err(UsersError::FailedUserTransactionCommit),
+        Ok(_v) => Ok(id),
+        Err(_e) => Err(UsersError::FailedUserTransactionCommit),
     }
 }
 
@@ -66,7 +79,13 @@ pub async fn save_user_tx(
     id: Uuid, 
     params: &InsertUserParams
 ) -> Result<(), UsersError> {
-    let pw = crypto::hash_password(params.password.clone());
+    let pw = match crypto::hash_password(&params.password) {
+        Ok(v) => v,
+        Err(e) => {
+            println!("{}", e);
+            return Err(UsersError::FailedUserInsert)
+        }
+    };
 
     match sqlx::query("INSERT INTO users (id, email, password) values ($1, $2, $3)")
         .bind(id)
@@ -74,17 +93,12 @@ pub async fn save_user_tx(
         .bind(&pw)
         .execute(&mut **tx)
         .await {
-            Ok(_v) => {
-                return Ok(())
-            },
+            Ok(_v) => Ok(()),
             Err(err) => {
-                let e = err.as_database_error().and_then(|e| {e.constraint()});
-                if e.is_some() {
-                    if e.unwrap() == "users_email_key" {
-                        return Err(UsersError::FailedUserInsertUniqueEmail)
-                    }
+                if let Some("users_email_key") = err.as_database_error().and_then(|e| e.constraint()) {
+                    return Err(UsersError::FailedUserInsertUniqueEmail)
                 }
-                return Err(UsersError::FailedUserInsert);
+                Err(UsersError::FailedUserInsert)
             },
         }
 }
@@ -99,8 +113,6 @@ pub struct InsertUserParams {
 #[derive(sqlx::FromRow)]
 struct Role {
     id: Uuid,
-    name: String,
-    description: String,
 }
 
 /// Insert a user role mapping to the `user_role` linking table
@@ -111,7 +123,7 @@ pub async fn insert_user_role_tx(
 ) -> Result<(), UsersError> {
     println!("{}", role_name);
 
-    let role_id = match sqlx::query_as::<_, Role>("SELECT * FROM roles WHERE name = ($1)")
+    let role_id = match sqlx::query_as::<_, Role>("SELECT id FROM roles WHERE name = ($1)")
         .bind(role_name)
         .fetch_one(&mut **tx).await {
             Ok(v) => v.id,
@@ -123,38 +135,350 @@ pub async fn insert_user_role_tx(
         .bind(role_id)
         .execute(&mut **tx)
         .await {
-            Ok(_v) => return Ok(()),
+            Ok(_v) => Ok(()),
             Err(e) => {
                 println!("{}", e);
-                return Err(UsersError::FailedUserRoleInsert)
+                Err(UsersError::FailedUserRoleInsert)
             }
         }
 }
 
-#[derive(sqlx::FromRow, Debug)]
+// no Debug, it holds the password hash
+#[derive(sqlx::FromRow)]
 struct User {
     id: Uuid,
-    email: String,
     password: String,
+    confirmed_at: Option<DateTime<Utc>>,
+    disabled_at: Option<DateTime<Utc>>,
 }
 
+/// Check the users credentials, returning their id when they match, the email is confirmed
+/// and the user isn't disabled
 pub async fn attempt_user_login(
     pool: &PgPool, 
     email: String,
     password: String,
-) -> Result<(), UsersError> {
+) -> Result<Uuid, UsersError> {
     let user = match sqlx::query_as::<_, User>("SELECT * FROM users WHERE email = $1")
         .bind(email)
         .fetch_one(pool)
         .await {
             Ok(v) => v,
-            Err(e) => return Err(UsersError::FailedLogin)
+            Err(_e) => return Err(UsersError::FailedLogin)
         };
 
-    println!("yeah?, {:?}", user);
+    match crypto::verify_password(&user.password, &password) {
+        Ok(true) => {},
+        Ok(false) => return Err(UsersError::FailedLogin),
+        Err(e) => {
+            println!("corrupt password hash for user {}: {}", user.id, e);
+            return Err(UsersError::FailedPasswordHash)
+        }
+    };
+
+    // the password is known now, so a hash from an older algorithm or weaker parameters
+    // can be replaced
+    if crypto::needs_rehash(&user.password) {
+        rehash_password(pool, user.id, &user.password, &password).await;
+    }
+
+    if user.disabled_at.is_some() {
+        return Err(UsersError::Disabled)
+    }
+
+    match user.confirmed_at {
+        None => Err(UsersError::Unconfirmed),
+        Some(_v) => Ok(user.id),
+    }
+}
+
+// a failed rehash doesn't fail the login, the old hash still works
+async fn rehash_password(pool: &PgPool, user_id: Uuid, old_hash: &str, password: &str) {
+    let pw = match crypto::hash_password(password) {
+        Ok(v) => v,
+        Err(e) => {
+            println!("{}", e);
+            return
+        }
+    };
+
+    // only if the password didn't change in the meantime
+    match sqlx::query("UPDATE users SET password = $2 WHERE id = $1 AND password = $3")
+        .bind(user_id)
+        .bind(&pw)
+        .bind(old_hash)
+        .execute(pool)
+        .await {
+            Ok(_v) => println!("password of user {} rehashed", user_id),
+            Err(e) => println!("failed to rehash the password {}", e),
+        }
+}
+/// Id of the user with `email`, the `sub` of their tokens
+pub async fn find_user_id(pool: &PgPool, email: &str) -> Result<Uuid, UsersError> {
+    match sqlx::query_as::<_, (Uuid,)>("SELECT id FROM users WHERE email = $1")
+        .bind(email)
+        .fetch_optional(pool)
+        .await {
+            Ok(Some(v)) => Ok(v.0),
+            Ok(None) => Err(UsersError::NotFound),
+            Err(e) => {
+                println!("{}", e);
+                Err(UsersError::FailedLookup)
+            }
+        }
+}
+
+/// Names of the roles the user with `email` has, used as their groups when evaluating policies
+pub async fn find_role_names(pool: &PgPool, email: &str) -> Result<Vec<String>, UsersError> {
+    match sqlx::query_as::<_, (String,)>("SELECT roles.name FROM roles JOIN user_roles ON user_roles.role_id = roles.id JOIN users ON users.id = user_roles.user_id WHERE users.email = $1 ORDER BY roles.name")
+        .bind(email)
+        .fetch_all(pool)
+        .await {
+            Ok(v) => Ok(v.into_iter().map(|r| r.0).collect()),
+            Err(e) => {
+                println!("{}", e);
+                Err(UsersError::FailedRoleNameLookup)
+            }
+        }
+}
+
+/// Scopes granted to the user by their roles, these are the `scope` of their access token
+pub async fn find_scopes(pool: &PgPool, user_id: Uuid) -> Result<Vec<String>, UsersError> {
+    match sqlx::query_as::<_, (String,)>("SELECT DISTINCT unnest(roles.scopes) AS scope FROM roles JOIN user_roles ON user_roles.role_id = roles.id WHERE user_roles.user_id = $1 ORDER BY scope")
+        .bind(user_id)
+        .fetch_all(pool)
+        .await {
+            Ok(v) => Ok(v.into_iter().map(|r| r.0).collect()),
+            Err(e) => {
+                println!("{}", e);
+                Err(UsersError::FailedScopeLookup)
+            }
+        }
+}
 
-    match crypto::validate_password(user.password, password) {
-        true => return Ok(()),
-        false => return Err(UsersError::FailedLogin),
+/// Replace the password of the user, it's hashed with `crypto::hash_password`
+pub async fn update_password_tx(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    user_id: Uuid,
+    password: &str,
+) -> Result<(), UsersError> {
+    let pw = match crypto::hash_password(password) {
+        Ok(v) => v,
+        Err(e) => {
+            println!("{}", e);
+            return Err(UsersError::FailedPasswordUpdate)
+        }
     };
-}
\ No newline at end of file
+
+    match sqlx::query("UPDATE users SET password = $2 WHERE id = $1")
+        .bind(user_id)
+        .bind(&pw)
+        .execute(&mut **tx)
+        .await {
+            Ok(_v) => Ok(()),
+            Err(e) => {
+                println!("{}", e);
+                Err(UsersError::FailedPasswordUpdate)
+            }
+        }
+}
+
+/// Set the password of the user with `email`, for admins. Their sessions are revoked, like
+/// after a reset. The password has to be checked against the `PasswordPolicy` first.
+pub async fn set_password(pool: &PgPool, email: &str, password: &str) -> Result<Uuid, UsersError> {
+    let user_id = find_user_id(pool, email).await?;
+    let mut tx = pool.begin().await.map_err(|_e| UsersError::FailedPasswordUpdate)?;
+
+    let result = async {
+        update_password_tx(&mut tx, user_id, password).await?;
+        revoke_sessions_tx(&mut tx, user_id).await
+    }.await;
+
+    if let Err(e) = result {
+        let _e = tx.rollback().await;
+        return Err(e)
+    }
+
+    match tx.commit().await {
+        Ok(_v) => Ok(user_id),
+        Err(_e) => Err(UsersError::FailedUserTransactionCommit),
+    }
+}
+
+/// Revoke every session of the user, for "log out everywhere"
+pub async fn revoke_sessions(pool: &PgPool, user_id: Uuid) -> Result<(), UsersError> {
+    let mut tx = pool.begin().await.map_err(|_e| UsersError::FailedSessionRevoke)?;
+
+    if let Err(e) = revoke_sessions_tx(&mut tx, user_id).await {
+        let _e = tx.rollback().await;
+        return Err(e)
+    }
+
+    match tx.commit().await {
+        Ok(_v) => Ok(()),
+        Err(_e) => Err(UsersError::FailedUserTransactionCommit),
+    }
+}
+
+/// Reject every access token of the user that was issued before now, and revoke their refresh tokens
+pub async fn revoke_sessions_tx(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    user_id: Uuid,
+) -> Result<(), UsersError> {
+    match sqlx::query("UPDATE users SET sessions_revoked_at = now() WHERE id = $1")
+        .bind(user_id)
+        .execute(&mut **tx)
+        .await {
+            Ok(_v) => {},
+            Err(e) => {
+                println!("{}", e);
+                return Err(UsersError::FailedSessionRevoke)
+            }
+        }
+
+    match sqlx::query("UPDATE refresh_tokens SET revoked_at = now() WHERE user_id = $1 AND revoked_at IS NULL")
+        .bind(user_id)
+        .execute(&mut **tx)
+        .await {
+            Ok(_v) => {},
+            Err(e) => {
+                println!("{}", e);
+                return Err(UsersError::FailedSessionRevoke)
+            }
+        }
+
+    match sqlx::query("UPDATE user_session_details SET revoked_at = now() WHERE user_id = $1 AND revoked_at IS NULL")
+        .bind(user_id)
+        .execute(&mut **tx)
+        .await {
+            Ok(_v) => Ok(()),
+            Err(e) => {
+                println!("{}", e);
+                Err(UsersError::FailedSessionRevoke)
+            }
+        }
+}
+
+#[derive(sqlx::FromRow, Debug)]
+pub struct UserSummary {
+    pub id: Uuid,
+    pub email: String,
+    pub roles: Vec<String>,
+    pub confirmed_at: Option<DateTime<Utc>>,
+    pub disabled_at: Option<DateTime<Utc>>,
+}
+
+/// Every user with the names of their roles, for the admin cli
+pub async fn find_users(pool: &PgPool) -> Result<Vec<UserSummary>, UsersError> {
+    match sqlx::query_as::<_, UserSummary>("SELECT users.id, users.email, COALESCE(array_agg(roles.name ORDER BY roles.name) FILTER (WHERE roles.name IS NOT NULL), '{}') AS roles, users.confirmed_at, users.disabled_at FROM users LEFT JOIN user_roles ON user_roles.user_id = users.id LEFT JOIN roles ON roles.id = user_roles.role_id GROUP BY users.id ORDER BY users.email")
+        .fetch_all(pool)
+        .await {
+            Ok(v) => Ok(v),
+            Err(e) => {
+                println!("{}", e);
+                Err(UsersError::FailedLookup)
+            }
+        }
+}
+
+/// Confirm the email of a user without a code, for users made by an admin
+pub async fn confirm_user(pool: &PgPool, user_id: Uuid) -> Result<(), UsersError> {
+    match sqlx::query("UPDATE users SET confirmed_at = now() WHERE id = $1 AND confirmed_at IS NULL")
+        .bind(user_id)
+        .execute(pool)
+        .await {
+            Ok(_v) => Ok(()),
+            Err(e) => {
+                println!("{}", e);
+                Err(UsersError::FailedUpdate)
+            }
+        }
+}
+
+/// Disable or enable the user with `email`. A disabled user can't sign in, and their
+/// sessions are revoked along with it.
+pub async fn set_disabled(pool: &PgPool, email: &str, disabled: bool) -> Result<Uuid, UsersError> {
+    let user_id = find_user_id(pool, email).await?;
+    let mut tx = pool.begin().await.map_err(|_e| UsersError::FailedUpdate)?;
+
+    let result = async {
+        let query = match disabled {
+            true => "UPDATE users SET disabled_at = now() WHERE id = $1 AND disabled_at IS NULL",
+            false => "UPDATE users SET disabled_at = NULL WHERE id = $1",
+        };
+
+        if let Err(e) = sqlx::query(query).bind(user_id).execute(&mut *tx).await {
+            println!("{}", e);
+            return Err(UsersError::FailedUpdate)
+        }
+
+        match disabled {
+            true => revoke_sessions_tx(&mut tx, user_id).await,
+            false => Ok(()),
+        }
+    }.await;
+
+    if let Err(e) = result {
+        let _e = tx.rollback().await;
+        return Err(e)
+    }
+
+    match tx.commit().await {
+        Ok(_v) => Ok(user_id),
+        Err(_e) => Err(UsersError::FailedUserTransactionCommit),
+    }
+}
+
+/// Replace the roles of the user with `email` with the one role. The new scopes are in the
+/// tokens they get at their next login or refresh.
+pub async fn set_role(pool: &PgPool, email: &str, role_name: &str) -> Result<Uuid, UsersError> {
+    let user_id = find_user_id(pool, email).await?;
+    let mut tx = pool.begin().await.map_err(|_e| UsersError::FailedUserRoleInsert)?;
+
+    let result = async {
+        if let Err(e) = sqlx::query("DELETE FROM user_roles WHERE user_id = $1").bind(user_id).execute(&mut *tx).await {
+            println!("{}", e);
+            return Err(UsersError::FailedUserRoleInsert)
+        }
+
+        insert_user_role_tx(&mut tx, user_id, role_name).await
+    }.await;
+
+    if let Err(e) = result {
+        let _e = tx.rollback().await;
+        return Err(e)
+    }
+
+    match tx.commit().await {
+        Ok(_v) => Ok(user_id),
+        Err(_e) => Err(UsersError::FailedUserTransactionCommit),
+    }
+}
+
+/// A confirmed user with a random email, for the tests that need one in the database
+#[cfg(test)]
+pub async fn insert_test_user(pool: &PgPool) -> (Uuid, String) {
+    let id = Uuid::new_v4();
+    let email = format!("test-{}@example.com", id);
+
+    sqlx::query("INSERT INTO users (id, email, password, confirmed_at) VALUES ($1, $2, '', now())")
+        .bind(id)
+        .bind(&email)
+        .execute(pool)
+        .await
+        .unwrap();
+
+    (id, email)
+}
+
+/// Remove a user made by `insert_test_user`, with the rows that don't cascade
+#[cfg(test)]
+pub async fn delete_test_user(pool: &PgPool, id: Uuid) {
+    for query in [
+        "DELETE FROM refresh_tokens WHERE user_id = $1",
+        "DELETE FROM user_roles WHERE user_id = $1",
+        "DELETE FROM users WHERE id = $1",
+    ] {
+        sqlx::query(query).bind(id).execute(pool).await.unwrap();
+    }
+}
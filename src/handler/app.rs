@@ -30,9 +30,11 @@ pub fn router() -> Router {
         .route_layer(middleware::from_fn(refresh_token))
 }
 
+// not routed, `/app` is served from the static dir
+#[allow(dead_code)]
 pub async fn render_app(
     Extension(templates): Extension<templates::Templates>,
-    session: Session<SessionPgPool>,
+    _session: Session<SessionPgPool>,
 ) -> impl IntoResponse {
     let context = templates::new_template_context();
  
@@ -1,40 +1,76 @@
-use serde::Deserialize;
-use rand::distributions::{Alphanumeric, DistString};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 use axum::{
     Extension,
     Router, 
-    Form,
     response::{Redirect, IntoResponse, Html},
     routing::{get, post},
-    extract::{Query},
+    extract::Query,
+    http::{header::USER_AGENT, HeaderMap},
 };
 use axum_session::{Session, SessionPgPool};
 use sqlx::postgres::PgPool;
-use crate::common::{templates, jwt};
-use crate::controller::users::attempt_user_login;
+use crate::common::{client_ip::ClientIp, csrf::{Csrf, CsrfForm}, templates, jwt, keys::Keys};
+use crate::controller::users::{attempt_user_login, find_scopes, UsersError};
+use crate::controller::login_attempts::{
+    record_login_success,
+    release_login_attempt,
+    reserve_login_attempt,
+    LoginAttemptsError,
+    ReservedAttempt,
+};
+use crate::controller::refresh_tokens::insert_refresh_token;
+use crate::controller::sessions::{start_session, Device};
+use crate::controller::two_factor::{is_enrolled, verify_second_factor, SecondFactor, TwoFactorError};
+
+/// how long the second factor can be entered for after the password
+const TWO_FACTOR_TTL_SECONDS: i64 = 300;
+/// wrong second factors before the password has to be entered again
+const TWO_FACTOR_MAX_ATTEMPTS: u32 = 5;
 
 pub fn router() -> Router {
     Router::new()
         .route("/login", get(render_login_page))
         .route("/login", post(login_user))
+        .route("/login/two-factor", get(render_two_factor_page))
+        .route("/login/two-factor", post(login_two_factor))
+}
+
+/// A login that passed the password check, and is waiting for the second factor. It's kept
+/// in the session under `two_factor_login`.
+#[derive(Serialize, Deserialize, Debug)]
+struct PendingLogin {
+    user_id: String,
+    email: String,
+    offline: Option<bool>,
+    started_at: i64,
+    attempts: u32,
 }
 
 #[derive(Deserialize)]
 pub struct LoginErrorParams {
     pub error: Option<String>,
+    // set after the email was confirmed on `/signup/confirm`
+    pub confirmed: Option<bool>,
+    // set after the password was reset on `/password/reset`
+    pub reset: Option<bool>,
+    // set after logging out on `/logout`
+    pub logged_out: Option<bool>,
 }
 
 #[axum_macros::debug_handler]
 pub async fn render_login_page(
     params: Query<LoginErrorParams>,
     Extension(templates): Extension<templates::Templates>,
-    session: Session<SessionPgPool>,
+    csrf: Csrf,
 ) -> impl IntoResponse {
     let mut context = templates::new_template_context();
-    let authenticity_token = Alphanumeric.sample_string(&mut rand::thread_rng(), 64);
     context.insert("error", &params.error);
-    context.insert("authenticity_token", &authenticity_token);
-    session.set("authenticity_token", authenticity_token);
+    context.insert("confirmed", &params.confirmed.unwrap_or(false));
+    context.insert("reset", &params.reset.unwrap_or(false));
+    context.insert("logged_out", &params.logged_out.unwrap_or(false));
+    context.insert("authenticity_token", &csrf.token());
     Html(templates.render("login_page", &context).unwrap())
 }
 
@@ -42,61 +78,284 @@ pub async fn render_login_page(
 pub struct LoginRequest {
     email: String,
     password: String,
-    authenticity_token: String,
     offline: Option<bool>,
 }
 
 #[axum_macros::debug_handler]
 pub async fn login_user(
     Extension(pool): Extension<PgPool>,
+    Extension(keys): Extension<Keys>,
+    Extension(client): Extension<ClientIp>,
+    headers: HeaderMap,
     session: Session<SessionPgPool>,
-    Form(req): Form<LoginRequest>,
+    CsrfForm(req): CsrfForm<LoginRequest>,
 ) -> impl IntoResponse {
-    let authenticity_token = session
-        .get("authenticity_token")
-        .unwrap_or(String::from(""));
-
-    if &authenticity_token != &req.authenticity_token {
-        return Redirect::to("/signup?error=internal_server_error")
-    } 
+    // locked out or backing off logins are refused before the password is checked, the
+    // attempt counts as a failed one until the password turns out to be right
+    let attempt = match reserve_attempt(&pool, &req.email, &client).await {
+        Ok(v) => v,
+        Err(redirect) => return redirect,
+    };
 
     // attempt login
-    match attempt_user_login(&pool, req.email.clone(), req.password).await {
-        Ok(_v) => println!("good auth"),
-        Err(_e) => return Redirect::to("/login?error=incorrect_email_password")
+    let result = attempt_user_login(&pool, req.email.clone(), req.password).await;
+    if !matches!(result, Err(UsersError::FailedLogin)) {
+        release_attempt(&pool, attempt).await;
+    }
+
+    let user_id = match result {
+        Ok(v) => v,
+        Err(UsersError::Unconfirmed) => return Redirect::to("/login?error=unconfirmed_email"),
+        Err(UsersError::Disabled) => return Redirect::to("/login?error=account_disabled"),
+        Err(UsersError::FailedPasswordHash) => return Redirect::to("/login?error=internal_server_error"),
+        Err(_e) => return Redirect::to("/login?error=incorrect_email_password"),
+    };
+
+    // users that enrolled a second factor have to enter it before any token is forged
+    match is_enrolled(&pool, user_id).await {
+        Ok(false) => {},
+        Ok(true) => {
+            session.set("two_factor_login", PendingLogin {
+                user_id: user_id.to_string(),
+                email: req.email,
+                offline: req.offline,
+                started_at: Utc::now().timestamp(),
+                attempts: 0,
+            });
+            return Redirect::to("/login/two-factor")
+        },
+        Err(e) => {
+            println!("failed to look up the second factor {:?}", e);
+            return Redirect::to("/login?error=internal_server_error")
+        }
+    }
+
+    sign_in(&pool, &keys, &session, SignIn {
+        user_id,
+        email: req.email,
+        offline: req.offline,
+        amr: vec![String::from("pwd")],
+        device: device(&headers, &client),
+    }).await
+}
+
+#[derive(Deserialize)]
+pub struct TwoFactorParams {
+    pub error: Option<String>,
+}
+
+#[axum_macros::debug_handler]
+pub async fn render_two_factor_page(
+    params: Query<TwoFactorParams>,
+    Extension(templates): Extension<templates::Templates>,
+    csrf: Csrf,
+    session: Session<SessionPgPool>,
+) -> impl IntoResponse {
+    if session.get::<PendingLogin>("two_factor_login").is_none() {
+        return Redirect::to("/login").into_response()
+    }
+
+    let mut context = templates::new_template_context();
+    context.insert("error", &params.error);
+    context.insert("authenticity_token", &csrf.token());
+    Html(templates.render("login_two_factor_page", &context).unwrap()).into_response()
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TwoFactorRequest {
+    code: String,
+}
+
+/// Second step of the login, the code from the authenticator app or a recovery code
+#[axum_macros::debug_handler]
+pub async fn login_two_factor(
+    Extension(pool): Extension<PgPool>,
+    Extension(keys): Extension<Keys>,
+    Extension(client): Extension<ClientIp>,
+    headers: HeaderMap,
+    session: Session<SessionPgPool>,
+    CsrfForm(req): CsrfForm<TwoFactorRequest>,
+) -> impl IntoResponse {
+    let mut pending = match session.get::<PendingLogin>("two_factor_login") {
+        Some(v) => v,
+        None => return Redirect::to("/login"),
+    };
+
+    if Utc::now().timestamp() - pending.started_at > TWO_FACTOR_TTL_SECONDS {
+        session.remove("two_factor_login");
+        return Redirect::to("/login?error=two_factor_expired")
+    }
+
+    let user_id = match Uuid::parse_str(&pending.user_id) {
+        Ok(v) => v,
+        Err(_e) => {
+            session.remove("two_factor_login");
+            return Redirect::to("/login?error=internal_server_error")
+        }
+    };
+
+    // wrong codes count against the account like wrong passwords, so they can't be guessed
+    // by entering the password again every few codes
+    let attempt = match reserve_attempt(&pool, &pending.email, &client).await {
+        Ok(v) => v,
+        Err(redirect) => {
+            session.remove("two_factor_login");
+            return redirect
+        }
+    };
+
+    let result = verify_second_factor(&pool, user_id, &req.code).await;
+    if !matches!(result, Err(TwoFactorError::InvalidCode)) {
+        release_attempt(&pool, attempt).await;
     }
 
-    let scopes: Vec<String> = vec!["admin".to_string()];
-    let audience: Vec<String> = vec!["webapp".to_string()];
+    let factor = match result {
+        Ok(v) => v,
+        Err(TwoFactorError::InvalidCode) => {
+            pending.attempts += 1;
+            if pending.attempts >= TWO_FACTOR_MAX_ATTEMPTS {
+                session.remove("two_factor_login");
+                return Redirect::to("/login?error=two_factor_attempts")
+            }
+
+            session.set("two_factor_login", pending);
+            return Redirect::to("/login/two-factor?error=invalid_code")
+        },
+        Err(e) => {
+            println!("failed to verify the second factor {:?}", e);
+            return Redirect::to("/login/two-factor?error=internal_server_error")
+        }
+    };
+
+    session.remove("two_factor_login");
+
+    // a recovery code isn't a code from the authenticator app, resource servers can tell them apart
+    let second_factor = match factor {
+        SecondFactor::Totp => "otp",
+        SecondFactor::RecoveryCode => {
+            println!("user {} signed in with a recovery code", user_id);
+            "rec"
+        },
+    };
+    let amr = vec![String::from("pwd"), String::from(second_factor), String::from("mfa")];
+    sign_in(&pool, &keys, &session, SignIn {
+        user_id,
+        email: pending.email,
+        offline: pending.offline,
+        amr,
+        device: device(&headers, &client),
+    }).await
+}
+
+// the login page with the reason a login isn't allowed right now
+async fn reserve_attempt(pool: &PgPool, email: &str, client: &ClientIp) -> Result<ReservedAttempt, Redirect> {
+    match reserve_login_attempt(pool, email, &client.0.to_string()).await {
+        Ok(v) => Ok(v),
+        Err(LoginAttemptsError::AccountLocked(_until)) => Err(Redirect::to("/login?error=account_locked")),
+        Err(LoginAttemptsError::IpLocked(_until)) => Err(Redirect::to("/login?error=ip_locked")),
+        Err(LoginAttemptsError::Throttled(_at)) => Err(Redirect::to("/login?error=too_many_attempts")),
+        Err(LoginAttemptsError::IpThrottled(_at)) => Err(Redirect::to("/login?error=ip_too_many_attempts")),
+        Err(e) => {
+            println!("failed to check the login attempts {:?}", e);
+            Err(Redirect::to("/login?error=internal_server_error"))
+        }
+    }
+}
 
-    // generate access, refresh tokens with the role (default, admin)
+// the password or code was right, or couldn't be checked, so it's not counted as a failure
+async fn release_attempt(pool: &PgPool, attempt: ReservedAttempt) {
+    if let Err(e) = release_login_attempt(pool, attempt).await {
+        println!("failed to release the login attempt {:?}", e);
+    }
+}
+
+/// A user that passed every factor of the login
+struct SignIn {
+    user_id: Uuid,
+    email: String,
+    offline: Option<bool>,
+    // authentication methods, the `amr` claim
+    amr: Vec<String>,
+    device: Device,
+}
+
+// the browser or app the request came from, the session is tagged with it
+fn device(headers: &HeaderMap, client: &ClientIp) -> Device {
+    Device {
+        user_agent: headers
+            .get(USER_AGENT)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("")
+            .to_owned(),
+        ip: client.0.to_string(),
+    }
+}
+
+// forge the tokens of a user that passed every factor, put them in the session, and tag
+// the session with the user and their device
+async fn sign_in(
+    pool: &PgPool,
+    keys: &Keys,
+    session: &Session<SessionPgPool>,
+    login: SignIn,
+) -> Redirect {
+    let SignIn { user_id, email, offline, amr, device } = login;
+
+    if let Err(e) = record_login_success(pool, &email).await {
+        println!("failed to reset the failed logins {:?}", e);
+    }
+
+    // scopes are granted by the roles of the user
+    let scopes = match find_scopes(pool, user_id).await {
+        Ok(v) => v,
+        Err(e) => {
+            println!("failed to find the users scopes {:?}", e);
+            return Redirect::to("/login?error=internal_server_error")
+        }
+    };
+
+    // generate access, refresh tokens with the scopes of the users roles
     let tokens = jwt::ForgeOptions::new()
-        .offline(req.offline)
-        .subject(req.email)
-        .issuer(String::from("https://steady-bytes.com"))
-        .audience(audience)
-        .authorized_parties(String::from("client_id"))
+        .offline(offline)
+        .subject(email)
+        .issuer(keys.issuer().to_owned())
+        .audience(keys.audience().to_vec())
+        .authorized_parties(keys.client_id().to_owned())
         .scopes(scopes)
-        .forge();
+        .authentication_methods(amr)
+        .forge(keys);
 
-    // if req.offline {
-    //     // insert the fresh token into db
-    // }
-     
     // forge tokens, if fail redirect to signup page with internal_server_error
     match tokens {
         Ok(tokens) => {
+            // a refresh token is only forged in offline mode, it starts a new token family
+            let mut refresh_family_id = None;
+            if let Some(claims) = &tokens.refresh_token_claims {
+                match insert_refresh_token(pool, user_id, claims).await {
+                    Ok(v) => refresh_family_id = Some(v),
+                    Err(e) => {
+                        println!("failed to save the refresh token {:?}", e);
+                        return Redirect::to("/login?error=internal_server_error")
+                    }
+                }
+            }
+
+            let session_id = session.get_session_id().await.inner();
+            if let Err(e) = start_session(pool, &session_id, user_id, refresh_family_id, &device).await {
+                println!("failed to tag the session {:?}", e);
+                return Redirect::to("/login?error=internal_server_error")
+            }
+
             // add access token to session
             session.set("access_token", &tokens.access_token);
             session.set("id_token", &tokens.id_token); 
-            // todo -> if refresh_token is forged save it to the refresh token table
-            session.set("refresh_token", &tokens.refresh_token.unwrap_or_default());
+            session.set("refresh_token", tokens.refresh_token.unwrap_or_default());
 
-            return Redirect::to("/app")
+            Redirect::to("/app")
         },
         Err(e) => {
             println!("and error occurred when forging the tokens {:?}", e);
-            return Redirect::to("/signup?error=internal_server_error")
+            Redirect::to("/signup?error=internal_server_error")
         }
     }
-}
\ No newline at end of file
+}
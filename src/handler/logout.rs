@@ -0,0 +1,174 @@
+use serde::Deserialize;
+use sqlx::postgres::PgPool;
+use axum::{
+    Extension,
+    Router,
+    http::{HeaderMap, StatusCode},
+    middleware,
+    response::{Html, IntoResponse, Redirect, Response},
+    routing::{get, post},
+};
+
+use axum_session::{Session, SessionPgPool};
+use crate::{
+    common::{csrf::{is_bearer, Csrf, CsrfForm}, jwt, keys::Keys, templates},
+    controller::refresh_tokens,
+    controller::revoked_tokens,
+    controller::sessions,
+    controller::users::{self, find_user_id},
+    middleware::authentication_token::authenticity_token_protected,
+    middleware::identification_token::identification_token,
+    middleware::refresh_token::{clear_tokens, refresh_token},
+};
+
+/// Ending the session of the signed in user, or every session they have
+pub fn router() -> Router {
+    Router::new()
+        .route("/logout", get(render_logout_page).post(logout))
+        .route("/logout/everywhere", post(logout_everywhere))
+        .route_layer(middleware::from_fn(authenticity_token_protected))
+        .route_layer(middleware::from_fn(identification_token))
+        .route_layer(middleware::from_fn(refresh_token))
+}
+
+#[axum_macros::debug_handler]
+pub async fn render_logout_page(
+    Extension(templates): Extension<templates::Templates>,
+    Extension(claims): Extension<jwt::AccessTokenClaims>,
+    csrf: Csrf,
+) -> Response {
+    let mut context = templates::new_template_context();
+    context.insert("authenticity_token", &csrf.token());
+    context.insert("email", &claims.sub);
+
+    Html(templates.render("logout_page", &context).unwrap()).into_response()
+}
+
+/// Sent by the logout page, api clients send their refresh token instead so its family is
+/// revoked along with the access token
+#[derive(Deserialize, Debug)]
+pub struct LogoutRequest {
+    refresh_token: Option<String>,
+}
+
+// revoke the access token of the request, and the family of the refresh token that goes
+// with it, from the session or sent by an api client
+async fn revoke_tokens(
+    pool: &PgPool,
+    keys: &Keys,
+    session: &Session<SessionPgPool>,
+    claims: &jwt::AccessTokenClaims,
+    refresh_token: Option<String>,
+) -> Result<(), ()> {
+    if let Err(e) = revoked_tokens::revoke_access_token(pool, claims).await {
+        println!("failed to revoke the access token {:?}", e);
+        return Err(())
+    }
+
+    let refresh_token = match refresh_token.or_else(|| session.get::<String>("refresh_token")) {
+        Some(v) if !v.is_empty() => v,
+        _ => return Ok(()),
+    };
+
+    // an expired refresh token can't renew anything, there's nothing left to revoke
+    match jwt::decode_token::<jwt::RefreshTokenClaims>(keys, &refresh_token) {
+        Ok(v) if v.claims.sub == claims.sub => match refresh_tokens::revoke_family_of(pool, &v.claims).await {
+            Ok(_v) => Ok(()),
+            // the token was never saved, eg. forged before the family was tracked
+            Err(refresh_tokens::RefreshTokensError::NotFound) => Ok(()),
+            Err(e) => {
+                println!("failed to revoke the refresh token family {:?}", e);
+                Err(())
+            }
+        },
+        Ok(_v) => Ok(()),
+        Err(_e) => Ok(()),
+    }
+}
+
+// forget the tokens and the session itself
+async fn end_session(pool: &PgPool, session: &Session<SessionPgPool>) {
+    let session_id = session.get_session_id().await.inner();
+    if let Err(e) = sessions::end_session(pool, &session_id).await {
+        println!("failed to forget the session {:?}", e);
+    }
+
+    clear_tokens(session);
+    session.destroy();
+}
+
+/// End the current session, its tokens can't be used anymore even though they haven't expired
+#[axum_macros::debug_handler]
+pub async fn logout(
+    Extension(pool): Extension<PgPool>,
+    Extension(keys): Extension<Keys>,
+    Extension(claims): Extension<jwt::AccessTokenClaims>,
+    session: Session<SessionPgPool>,
+    headers: HeaderMap,
+    CsrfForm(req): CsrfForm<LogoutRequest>,
+) -> Response {
+    // api clients authenticate every request with their access token, there's no session
+    let bearer = is_bearer(&headers);
+
+    let refresh_token = if bearer { req.refresh_token } else { None };
+
+    if let Err(_e) = revoke_tokens(&pool, &keys, &session, &claims, refresh_token).await {
+        return match bearer {
+            true => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+            false => Redirect::to("/login?error=internal_server_error").into_response(),
+        }
+    }
+
+    println!("{} logged out", claims.sub);
+
+    match bearer {
+        true => StatusCode::NO_CONTENT.into_response(),
+        false => {
+            end_session(&pool, &session).await;
+            Redirect::to("/login?logged_out=true").into_response()
+        }
+    }
+}
+
+/// End every session of the user, in every browser and api client. Tokens issued before now
+/// are rejected and every refresh token family is revoked.
+#[axum_macros::debug_handler]
+pub async fn logout_everywhere(
+    Extension(pool): Extension<PgPool>,
+    Extension(claims): Extension<jwt::AccessTokenClaims>,
+    session: Session<SessionPgPool>,
+    headers: HeaderMap,
+    CsrfForm(_req): CsrfForm<LogoutRequest>,
+) -> Response {
+    // api clients authenticate every request with their access token, there's no session
+    let bearer = is_bearer(&headers);
+
+    let result = match find_user_id(&pool, &claims.sub).await {
+        Ok(user_id) => users::revoke_sessions(&pool, user_id).await,
+        Err(e) => Err(e),
+    };
+
+    // the access token is put on the list too, it may have been issued in the same second
+    // as the revocation
+    if let Err(e) = result {
+        println!("failed to revoke the sessions of {} {:?}", claims.sub, e);
+        return match bearer {
+            true => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+            false => Redirect::to("/login?error=internal_server_error").into_response(),
+        }
+    }
+
+    if let Err(e) = revoked_tokens::revoke_access_token(&pool, &claims).await {
+        println!("failed to revoke the access token {:?}", e);
+    }
+
+    println!("{} logged out everywhere", claims.sub);
+
+    match bearer {
+        true => StatusCode::NO_CONTENT.into_response(),
+        false => {
+            end_session(&pool, &session).await;
+            Redirect::to("/login?logged_out=true").into_response()
+        }
+    }
+}
@@ -1,3 +1,11 @@
 pub mod signup;
 pub mod app;
-pub mod login;
\ No newline at end of file
+pub mod login;
+pub mod token;
+pub mod password;
+pub mod policies;
+pub mod well_known;
+pub mod two_factor;
+pub mod users;
+pub mod logout;
+pub mod sessions;
\ No newline at end of file
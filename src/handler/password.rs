@@ -0,0 +1,161 @@
+use serde::Deserialize;
+use sqlx::postgres::PgPool;
+use axum::{
+    Extension,
+    Router,
+    extract::Query,
+    response::{Html, IntoResponse, Redirect},
+    routing::get,
+};
+
+use crate::common::{csrf::{Csrf, CsrfForm}, password_policy::PasswordPolicies, templates};
+use crate::controller::password_resets::{
+    create_reset_token,
+    reset_password,
+    verify_reset_token,
+    PasswordResetsError,
+};
+
+pub fn router() -> Router {
+    Router::new()
+        .route("/password/forgot", get(render_forgot_page).post(forgot_password))
+        .route("/password/reset", get(render_reset_page).post(reset_user_password))
+}
+
+#[derive(Deserialize)]
+pub struct ForgotParams {
+    pub error: Option<String>,
+    pub sent: Option<bool>,
+}
+
+#[axum_macros::debug_handler]
+pub async fn render_forgot_page(
+    params: Query<ForgotParams>,
+    Extension(templates): Extension<templates::Templates>,
+    csrf: Csrf,
+) -> impl IntoResponse {
+    let mut context = templates::new_template_context();
+    context.insert("authenticity_token", &csrf.token());
+    context.insert("error", &params.error);
+    context.insert("sent", &params.sent.unwrap_or(false));
+
+    Html(templates.render("password_forgot_page", &context).unwrap())
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ForgotRequest {
+    email: String,
+}
+
+/// Send a reset link to the email. The response is the same whether or not the email
+/// belongs to a user.
+#[axum_macros::debug_handler]
+pub async fn forgot_password(
+    Extension(pool): Extension<PgPool>,
+    CsrfForm(req): CsrfForm<ForgotRequest>,
+) -> Redirect {
+    if let Err(e) = create_reset_token(&pool, &req.email).await {
+        println!("password reset not sent {:?}", e);
+    }
+
+    Redirect::to("/password/forgot?sent=true")
+}
+
+#[derive(Deserialize)]
+pub struct ResetParams {
+    pub token: Option<String>,
+    pub error: Option<String>,
+}
+
+#[axum_macros::debug_handler]
+pub async fn render_reset_page(
+    params: Query<ResetParams>,
+    Extension(templates): Extension<templates::Templates>,
+    Extension(pool): Extension<PgPool>,
+    Extension(password_policy): Extension<PasswordPolicies>,
+    csrf: Csrf,
+) -> impl IntoResponse {
+    let mut context = templates::new_template_context();
+    context.insert("password_policy", password_policy.as_ref());
+    context.insert("authenticity_token", &csrf.token());
+
+    let token = params.token.clone().unwrap_or_default();
+    let error = match verify_reset_token(&pool, &token).await {
+        Ok(_v) => params.error.clone(),
+        Err(PasswordResetsError::Expired) => Some(String::from("expired_token")),
+        Err(PasswordResetsError::InvalidToken) => Some(String::from("invalid_token")),
+        Err(_e) => Some(String::from("internal_server_error")),
+    };
+
+    context.insert("token", &token);
+    context.insert("error", &error);
+
+    Html(templates.render("password_reset_page", &context).unwrap())
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ResetRequest {
+    token: String,
+    password: String,
+    confirm_password: String,
+}
+
+#[axum_macros::debug_handler]
+pub async fn reset_user_password(
+    Extension(pool): Extension<PgPool>,
+    Extension(password_policy): Extension<PasswordPolicies>,
+    CsrfForm(req): CsrfForm<ResetRequest>,
+) -> Redirect {
+    let retry = |error: &str| Redirect::to(&reset_url(&req.token, error));
+
+    if req.password != req.confirm_password {
+        return retry("password_match")
+    }
+
+    // the policy needs the email of the user the token belongs to
+    let email = match verify_reset_token(&pool, &req.token).await {
+        Ok(v) => v,
+        Err(PasswordResetsError::Expired) => return retry("expired_token"),
+        Err(PasswordResetsError::InvalidToken) => return retry("invalid_token"),
+        Err(e) => {
+            println!("failed to find the reset token {:?}", e);
+            return retry("internal_server_error")
+        }
+    };
+
+    if let Err(violation) = password_policy.check(&req.password, &email) {
+        return retry(violation.code())
+    }
+
+    match reset_password(&pool, &req.token, &req.password).await {
+        Ok(_v) => Redirect::to("/login?reset=true"),
+        Err(PasswordResetsError::Expired) => retry("expired_token"),
+        Err(PasswordResetsError::InvalidToken) => retry("invalid_token"),
+        Err(e) => {
+            println!("failed to reset password {:?}", e);
+            retry("internal_server_error")
+        }
+    }
+}
+
+// the reset page again, the token is whatever was posted so it's encoded
+fn reset_url(token: &str, error: &str) -> String {
+    let query = serde_urlencoded::to_string([("token", token), ("error", error)]).unwrap_or_default();
+    format!("/password/reset?{}", query)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reset_url_keeps_the_token_in_its_parameter() {
+        assert_eq!(reset_url("abc&error=x", "invalid_token"), "/password/reset?token=abc%26error%3Dx&error=invalid_token");
+    }
+
+    #[test]
+    fn reset_url_is_a_valid_location() {
+        let url = reset_url("a\r\nSet-Cookie: x=1", "password_match");
+        assert!(axum::http::HeaderValue::from_str(&url).is_ok());
+    }
+}
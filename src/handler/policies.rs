@@ -0,0 +1,300 @@
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgPool;
+use axum::{
+    Extension,
+    Json,
+    Router,
+    extract::{Path, Query},
+    http::StatusCode,
+    middleware,
+    routing::{get, post, put},
+};
+
+use crate::{
+    api::draft::access_controls::v1::{
+        AccessControlPolicy,
+        LookupObjectKey,
+        Operation,
+        Outcome,
+        PolicySetVersion,
+        Subject,
+    },
+    common::jwt,
+    controller::policies::{self, PoliciesError, PolicyFilter},
+    middleware::authentication_token::authenticity_token_protected,
+    middleware::identification_token::identification_token,
+    middleware::refresh_token::refresh_token,
+    middleware::require_scope::RequireScope,
+};
+
+/// JSON version of the `PolicyAdmin` gRPC service, for the signed in admin in the webapp
+/// or an api client with a bearer token
+pub fn router() -> Router {
+    Router::new()
+        .route("/policies", get(list_policies)
+            .route_layer(RequireScope("policies:read"))
+            .merge(post(create_policy).route_layer(RequireScope("policies:write"))))
+        .route("/policies/:id", put(update_policy).delete(delete_policy)
+            .route_layer(RequireScope("policies:write")))
+        .route("/policies/versions", get(list_versions)
+            .route_layer(RequireScope("policies:read")))
+        .route("/policies/rollback", post(rollback)
+            .route_layer(RequireScope("policies:write")))
+        .route_layer(middleware::from_fn(authenticity_token_protected))
+        .route_layer(middleware::from_fn(identification_token))
+        .route_layer(middleware::from_fn(refresh_token))
+}
+
+/// An `AccessControlPolicy` with its enums as their proto names, eg. `CHANGE_PERMISSION`
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct PolicyJson {
+    #[serde(default)]
+    id: String,
+    data_type: String,
+    operation: String,
+    outcome: String,
+    #[serde(default)]
+    subject_user_id: String,
+    #[serde(default)]
+    subject_group_ids: Vec<String>,
+    #[serde(default)]
+    lookup_object_key: Vec<ObjectKeyJson>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ObjectKeyJson {
+    key: String,
+    value: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ListPoliciesQuery {
+    #[serde(default)]
+    data_type: String,
+    #[serde(default)]
+    subject_user_id: String,
+    #[serde(default)]
+    subject_group_id: String,
+    operation: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ListPoliciesResponse {
+    policies: Vec<PolicyJson>,
+    version: i64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct PolicyResponse {
+    policy: PolicyJson,
+    version: i64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct VersionResponse {
+    version: i64,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ListVersionsQuery {
+    policy_id: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct PolicySetVersionJson {
+    version: i64,
+    change: String,
+    policy_id: String,
+    policy: Option<PolicyJson>,
+    changed_by: String,
+    created_at: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RollbackRequest {
+    version: i64,
+}
+
+pub async fn list_policies(
+    Extension(pool): Extension<PgPool>,
+    Extension(claims): Extension<jwt::AccessTokenClaims>,
+    Query(query): Query<ListPoliciesQuery>,
+) -> Result<Json<ListPoliciesResponse>, StatusCode> {
+    authorize(&pool, claims).await?;
+
+    let operation = match query.operation {
+        Some(name) => parse_operation(&name)? as i32,
+        None => 0,
+    };
+
+    let filter = PolicyFilter {
+        data_type: query.data_type,
+        subject_user_id: query.subject_user_id,
+        subject_group_id: query.subject_group_id,
+        operation,
+    };
+
+    let version = policies::current_version(&pool).await.map_err(to_status)?;
+    match policies::find_policies(&pool, &filter).await {
+        Ok(v) => Ok(Json(ListPoliciesResponse {
+            policies: v.into_iter().map(PolicyJson::from).collect(),
+            version,
+        })),
+        Err(e) => Err(to_status(e)),
+    }
+}
+
+pub async fn create_policy(
+    Extension(pool): Extension<PgPool>,
+    Extension(claims): Extension<jwt::AccessTokenClaims>,
+    Json(req): Json<PolicyJson>,
+) -> Result<(StatusCode, Json<PolicyResponse>), StatusCode> {
+    let changed_by = authorize(&pool, claims).await?;
+
+    match policies::create_policy(&pool, req.into_policy()?, &changed_by).await {
+        Ok((policy, version)) => Ok((StatusCode::CREATED, Json(PolicyResponse {
+            policy: PolicyJson::from(policy),
+            version,
+        }))),
+        Err(e) => Err(to_status(e)),
+    }
+}
+
+pub async fn update_policy(
+    Extension(pool): Extension<PgPool>,
+    Extension(claims): Extension<jwt::AccessTokenClaims>,
+    Path(id): Path<String>,
+    Json(mut req): Json<PolicyJson>,
+) -> Result<Json<PolicyResponse>, StatusCode> {
+    let changed_by = authorize(&pool, claims).await?;
+    req.id = id;
+
+    match policies::update_policy(&pool, req.into_policy()?, &changed_by).await {
+        Ok((policy, version)) => Ok(Json(PolicyResponse {
+            policy: PolicyJson::from(policy),
+            version,
+        })),
+        Err(e) => Err(to_status(e)),
+    }
+}
+
+pub async fn delete_policy(
+    Extension(pool): Extension<PgPool>,
+    Extension(claims): Extension<jwt::AccessTokenClaims>,
+    Path(id): Path<String>,
+) -> Result<Json<VersionResponse>, StatusCode> {
+    let changed_by = authorize(&pool, claims).await?;
+
+    match policies::delete_policy(&pool, &id, &changed_by).await {
+        Ok(version) => Ok(Json(VersionResponse { version })),
+        Err(e) => Err(to_status(e)),
+    }
+}
+
+pub async fn list_versions(
+    Extension(pool): Extension<PgPool>,
+    Extension(claims): Extension<jwt::AccessTokenClaims>,
+    Query(query): Query<ListVersionsQuery>,
+) -> Result<Json<Vec<PolicySetVersionJson>>, StatusCode> {
+    authorize(&pool, claims).await?;
+
+    match policies::find_versions(&pool, query.policy_id.as_deref()).await {
+        Ok(v) => Ok(Json(v.into_iter().map(PolicySetVersionJson::from).collect())),
+        Err(e) => Err(to_status(e)),
+    }
+}
+
+pub async fn rollback(
+    Extension(pool): Extension<PgPool>,
+    Extension(claims): Extension<jwt::AccessTokenClaims>,
+    Json(req): Json<RollbackRequest>,
+) -> Result<Json<VersionResponse>, StatusCode> {
+    let changed_by = authorize(&pool, claims).await?;
+
+    match policies::rollback_policies(&pool, req.version, &changed_by).await {
+        Ok(version) => Ok(Json(VersionResponse { version })),
+        Err(e) => Err(to_status(e)),
+    }
+}
+
+// the caller has to be allowed CHANGE_PERMISSION on `access_control_policies`,
+// returns their subject to record as `changed_by`
+async fn authorize(pool: &PgPool, claims: jwt::AccessTokenClaims) -> Result<String, StatusCode> {
+    let subject = policies::subject_for_user(pool, &claims.sub).await.map_err(to_status)?;
+
+    match policies::can_change_permission(pool, subject).await {
+        Ok(true) => Ok(claims.sub),
+        Ok(false) => Err(StatusCode::FORBIDDEN),
+        Err(e) => Err(to_status(e)),
+    }
+}
+
+fn to_status(e: PoliciesError) -> StatusCode {
+    match e {
+        PoliciesError::NotFound | PoliciesError::UnknownVersion => StatusCode::NOT_FOUND,
+        PoliciesError::InvalidPolicy(_field) => StatusCode::BAD_REQUEST,
+        e => {
+            println!("policy administration failed {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+fn parse_operation(name: &str) -> Result<Operation, StatusCode> {
+    Operation::from_str_name(name).ok_or(StatusCode::BAD_REQUEST)
+}
+
+impl PolicyJson {
+    fn into_policy(self) -> Result<AccessControlPolicy, StatusCode> {
+        let outcome = Outcome::from_str_name(&self.outcome).ok_or(StatusCode::BAD_REQUEST)?;
+
+        Ok(AccessControlPolicy {
+            id: self.id,
+            lookup_object_key: self.lookup_object_key.into_iter().map(|k| LookupObjectKey {
+                key: k.key,
+                value: k.value,
+            }).collect(),
+            data_type: self.data_type,
+            operation: parse_operation(&self.operation)? as i32,
+            subject: Some(Subject {
+                user_id: self.subject_user_id,
+                group_ids: self.subject_group_ids,
+            }),
+            outcome: outcome as i32,
+        })
+    }
+}
+
+impl From<AccessControlPolicy> for PolicyJson {
+    fn from(policy: AccessControlPolicy) -> Self {
+        let operation = policy.operation().as_str_name().to_owned();
+        let outcome = policy.outcome().as_str_name().to_owned();
+        let subject = policy.subject.unwrap_or_default();
+
+        PolicyJson {
+            id: policy.id,
+            data_type: policy.data_type,
+            operation,
+            outcome,
+            subject_user_id: subject.user_id,
+            subject_group_ids: subject.group_ids,
+            lookup_object_key: policy.lookup_object_key.into_iter().map(|k| ObjectKeyJson {
+                key: k.key,
+                value: k.value,
+            }).collect(),
+        }
+    }
+}
+
+impl From<PolicySetVersion> for PolicySetVersionJson {
+    fn from(v: PolicySetVersion) -> Self {
+        PolicySetVersionJson {
+            version: v.version,
+            change: v.change,
+            policy_id: v.policy_id,
+            policy: v.policy.map(PolicyJson::from),
+            changed_by: v.changed_by,
+            created_at: v.created_at,
+        }
+    }
+}
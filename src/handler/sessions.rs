@@ -0,0 +1,165 @@
+use chrono::SecondsFormat;
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgPool;
+use uuid::Uuid;
+use axum::{
+    Extension,
+    Json,
+    Router,
+    extract::{Path, Query},
+    http::StatusCode,
+    middleware,
+    response::{Html, IntoResponse, Redirect, Response},
+    routing::{delete, get, post},
+};
+
+use axum_session::{Session, SessionPgPool};
+use crate::{
+    common::{csrf::{Csrf, CsrfForm}, jwt, templates},
+    controller::sessions::{self, ActiveSession, SessionsError},
+    controller::users::find_user_id,
+    middleware::authentication_token::authenticity_token_protected,
+    middleware::identification_token::identification_token,
+    middleware::refresh_token::refresh_token,
+};
+
+/// The active sessions of the signed in user, on a page for browsers and as json for api
+/// clients. Any of them can be revoked.
+pub fn router() -> Router {
+    Router::new()
+        .route("/account/sessions", get(render_sessions_page))
+        .route("/account/sessions/revoke", post(revoke_session_form))
+        .route("/sessions", get(list_sessions))
+        .route("/sessions/:id", delete(revoke_session))
+        .route_layer(middleware::from_fn(authenticity_token_protected))
+        .route_layer(middleware::from_fn(identification_token))
+        .route_layer(middleware::from_fn(refresh_token))
+}
+
+#[derive(Serialize, Debug)]
+pub struct SessionJson {
+    id: String,
+    user_agent: String,
+    ip: String,
+    created_at: String,
+    last_seen_at: String,
+    // the session the list was asked for from
+    current: bool,
+}
+
+impl From<ActiveSession> for SessionJson {
+    fn from(s: ActiveSession) -> Self {
+        SessionJson {
+            id: s.id.to_string(),
+            user_agent: s.user_agent,
+            ip: s.ip,
+            created_at: s.created_at.to_rfc3339_opts(SecondsFormat::Secs, true),
+            last_seen_at: s.last_seen_at.to_rfc3339_opts(SecondsFormat::Secs, true),
+            current: s.current,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct SessionsParams {
+    pub error: Option<String>,
+    pub revoked: Option<bool>,
+}
+
+#[axum_macros::debug_handler]
+pub async fn render_sessions_page(
+    params: Query<SessionsParams>,
+    Extension(templates): Extension<templates::Templates>,
+    Extension(pool): Extension<PgPool>,
+    Extension(claims): Extension<jwt::AccessTokenClaims>,
+    csrf: Csrf,
+    session: Session<SessionPgPool>,
+) -> Response {
+    let mut context = templates::new_template_context();
+    context.insert("authenticity_token", &csrf.token());
+    context.insert("error", &params.error);
+    context.insert("revoked", &params.revoked.unwrap_or(false));
+
+    let session_id = session.get_session_id().await.inner();
+    let active = match find_user_id(&pool, &claims.sub).await {
+        Ok(user_id) => sessions::find_active_sessions(&pool, user_id, &session_id).await.ok(),
+        Err(_e) => None,
+    };
+
+    match active {
+        Some(v) => context.insert("sessions", &v.into_iter().map(SessionJson::from).collect::<Vec<_>>()),
+        None => {
+            context.insert("sessions", &Vec::<SessionJson>::new());
+            context.insert("error", "internal_server_error");
+        }
+    }
+
+    Html(templates.render("account_sessions_page", &context).unwrap()).into_response()
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RevokeSessionRequest {
+    id: String,
+}
+
+/// Revoke a session from the sessions page, revoking the current one logs the user out
+#[axum_macros::debug_handler]
+pub async fn revoke_session_form(
+    Extension(pool): Extension<PgPool>,
+    Extension(claims): Extension<jwt::AccessTokenClaims>,
+    CsrfForm(req): CsrfForm<RevokeSessionRequest>,
+) -> Redirect {
+    let id = match Uuid::parse_str(&req.id) {
+        Ok(v) => v,
+        Err(_e) => return Redirect::to("/account/sessions?error=not_found"),
+    };
+
+    match revoke(&pool, &claims.sub, id).await {
+        StatusCode::NO_CONTENT => Redirect::to("/account/sessions?revoked=true"),
+        StatusCode::NOT_FOUND => Redirect::to("/account/sessions?error=not_found"),
+        _ => Redirect::to("/account/sessions?error=internal_server_error"),
+    }
+}
+
+pub async fn list_sessions(
+    Extension(pool): Extension<PgPool>,
+    Extension(claims): Extension<jwt::AccessTokenClaims>,
+    session: Session<SessionPgPool>,
+) -> Result<Json<Vec<SessionJson>>, StatusCode> {
+    let user_id = find_user_id(&pool, &claims.sub)
+        .await
+        .map_err(|_e| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    // bearer clients have no session of their own, so none of them is current
+    let session_id = session.get_session_id().await.inner();
+
+    match sessions::find_active_sessions(&pool, user_id, &session_id).await {
+        Ok(v) => Ok(Json(v.into_iter().map(SessionJson::from).collect())),
+        Err(_e) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+pub async fn revoke_session(
+    Extension(pool): Extension<PgPool>,
+    Extension(claims): Extension<jwt::AccessTokenClaims>,
+    Path(id): Path<Uuid>,
+) -> StatusCode {
+    revoke(&pool, &claims.sub, id).await
+}
+
+/// Revoke a session of the user with `email`, it has to be one of theirs
+pub async fn revoke(pool: &PgPool, email: &str, id: Uuid) -> StatusCode {
+    let user_id = match find_user_id(pool, email).await {
+        Ok(v) => v,
+        Err(_e) => return StatusCode::NOT_FOUND,
+    };
+
+    match sessions::revoke_session(pool, user_id, id).await {
+        Ok(_v) => {
+            println!("session {} of {} revoked", id, email);
+            StatusCode::NO_CONTENT
+        },
+        Err(SessionsError::NotFound) => StatusCode::NOT_FOUND,
+        Err(_e) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
@@ -1,4 +1,3 @@
-use rand::distributions::{Alphanumeric, DistString};
 use serde::Deserialize;
 use sqlx::postgres::PgPool;
 
@@ -6,26 +5,34 @@ use axum::{
     extract::{Path, Query},
     Extension,
     response::{Html, IntoResponse, Redirect},
-    Form,
     Router,
     routing::{get, post},
 };
 
 use axum_session::{Session, SessionPgPool};
 
-use crate::common::{templates, jwt};
+use crate::common::{client_ip::ClientIp, csrf::{Csrf, CsrfForm}, password_policy::PasswordPolicies, templates};
 use crate::controller::users::{
     count_users, 
     insert_user, 
     InsertUserParams,
     UsersError,
 };
+use crate::controller::confirmation_codes::{
+    confirm_with_code,
+    create_confirmation_code,
+    resend_confirmation_code,
+    ConfirmationCodesError,
+};
 
 pub fn router() -> Router {
     Router::new()
         .route("/greet/:name", get(greet))
         .route("/signup", get(render_signup_page))
         .route("/signup", post(signup_user))
+        .route("/signup/confirm", get(render_confirm_page))
+        .route("/signup/confirm", post(confirm_user))
+        .route("/signup/confirm/resend", post(resend_code))
 }
 
 pub async fn greet(
@@ -52,21 +59,18 @@ pub struct SignupErrorParams {
 ///
 /// render_signup_page
 /// Will render an html signup page. 
-/// @TODO -> Generate a strong `authenticity_token` using something that is signed with a secret key
-///          example: https://medium.com/@web3developer/signing-and-verifying-messages-with-hmac-in-rust-using-ring-69e6ed93ee78
 #[axum_macros::debug_handler]
 pub async fn render_signup_page(
     params: Query<SignupErrorParams>,
     Extension(templates): Extension<templates::Templates>,
     Extension(pool): Extension<PgPool>,
-    session: Session<SessionPgPool>,
+    Extension(password_policy): Extension<PasswordPolicies>,
+    csrf: Csrf,
 ) -> impl IntoResponse {
     let mut context = templates::new_template_context();
-    let authenticity_token = Alphanumeric.sample_string(&mut rand::thread_rng(), 64);
-    println!("authenticity_ token: {}", authenticity_token);
-    context.insert("authenticity_token", &authenticity_token);
-    session.set("authenticity_token", authenticity_token);
+    context.insert("authenticity_token", &csrf.token());
     context.insert("error", &params.error);
+    context.insert("password_policy", password_policy.as_ref());
 
     match count_users(&pool).await {
         Ok(v) => {
@@ -87,28 +91,22 @@ pub struct NewUserRequest {
     email: String,
     password: String,
     confirm_password: String,
-    offline: bool,
-    authenticity_token: String,
 }
 
 #[axum_macros::debug_handler]
 pub async fn signup_user(
     Extension(pool): Extension<PgPool>,
+    Extension(password_policy): Extension<PasswordPolicies>,
+    Extension(client): Extension<ClientIp>,
     session: Session<SessionPgPool>,
-    Form(req): Form<NewUserRequest>,
+    CsrfForm(req): CsrfForm<NewUserRequest>,
 ) -> Redirect { 
-    let authenticity_token = session
-        .get("authenticity_token")
-        .unwrap_or(String::from(""));
-
-    if &authenticity_token != &req.authenticity_token {
-        return Redirect::to("/signup?error=internal_server_error")
-    } 
-
-    if &req.password != &req.confirm_password {
+    if req.password != req.confirm_password {
         return Redirect::to("/signup?error=password_match")
-    } else if &req.password.len() < &8 || &req.password.len() > &20 {
-        return Redirect::to("/signup?error=password_strength")
+    }
+
+    if let Err(violation) = password_policy.check(&req.password, &req.email) {
+        return Redirect::to(&format!("/signup?error={}", violation.code()))
     }
 
     let role_name = match count_users(&pool).await {
@@ -116,16 +114,14 @@ pub async fn signup_user(
         Err(_e) => String::from("default"),
     };
 
-    let scopes: Vec<String> = vec!["admin".to_string()];
-    let audience: Vec<String> = vec!["webapp".to_string()];
 
     let insert_params = &InsertUserParams{
         email: req.email.clone(),
         password: req.password.clone(),
-        role_name: role_name,
+        role_name,
     };
 
-    match insert_user(&pool, insert_params).await {
+    let user_id = match insert_user(&pool, insert_params).await {
        Ok(v) => v,
        Err(e) => {
             if let UsersError::FailedUserInsertUniqueEmail = e {
@@ -136,30 +132,97 @@ pub async fn signup_user(
        }
     };
 
-    // generate access, refresh tokens with the role (default, admin)
-    let tokens = jwt::ForgeOptions::new()
-        .offline(Some(req.offline))
-        .subject(req.email.clone())
-        .issuer(String::from("https://steady-bytes.com"))
-        .audience(audience)
-        .authorized_parties(String::from("client_id"))
-        .scopes(scopes)
-        .forge();
-
-    // todo -> figure out how to set in the users session
- 
-    // match tokens {
-    //     Ok(tokens) => {
-    //         // add access token to session
-    //         session.set("access_token", tokens.access_token);
-    //         session.set("id_token", tokens.id_token);
-    //         session.set("refresh_token", tokens.refresh_token);
-    //     },
-    //     Err(e) => {
-    //         println!("and error occurred when forging the tokens {:?}", e);
-    //         return Redirect::to("/signup?error=internal_server_error")
-    //     }
-    // }
-
-    Redirect::to("/app")
-}
\ No newline at end of file
+    // the user can't login until they've confirmed their email with the code
+    match create_confirmation_code(&pool, user_id, &req.email, &client.0.to_string()).await {
+        Ok(_code) => {},
+        Err(e) => {
+            println!("failed to create a confirmation code {:?}", e);
+            return Redirect::to("/signup?error=internal_server_error")
+        }
+    }
+
+    session.set("confirm_email", &req.email);
+
+    Redirect::to("/signup/confirm")
+}
+
+#[derive(Deserialize)]
+pub struct ConfirmParams {
+    pub error: Option<String>,
+    pub resent: Option<bool>,
+}
+
+#[axum_macros::debug_handler]
+pub async fn render_confirm_page(
+    params: Query<ConfirmParams>,
+    Extension(templates): Extension<templates::Templates>,
+    csrf: Csrf,
+    session: Session<SessionPgPool>,
+) -> impl IntoResponse {
+    let mut context = templates::new_template_context();
+    context.insert("authenticity_token", &csrf.token());
+    context.insert("error", &params.error);
+    context.insert("resent", &params.resent.unwrap_or(false));
+    context.insert("email", &session.get::<String>("confirm_email").unwrap_or_default());
+
+    Html(templates.render("signup_confirm_page", &context).unwrap())
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ConfirmRequest {
+    email: String,
+    code: String,
+}
+
+#[axum_macros::debug_handler]
+pub async fn confirm_user(
+    Extension(pool): Extension<PgPool>,
+    session: Session<SessionPgPool>,
+    CsrfForm(req): CsrfForm<ConfirmRequest>,
+) -> Redirect {
+    session.set("confirm_email", &req.email);
+
+    match confirm_with_code(&pool, &req.email, &req.code).await {
+        Ok(_) => {
+            session.remove("confirm_email");
+            Redirect::to("/login?confirmed=true")
+        },
+        // an unknown or already confirmed email gets the same error as a wrong code
+        Err(ConfirmationCodesError::InvalidCode)
+        | Err(ConfirmationCodesError::NotFound)
+        | Err(ConfirmationCodesError::AlreadyConfirmed) => {
+            Redirect::to("/signup/confirm?error=invalid_code")
+        },
+        Err(ConfirmationCodesError::Expired) => Redirect::to("/signup/confirm?error=expired_code"),
+        Err(ConfirmationCodesError::TooManyAttempts) => Redirect::to("/signup/confirm?error=too_many_attempts"),
+        Err(e) => {
+            println!("failed to confirm {:?}", e);
+            Redirect::to("/signup/confirm?error=internal_server_error")
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ResendRequest {
+    email: String,
+}
+
+/// Send a new code, the old one stops working. The response is the same whether or not
+/// the email belongs to an unconfirmed user, only the limit of the client ip is reported.
+#[axum_macros::debug_handler]
+pub async fn resend_code(
+    Extension(pool): Extension<PgPool>,
+    Extension(client): Extension<ClientIp>,
+    session: Session<SessionPgPool>,
+    CsrfForm(req): CsrfForm<ResendRequest>,
+) -> Redirect {
+    session.set("confirm_email", &req.email);
+
+    match resend_confirmation_code(&pool, &req.email, &client.0.to_string()).await {
+        Ok(_v) => {},
+        Err(ConfirmationCodesError::IpTooManySends) => return Redirect::to("/signup/confirm?error=too_many_sends"),
+        Err(e) => println!("confirmation code not resent {:?}", e),
+    }
+
+    Redirect::to("/signup/confirm?resent=true")
+}
@@ -0,0 +1,59 @@
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgPool;
+use axum::{
+    Extension,
+    Json,
+    Router,
+    http::StatusCode,
+    routing::post,
+};
+
+use crate::common::{jwt, keys::Keys};
+use crate::controller::refresh_tokens::{rotate_refresh_token, RefreshTokensError};
+
+pub fn router() -> Router {
+    Router::new()
+        .route("/token/refresh", post(refresh))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RefreshRequest {
+    refresh_token: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct RefreshResponse {
+    access_token: String,
+    id_token: String,
+    refresh_token: String,
+}
+
+/// Exchange a refresh token for a new token pair. The presented refresh token is retired,
+/// presenting it again will revoke every token that descends from the same login.
+#[axum_macros::debug_handler]
+pub async fn refresh(
+    Extension(pool): Extension<PgPool>,
+    Extension(keys): Extension<Keys>,
+    Json(req): Json<RefreshRequest>,
+) -> Result<Json<RefreshResponse>, StatusCode> {
+    let claims = match jwt::decode_token::<jwt::RefreshTokenClaims>(&keys, &req.refresh_token) {
+        Ok(v) => v.claims,
+        Err(_e) => return Err(StatusCode::UNAUTHORIZED),
+    };
+
+    match rotate_refresh_token(&pool, &keys, &claims).await {
+        Ok(tokens) => Ok(Json(RefreshResponse {
+            access_token: tokens.access_token,
+            id_token: tokens.id_token,
+            refresh_token: tokens.refresh_token.unwrap_or_default(),
+        })),
+        Err(RefreshTokensError::NotFound)
+        | Err(RefreshTokensError::Expired)
+        | Err(RefreshTokensError::Revoked)
+        | Err(RefreshTokensError::Reused) => Err(StatusCode::UNAUTHORIZED),
+        Err(e) => {
+            println!("failed to rotate refresh token {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
@@ -0,0 +1,173 @@
+use serde::Deserialize;
+use sqlx::postgres::PgPool;
+use uuid::Uuid;
+use axum::{
+    Extension,
+    Router,
+    extract::Query,
+    middleware,
+    response::{Html, IntoResponse, Redirect, Response},
+    routing::{get, post},
+};
+
+use crate::{
+    common::{csrf::{Csrf, CsrfForm}, jwt, keys::Keys, templates, totp},
+    controller::two_factor::{self, TwoFactorError},
+    controller::users::find_user_id,
+    middleware::authentication_token::authenticity_token_protected,
+    middleware::identification_token::identification_token,
+    middleware::refresh_token::refresh_token,
+};
+
+/// Enrollment of the signed in user in TOTP two-factor authentication
+pub fn router() -> Router {
+    Router::new()
+        .route("/account/two-factor", get(render_two_factor_page).post(begin_enrollment))
+        .route("/account/two-factor/confirm", post(confirm_enrollment))
+        .route_layer(middleware::from_fn(authenticity_token_protected))
+        .route_layer(middleware::from_fn(identification_token))
+        .route_layer(middleware::from_fn(refresh_token))
+}
+
+#[derive(Deserialize)]
+pub struct TwoFactorParams {
+    pub error: Option<String>,
+}
+
+// the page with the authenticity_token set, and nothing about the enrollment yet
+fn new_context(csrf: &Csrf, error: Option<&str>) -> templates::Context {
+    let mut context = templates::new_template_context();
+    context.insert("authenticity_token", &csrf.token());
+    context.insert("error", &error);
+    context.insert("enrolled", &false);
+    context.insert("secret", &None::<String>);
+    context.insert("recovery_codes", &Vec::<String>::new());
+    context
+}
+
+// show the secret of a pending enrollment, as text, as an otpauth uri and as a QR code
+fn insert_enrollment(context: &mut templates::Context, keys: &Keys, email: &str, secret: &str) {
+    // authenticator apps show the issuer as the account name, the host reads better than a url
+    let issuer = keys.issuer().trim_start_matches("https://").trim_start_matches("http://");
+    let uri = totp::otpauth_uri(issuer, email, secret);
+    context.insert("secret", secret);
+    context.insert("qr_code", &totp::qr_code_svg(&uri));
+    context.insert("otpauth_uri", &uri);
+}
+
+async fn current_user_id(pool: &PgPool, claims: &jwt::AccessTokenClaims) -> Option<Uuid> {
+    match find_user_id(pool, &claims.sub).await {
+        Ok(v) => Some(v),
+        Err(e) => {
+            println!("failed to find the signed in user {:?}", e);
+            None
+        }
+    }
+}
+
+#[axum_macros::debug_handler]
+pub async fn render_two_factor_page(
+    params: Query<TwoFactorParams>,
+    Extension(templates): Extension<templates::Templates>,
+    Extension(pool): Extension<PgPool>,
+    Extension(claims): Extension<jwt::AccessTokenClaims>,
+    csrf: Csrf,
+) -> Response {
+    let mut context = new_context(&csrf, params.error.as_deref());
+
+    let user_id = match current_user_id(&pool, &claims).await {
+        Some(v) => v,
+        None => return Redirect::to("/login").into_response(),
+    };
+
+    match two_factor::find_status(&pool, user_id).await {
+        Ok(status) => {
+            context.insert("enrolled", &status.enrolled);
+            context.insert("recovery_codes_left", &status.recovery_codes_left);
+        },
+        Err(_e) => context.insert("error", "internal_server_error"),
+    }
+
+    Html(templates.render("account_two_factor_page", &context).unwrap()).into_response()
+}
+
+// nothing but the authenticity_token
+#[derive(Deserialize, Debug)]
+pub struct BeginEnrollmentRequest {}
+
+/// Create a new secret, and show it so it can be added to the authenticator app
+#[axum_macros::debug_handler]
+pub async fn begin_enrollment(
+    Extension(templates): Extension<templates::Templates>,
+    Extension(pool): Extension<PgPool>,
+    Extension(keys): Extension<Keys>,
+    Extension(claims): Extension<jwt::AccessTokenClaims>,
+    csrf: Csrf,
+    CsrfForm(_req): CsrfForm<BeginEnrollmentRequest>,
+) -> Response {
+    let user_id = match current_user_id(&pool, &claims).await {
+        Some(v) => v,
+        None => return Redirect::to("/login").into_response(),
+    };
+
+    let secret = match two_factor::begin_enrollment(&pool, user_id).await {
+        Ok(v) => v,
+        Err(TwoFactorError::AlreadyEnrolled) => return Redirect::to("/account/two-factor").into_response(),
+        Err(e) => {
+            println!("failed to begin the two-factor enrollment {:?}", e);
+            return Redirect::to("/account/two-factor?error=internal_server_error").into_response()
+        }
+    };
+
+    let mut context = new_context(&csrf, None);
+    insert_enrollment(&mut context, &keys, &claims.sub, &secret);
+
+    Html(templates.render("account_two_factor_page", &context).unwrap()).into_response()
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ConfirmEnrollmentRequest {
+    code: String,
+}
+
+/// Turn on two-factor authentication once the authenticator shows the right code, and show
+/// the recovery codes
+#[axum_macros::debug_handler]
+pub async fn confirm_enrollment(
+    Extension(templates): Extension<templates::Templates>,
+    Extension(pool): Extension<PgPool>,
+    Extension(keys): Extension<Keys>,
+    Extension(claims): Extension<jwt::AccessTokenClaims>,
+    csrf: Csrf,
+    CsrfForm(req): CsrfForm<ConfirmEnrollmentRequest>,
+) -> Response {
+    let user_id = match current_user_id(&pool, &claims).await {
+        Some(v) => v,
+        None => return Redirect::to("/login").into_response(),
+    };
+
+    match two_factor::confirm_enrollment(&pool, user_id, &req.code).await {
+        Ok(recovery_codes) => {
+            let mut context = new_context(&csrf, None);
+            context.insert("enrolled", &true);
+            context.insert("recovery_codes", &recovery_codes);
+
+            Html(templates.render("account_two_factor_page", &context).unwrap()).into_response()
+        },
+        // show the same secret again, so it doesn't have to be scanned again
+        Err(TwoFactorError::InvalidCode) => match two_factor::find_pending_secret(&pool, user_id).await {
+            Ok(secret) => {
+                let mut context = new_context(&csrf, Some("invalid_code"));
+                insert_enrollment(&mut context, &keys, &claims.sub, &secret);
+
+                Html(templates.render("account_two_factor_page", &context).unwrap()).into_response()
+            },
+            Err(_e) => Redirect::to("/account/two-factor?error=internal_server_error").into_response(),
+        },
+        Err(TwoFactorError::NotEnrolled) => Redirect::to("/account/two-factor").into_response(),
+        Err(e) => {
+            println!("failed to confirm the two-factor enrollment {:?}", e);
+            Redirect::to("/account/two-factor?error=internal_server_error").into_response()
+        }
+    }
+}
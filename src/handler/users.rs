@@ -0,0 +1,226 @@
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgPool;
+use uuid::Uuid;
+use axum::{
+    Extension,
+    Json,
+    Router,
+    extract::Query,
+    http::StatusCode,
+    middleware,
+    response::{Html, IntoResponse, Redirect, Response},
+    routing::{get, post},
+};
+
+use crate::{
+    common::{csrf::{Csrf, CsrfForm}, password_policy::PasswordPolicies, templates},
+    controller::login_attempts::{self, LoginAttemptsError},
+    controller::sessions,
+    controller::users::{self, UsersError},
+    middleware::authentication_token::authenticity_token_protected,
+    middleware::identification_token::identification_token,
+    middleware::refresh_token::refresh_token,
+    middleware::require_scope::RequireScope,
+    handler::sessions::{revoke, SessionJson},
+};
+
+/// User administration for admins, every route needs `users:write` since they read or
+/// change other users accounts
+pub fn router() -> Router {
+    Router::new()
+        .route("/users/lockouts", get(list_lockouts))
+        .route("/users/unlock", post(unlock_user))
+        .route("/users/password", post(set_user_password))
+        .route("/users/sessions", get(list_user_sessions))
+        .route("/users/sessions/revoke", post(revoke_user_session))
+        .route("/account/users/sessions", get(render_user_sessions_page))
+        .route("/account/users/sessions/revoke", post(revoke_user_session_form))
+        .route_layer(RequireScope("users:write"))
+        .route_layer(middleware::from_fn(authenticity_token_protected))
+        .route_layer(middleware::from_fn(identification_token))
+        .route_layer(middleware::from_fn(refresh_token))
+}
+
+#[derive(Serialize, Debug)]
+pub struct LockoutJson {
+    // `email` or `ip`
+    kind: String,
+    key: String,
+    failures: i32,
+    last_failure_at: String,
+    locked_until: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct UnlockRequest {
+    email: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SetPasswordRequest {
+    email: String,
+    password: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct UserSessionsParams {
+    email: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RevokeUserSessionRequest {
+    email: String,
+    id: Uuid,
+}
+
+#[derive(Deserialize)]
+pub struct UserSessionsPageParams {
+    pub email: Option<String>,
+    pub error: Option<String>,
+    pub revoked: Option<bool>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RevokeUserSessionForm {
+    email: String,
+    id: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ErrorResponse {
+    // eg. `password_too_short`, the same codes the forms use
+    error: &'static str,
+}
+
+pub async fn list_lockouts(
+    Extension(pool): Extension<PgPool>,
+) -> Result<Json<Vec<LockoutJson>>, StatusCode> {
+    match login_attempts::find_lockouts(&pool).await {
+        Ok(v) => Ok(Json(v.into_iter().map(|l| LockoutJson {
+            kind: l.kind,
+            key: l.key,
+            failures: l.failures,
+            last_failure_at: l.last_failure_at.to_rfc3339(),
+            locked_until: l.locked_until.to_rfc3339(),
+        }).collect())),
+        Err(_e) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+/// Lift the lockout of an account, and forget its failed logins
+pub async fn unlock_user(
+    Extension(pool): Extension<PgPool>,
+    Json(req): Json<UnlockRequest>,
+) -> StatusCode {
+    match login_attempts::unlock_account(&pool, &req.email).await {
+        Ok(_v) => {
+            println!("account {} unlocked", req.email);
+            StatusCode::NO_CONTENT
+        },
+        Err(LoginAttemptsError::NotFound) => StatusCode::NOT_FOUND,
+        Err(_e) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+/// Set the password of a user, it has to follow the same `PasswordPolicy` as signup
+pub async fn set_user_password(
+    Extension(pool): Extension<PgPool>,
+    Extension(password_policy): Extension<PasswordPolicies>,
+    Json(req): Json<SetPasswordRequest>,
+) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
+    if let Err(violation) = password_policy.check(&req.password, &req.email) {
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, Json(ErrorResponse { error: violation.code() })))
+    }
+
+    match users::set_password(&pool, &req.email, &req.password).await {
+        Ok(user_id) => {
+            println!("password of user {} set by an admin", user_id);
+            Ok(StatusCode::NO_CONTENT)
+        },
+        Err(UsersError::NotFound) => Err((StatusCode::NOT_FOUND, Json(ErrorResponse { error: "not_found" }))),
+        Err(e) => {
+            println!("failed to set the password {:?}", e);
+            Err((StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse { error: "internal_server_error" })))
+        }
+    }
+}
+
+/// The active sessions of any user
+pub async fn list_user_sessions(
+    Extension(pool): Extension<PgPool>,
+    Query(params): Query<UserSessionsParams>,
+) -> Result<Json<Vec<SessionJson>>, StatusCode> {
+    let user_id = match users::find_user_id(&pool, &params.email).await {
+        Ok(v) => v,
+        Err(UsersError::NotFound) => return Err(StatusCode::NOT_FOUND),
+        Err(_e) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+    };
+
+    // the admin's own session isn't one of the users, none of them is current
+    match sessions::find_active_sessions(&pool, user_id, "").await {
+        Ok(v) => Ok(Json(v.into_iter().map(SessionJson::from).collect())),
+        Err(_e) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+/// Revoke one session of a user, eg. on a device they lost
+pub async fn revoke_user_session(
+    Extension(pool): Extension<PgPool>,
+    Json(req): Json<RevokeUserSessionRequest>,
+) -> StatusCode {
+    revoke(&pool, &req.email, req.id).await
+}
+
+/// The sessions page for admins, the sessions of the user with `email`
+#[axum_macros::debug_handler]
+pub async fn render_user_sessions_page(
+    params: Query<UserSessionsPageParams>,
+    Extension(templates): Extension<templates::Templates>,
+    Extension(pool): Extension<PgPool>,
+    csrf: Csrf,
+) -> Response {
+    let mut context = templates::new_template_context();
+    context.insert("authenticity_token", &csrf.token());
+    context.insert("email", &params.email);
+    context.insert("error", &params.error);
+    context.insert("revoked", &params.revoked.unwrap_or(false));
+    context.insert("sessions", &Vec::<SessionJson>::new());
+
+    if let Some(email) = &params.email {
+        let active = match users::find_user_id(&pool, email).await {
+            Ok(user_id) => sessions::find_active_sessions(&pool, user_id, "").await.map_err(|_e| "internal_server_error"),
+            Err(UsersError::NotFound) => Err("user_not_found"),
+            Err(_e) => Err("internal_server_error"),
+        };
+
+        match active {
+            Ok(v) => context.insert("sessions", &v.into_iter().map(SessionJson::from).collect::<Vec<_>>()),
+            Err(error) => context.insert("error", error),
+        }
+    }
+
+    Html(templates.render("admin_sessions_page", &context).unwrap()).into_response()
+}
+
+/// Revoke a session of a user from the admin sessions page
+#[axum_macros::debug_handler]
+pub async fn revoke_user_session_form(
+    Extension(pool): Extension<PgPool>,
+    CsrfForm(req): CsrfForm<RevokeUserSessionForm>,
+) -> Redirect {
+    let page = |outcome: (&str, &str)| {
+        let query = serde_urlencoded::to_string([("email", req.email.as_str()), outcome]).unwrap_or_default();
+        Redirect::to(&format!("/account/users/sessions?{}", query))
+    };
+
+    let id = match Uuid::parse_str(&req.id) {
+        Ok(v) => v,
+        Err(_e) => return page(("error", "not_found")),
+    };
+
+    match revoke(&pool, &req.email, id).await {
+        StatusCode::NO_CONTENT => page(("revoked", "true")),
+        StatusCode::NOT_FOUND => page(("error", "not_found")),
+        _ => page(("error", "internal_server_error")),
+    }
+}
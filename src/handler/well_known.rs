@@ -0,0 +1,22 @@
+use axum::{
+    Extension,
+    Json,
+    Router,
+    response::IntoResponse,
+    routing::get,
+};
+
+use crate::common::keys::Keys;
+
+pub fn router() -> Router {
+    Router::new()
+        .route("/.well-known/jwks.json", get(jwks))
+}
+
+/// Publish the public half of every asymmetric signing key, including retired ones,
+/// so other services can verify the tokens we issue
+pub async fn jwks(
+    Extension(keys): Extension<Keys>,
+) -> impl IntoResponse {
+    Json(keys.jwks())
+}
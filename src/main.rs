@@ -1,17 +1,28 @@
 use crate::common::runtime::Runtime;
+use crate::common::shutdown;
 
+// generated by build.rs, not every message is used yet
+#[allow(dead_code)]
+#[path = "../api/draft/mod.rs"]
+mod api;
+mod client;
 mod common;
 mod controller;
 mod handler;
 mod middleware;
+mod service;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     match Runtime::new().default().await {
-        Ok(x) => {
+        Ok(Some(x)) => {
           x.execute().await;
           Ok(())
         },
-        Err(_e) => Ok(())
+        Ok(None) => Ok(()),
+        Err(e) => {
+          println!("{}", e);
+          std::process::exit(shutdown::EXIT_FAILED)
+        }
     }
-}
\ No newline at end of file
+}
@@ -1,59 +1,128 @@
 use axum::{
-    http::{Request, StatusCode},
+    http::{header::WWW_AUTHENTICATE, Request, StatusCode},
     middleware::Next,
-    response::Response
+    response::{IntoResponse, Redirect, Response},
 };
 
 use axum_session::{Session, SessionPgPool};
-use crate::common::jwt;
+use sqlx::postgres::PgPool;
+use crate::common::{client_ip::ClientIp, jwt, keys::Keys};
+use crate::controller::revoked_tokens::is_access_token_revoked;
+use crate::controller::sessions::touch_session;
+use crate::middleware::{bearer_token, refresh_token::clear_tokens};
 
 // Is a wrapper around the returned extention type
 
+/// Checks the access token of a request, either from an `Authorization: Bearer` header or
+/// from the session, and puts its `AccessTokenClaims` into the request extensions. Tokens
+/// revoked at logout, or issued before the sessions of the user were revoked, are rejected.
 pub async fn authenticity_token_protected<B>(
     mut req: Request<B>, 
     next: Next<B>,
-) -> Result<Response, StatusCode> {
-    let _headers = req.headers();
-    let extentions = req.extensions_mut();
+) -> Response {
+    let keys = match req.extensions().get::<Keys>() {
+        None => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        Some(v) => v.clone(),
+    };
+
+    let pool = match req.extensions().get::<PgPool>() {
+        None => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        Some(v) => v.clone(),
+    };
 
     println!("auth middleware");
 
+    // api clients send their access token with every request, they get a 401 instead of
+    // being sent to the login page
+    if let Some(token) = bearer_token(&req) {
+        return match jwt::decode_token::<jwt::AccessTokenClaims>(&keys, &token) {
+            Ok(v) => match is_access_token_revoked(&pool, &v.claims).await {
+                Ok(false) => {
+                    req.extensions_mut().insert(v.claims);
+                    next.run(req).await
+                },
+                Ok(true) => {
+                    println!("bearer token was revoked");
+                    (StatusCode::UNAUTHORIZED, [(WWW_AUTHENTICATE, "Bearer")]).into_response()
+                },
+                Err(_e) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+            },
+            Err(e) => {
+                println!("bearer token rejected {:?}", e);
+                (StatusCode::UNAUTHORIZED, [(WWW_AUTHENTICATE, "Bearer")]).into_response()
+            }
+        }
+    }
+
     // get the session extractor from the request context
-    let session = match extentions.get::<Session<SessionPgPool>>() {
+    let session = match req.extensions().get::<Session<SessionPgPool>>() {
         None => return {
             println!("no session found");
-            Err(StatusCode::BAD_REQUEST)
+            Redirect::to("/login").into_response()
         },
-        Some(v) => v,
+        Some(v) => v.clone(),
     };
 
-    // get the auth token from the request and log it
+    // get the auth token from the request and log it, an expired token has already been
+    // renewed by the `refresh_token` middleware when the session has a refresh token
     match session.get::<String>("access_token") {
         None => {
             println!("access_token not found"); 
-            return Err(StatusCode::BAD_REQUEST)
+            Redirect::to("/login").into_response()
         },
         Some(access_token) => {
             println!("found access access_token: {}", access_token);
 
-            if jwt::is_token_valid::<jwt::AccessTokenClaims>(&access_token) == false {
+            if !jwt::is_token_valid::<jwt::AccessTokenClaims>(&keys, &access_token) {
                 println!("token is invalid");
-                return Err(StatusCode::BAD_REQUEST) 
+                return Redirect::to("/login").into_response()
             };
         
-            match jwt::decode_token::<jwt::AccessTokenClaims>(&access_token) {
+            match jwt::decode_token::<jwt::AccessTokenClaims>(&keys, &access_token) {
                 Err(_e) => {
                     println!("failed to decode access token");
-                    return Err(StatusCode::BAD_REQUEST) 
+                    return Redirect::to("/login").into_response()
+                },
+                Ok(v) => {
+                    match is_access_token_revoked(&pool, &v.claims).await {
+                        Ok(false) => {},
+                        Ok(true) => {
+                            println!("session was revoked");
+                            clear_tokens(&session);
+                            return Redirect::to("/login").into_response()
+                        },
+                        Err(_e) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+                    }
+
+                    // the session may have been revoked from another device
+                    let session_id = session.get_session_id().await.inner();
+                    let ip = req
+                        .extensions()
+                        .get::<ClientIp>()
+                        .map(|v| v.0.to_string())
+                        .unwrap_or_default();
+
+                    match touch_session(&pool, &session_id, &ip).await {
+                        Ok(true) => {},
+                        Ok(false) => {
+                            println!("session was revoked from another device");
+                            clear_tokens(&session);
+                            return Redirect::to("/login").into_response()
+                        },
+                        Err(_e) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+                    }
+
+                    session.set("access_token_claims", &v.claims);
+                    // handlers and guards further down read the claims from the request
+                    req.extensions_mut().insert(v.claims);
                 },
-                Ok(v) => session.set("access_token_claims", &v.claims)
             } 
 
             // set the access_token back into the session
             session.set("access_token", access_token);
 
             // return the call to next
-            Ok(next.run(req).await)
+            next.run(req).await
         }
     }
-}
\ No newline at end of file
+}
@@ -1,52 +1,61 @@
 use axum::{
     http::{Request, StatusCode},
     middleware::Next,
-    response::Response,
+    response::{IntoResponse, Redirect, Response},
 };
 
 use axum_session::{Session, SessionPgPool};
-use crate::common::jwt;
+use crate::common::{jwt, keys::Keys};
+use crate::middleware::bearer_token;
 
 pub async fn identification_token<B>(
     mut req: Request<B>,
     next: Next<B>,
-) -> Result<Response, StatusCode> {
-    let _headers = req.headers();
-    let extentions = req.extensions_mut();
+) -> Response {
+    // bearer clients only send an access token, it's checked by `authenticity_token_protected`
+    if bearer_token(&req).is_some() {
+        return next.run(req).await
+    }
+
+    let keys = match req.extensions().get::<Keys>() {
+        None => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        Some(v) => v.clone(),
+    };
 
-    let session = match extentions.get::<Session<SessionPgPool>>() {
+    let session = match req.extensions().get::<Session<SessionPgPool>>() {
         None => return {
             println!("failed to get session");
-            Err(StatusCode::BAD_REQUEST)
+            Redirect::to("/login").into_response()
         },
-        Some(v) => v,
+        Some(v) => v.clone(),
     };
 
     match session.get::<String>("id_token") {
         None => {
             println!("failed to get id_token from session");
-            return Err(StatusCode::BAD_REQUEST)
+            Redirect::to("/login").into_response()
         },
         Some(id_token) => {
             println!("id_token: {}", id_token);
 
-            if jwt::is_token_valid::<jwt::IdTokenClaims>(&id_token) == false {
+            if !jwt::is_token_valid::<jwt::IdTokenClaims>(&keys, &id_token) {
                 println!("failed to get session");
-                return Err(StatusCode::BAD_REQUEST)
+                return Redirect::to("/login").into_response()
             }
         
-            match jwt::decode_token::<jwt::IdTokenClaims>(&id_token) {
+            match jwt::decode_token::<jwt::IdTokenClaims>(&keys, &id_token) {
                 Err(_e) => {
                     println!("failed to decode identity token");
-                    return Err(StatusCode::BAD_REQUEST)
+                    return Redirect::to("/login").into_response()
                 },
                 Ok(v) => {
-                    session.set("id_token_claims", &v.claims)
+                    session.set("id_token_claims", &v.claims);
+                    req.extensions_mut().insert(v.claims);
                 },
             } 
 
             session.set("id_token", id_token);
-            Ok(next.run(req).await)
+            next.run(req).await
         }
     }
-}
\ No newline at end of file
+}
@@ -1,3 +1,13 @@
+use axum::http::{header::AUTHORIZATION, Request};
+
 pub mod authentication_token;
 pub mod identification_token;
-pub mod refresh_token;
\ No newline at end of file
+pub mod refresh_token;
+pub mod require_scope;
+
+/// Token from an `Authorization: Bearer <token>` header, sent by clients that don't use a session
+pub fn bearer_token<B>(req: &Request<B>) -> Option<String> {
+    let header = req.headers().get(AUTHORIZATION)?.to_str().ok()?;
+
+    header.strip_prefix("Bearer ").map(|t| t.trim().to_owned())
+}
@@ -1,49 +1,231 @@
 use axum::{
     http::{Request, StatusCode},
     middleware::Next,
-    response::Response
+    response::{IntoResponse, Redirect, Response},
 };
 
 use axum_session::{Session, SessionPgPool};
-use crate::common::jwt;
+use serde::Deserialize;
+use sqlx::postgres::PgPool;
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+use crate::common::{jwt, keys::{KeyStore, Keys}};
+use crate::controller::refresh_tokens::{rotate_refresh_token, verify_refresh_token};
+use crate::middleware::bearer_token;
 
+// renewals in flight by session id, concurrent requests of a session renew one at a time
+static RENEWALS: Mutex<BTreeMap<String, Arc<tokio::sync::Mutex<()>>>> = Mutex::new(BTreeMap::new());
+
+/// First middleware in the chain. Checks the refresh token in the session, and when the access
+/// or id token has expired uses it to renew all three before the rest of the chain runs.
 pub async fn refresh_token<B>(
     mut req: Request<B>,
     next: Next<B>,
-) -> Result<Response, StatusCode> {
-    let _headers = req.headers();
+) -> Response {
+    // bearer clients renew their own tokens with `/token/refresh`
+    if bearer_token(&req).is_some() {
+        return next.run(req).await
+    }
+
     let extentions = req.extensions_mut();
 
+    let keys = match extentions.get::<Keys>() {
+        None => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        Some(v) => v.clone(),
+    };
+
+    let pool = match extentions.get::<PgPool>() {
+        None => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        Some(v) => v.clone(),
+    };
+
     // get the session extractor from the request context
     let session = match extentions.get::<Session<SessionPgPool>>() {
-        None => return Err(StatusCode::BAD_REQUEST),
-        Some(v) => v,
+        None => return Redirect::to("/login").into_response(),
+        Some(v) => v.clone(),
     };
 
     // get the auth token from the request and log it
-    match session.get::<String>("refresh_token") {
-        None => return Ok(next.run(req).await),
-        Some(token) => {
-            println!("refresh_token: {}", token);
-            // check to make sure the token is not empty
-            if token == "" {
-                return Ok(next.run(req).await)
-            } else {
-                // when a token is not and empty, attempt to check to see if it's a valid refresh_token
-                if jwt::is_token_valid::<jwt::RefreshTokenClaims>(&token) == false {
-                    return Err(StatusCode::BAD_REQUEST) 
-                };
-            
-                match jwt::decode_token::<jwt::RefreshTokenClaims>(&token) {
-                    Err(_e) => {
-                        println!("failed to decode refresh token");
-                        return Err(StatusCode::BAD_REQUEST) 
-                    },
-                    Ok(v) => session.set("refresh_token", &v.claims)
-                }
-            }
+    let token = match session.get::<String>("refresh_token") {
+        None => return next.run(req).await,
+        Some(token) => token,
+    };
+
+    // check to make sure the token is not empty, without one there is nothing to renew with
+    if token.is_empty() {
+        return next.run(req).await
+    }
+
+    // when a token is not and empty, attempt to check to see if it's a valid refresh_token
+    let claims = match jwt::decode_token::<jwt::RefreshTokenClaims>(&keys, &token) {
+        Err(_e) => {
+            println!("failed to decode refresh token");
+            clear_tokens(&session);
+            return Redirect::to("/login").into_response()
+        },
+        Ok(v) => v.claims,
+    };
+
+    let renew = needs_renewal::<jwt::AccessTokenClaims>(&keys, session.get("access_token"))
+        || needs_renewal::<jwt::IdTokenClaims>(&keys, session.get("id_token"));
+
+    if renew {
+        let session_id = session.get_session_id().await.inner();
+        let lock = renewal_lock(&session_id);
+        let guard = lock.lock().await;
+
+        // a request that held the lock before this one may have renewed the tokens already
+        let renewed = session.get::<String>("refresh_token").is_some_and(|v| v != token);
+
+        // rotating also checks that the refresh token hasn't been retired or revoked
+        let result = if renewed {
+            Ok(())
+        } else {
+            rotate_refresh_token(&pool, &keys, &claims).await.map(|tokens| {
+                println!("renewed tokens from refresh token");
+                session.set("access_token", &tokens.access_token);
+                session.set("id_token", &tokens.id_token);
+                session.set("refresh_token", tokens.refresh_token.unwrap_or_default());
+                session.set("refresh_token_claims", &tokens.refresh_token_claims);
+            })
+        };
+
+        drop(guard);
+        release_renewal_lock(&session_id, lock);
+
+        if let Err(e) = result {
+            println!("failed to renew tokens {:?}", e);
+            clear_tokens(&session);
+            return Redirect::to("/login").into_response()
         }
+    } else {
+        // the signature is fine, make sure the token hasn't been retired or revoked
+        if let Err(e) = verify_refresh_token(&pool, &claims).await {
+            println!("refresh token rejected {:?}", e);
+            clear_tokens(&session);
+            return Redirect::to("/login").into_response()
+        }
+
+        session.set("refresh_token_claims", &claims);
+    }
+
+    next.run(req).await
+}
+
+// a missing or expired token can be renewed, any other failure is left for the
+// middleware that owns the token to reject
+fn needs_renewal<T: for<'de> Deserialize<'de> + Debug>(keys: &KeyStore, token: Option<String>) -> bool {
+    match token {
+        None => true,
+        Some(t) if t.is_empty() => true,
+        Some(t) => matches!(jwt::decode_token::<T>(keys, &t), Err(jwt::Error::ExpiredToken)),
     }
+}
 
-    Ok(next.run(req).await)
-}
\ No newline at end of file
+// the lock renewals of the session wait on
+fn renewal_lock(session_id: &str) -> Arc<tokio::sync::Mutex<()>> {
+    let mut renewals = RENEWALS.lock().unwrap();
+    renewals.entry(session_id.to_owned()).or_default().clone()
+}
+
+// forget the lock once nothing else is waiting on it
+fn release_renewal_lock(session_id: &str, lock: Arc<tokio::sync::Mutex<()>>) {
+    let mut renewals = RENEWALS.lock().unwrap();
+    drop(lock);
+    if renewals.get(session_id).is_some_and(|v| Arc::strong_count(v) == 1) {
+        renewals.remove(session_id);
+    }
+}
+
+pub fn clear_tokens(session: &Session<SessionPgPool>) {
+    session.remove("access_token");
+    session.remove("access_token_claims");
+    session.remove("id_token");
+    session.remove("id_token_claims");
+    session.remove("refresh_token");
+    session.remove("refresh_token_claims");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::config::TokenConfig;
+    use chrono::{Duration, Utc};
+    use jsonwebtoken::{encode, Header};
+
+    fn keys() -> KeyStore {
+        KeyStore::ephemeral().with_claims(&TokenConfig {
+            keyset: None,
+            issuer: String::from("test"),
+            audience: vec![String::from("test")],
+            client_id: String::from("test"),
+        })
+    }
+
+    fn access_token(keys: &KeyStore, exp: i64) -> String {
+        let (kid, alg, key) = keys.signing_key();
+        let header = Header { kid: Some(kid.to_owned()), alg, ..Default::default() };
+        let claims = serde_json::json!({
+            "iss": "test",
+            "sub": "test@example.com",
+            "aud": ["test"],
+            "azp": "test",
+            "exp": exp,
+            "iat": exp - 3600,
+            "scope": [],
+        });
+        encode(&header, &claims, key).unwrap()
+    }
+
+    #[test]
+    fn missing_or_empty_tokens_are_renewed() {
+        let keys = keys();
+        assert!(needs_renewal::<jwt::AccessTokenClaims>(&keys, None));
+        assert!(needs_renewal::<jwt::AccessTokenClaims>(&keys, Some(String::new())));
+    }
+
+    #[test]
+    fn expired_tokens_are_renewed() {
+        let keys = keys();
+        let token = access_token(&keys, (Utc::now() - Duration::hours(1)).timestamp());
+        assert!(needs_renewal::<jwt::AccessTokenClaims>(&keys, Some(token)));
+    }
+
+    #[test]
+    fn valid_and_invalid_tokens_are_not_renewed() {
+        let keys = keys();
+        let token = access_token(&keys, (Utc::now() + Duration::hours(1)).timestamp());
+        assert!(!needs_renewal::<jwt::AccessTokenClaims>(&keys, Some(token)));
+        // left for the middleware that owns the token to reject
+        assert!(!needs_renewal::<jwt::AccessTokenClaims>(&keys, Some(String::from("garbage"))));
+        let other = access_token(&KeyStore::ephemeral(), (Utc::now() - Duration::hours(1)).timestamp());
+        assert!(!needs_renewal::<jwt::AccessTokenClaims>(&keys, Some(other)));
+    }
+
+    #[tokio::test]
+    async fn renewals_of_a_session_wait_on_each_other() {
+        let first = renewal_lock("session-a");
+        let guard = first.lock().await;
+
+        let second = renewal_lock("session-a");
+        assert!(Arc::ptr_eq(&first, &second));
+        assert!(second.try_lock().is_err());
+        assert!(renewal_lock("session-b").try_lock().is_ok());
+
+        drop(guard);
+        assert!(second.try_lock().is_ok());
+    }
+
+    #[test]
+    fn renewal_locks_are_forgotten_once_released() {
+        let first = renewal_lock("session-c");
+        let second = renewal_lock("session-c");
+
+        release_renewal_lock("session-c", first);
+        assert!(RENEWALS.lock().unwrap().contains_key("session-c"));
+
+        release_renewal_lock("session-c", second);
+        assert!(!RENEWALS.lock().unwrap().contains_key("session-c"));
+    }
+}
@@ -0,0 +1,71 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use axum::{
+    http::{Request, StatusCode},
+    response::{IntoResponse, Response},
+};
+use tower::{Layer, Service};
+
+use crate::common::jwt::AccessTokenClaims;
+
+/// Route guard that returns 403 unless the access token has the scope, it reads the
+/// `AccessTokenClaims` put on the request by `authenticity_token_protected` so it has to
+/// be layered before it, ie. run after it.
+///
+/// .route_layer(RequireScope("users:write"))
+/// .route_layer(middleware::from_fn(authenticity_token_protected))
+#[derive(Debug, Clone, Copy)]
+pub struct RequireScope(pub &'static str);
+
+impl<S> Layer<S> for RequireScope {
+    type Service = RequireScopeService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RequireScopeService {
+            inner,
+            scope: self.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RequireScopeService<S> {
+    inner: S,
+    scope: &'static str,
+}
+
+impl<S, B> Service<Request<B>> for RequireScopeService<S>
+where
+    S: Service<Request<B>, Response = Response> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    B: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        let allowed = match req.extensions().get::<AccessTokenClaims>() {
+            Some(claims) => claims.has_scope(self.scope),
+            None => false,
+        };
+
+        if !allowed {
+            println!("access token is missing scope {}", self.scope);
+            return Box::pin(async { Ok(StatusCode::FORBIDDEN.into_response()) })
+        }
+
+        // the clone might not be ready, keep the service that was polled
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        Box::pin(async move { inner.call(req).await })
+    }
+}
@@ -0,0 +1,79 @@
+use std::time::Duration;
+
+use axum::http::{HeaderName, HeaderValue, Method};
+use sqlx::postgres::PgPool;
+use tonic::{metadata::MetadataMap, transport::server::Routes, Status};
+use tonic_web::{GrpcWebLayer, GrpcWebService};
+use tower_http::cors::{AllowOrigin, Cors, CorsLayer};
+use crate::common::{jwt, keys::{KeyStore, Keys}, password_policy::PasswordPolicies};
+
+pub mod policy_admin;
+pub mod policy_evaluator;
+pub mod users;
+
+/// Every gRPC service, they're served next to the axum router by `multiplex`
+pub type GrpcRoutes = Cors<GrpcWebService<Routes>>;
+
+/// The gRPC services, callable with gRPC and gRPC-web. Browsers on `web_origins` can call them
+/// from another origin, the webapp served by us doesn't need to be listed.
+pub fn routes(pool: PgPool, keys: Keys, password_policy: PasswordPolicies, web_origins: &[String]) -> GrpcRoutes {
+    let origins = web_origins
+        .iter()
+        .filter_map(|o| HeaderValue::from_str(o).ok())
+        .collect::<Vec<HeaderValue>>();
+
+    // calls are authenticated with `authorization` metadata, never cookies
+    let cors = CorsLayer::new()
+        .allow_origin(AllowOrigin::list(origins))
+        .allow_methods([Method::POST])
+        .allow_headers(["authorization", "content-type", "x-grpc-web", "x-user-agent", "grpc-timeout"].map(HeaderName::from_static))
+        .expose_headers(["grpc-status", "grpc-message", "grpc-status-details-bin"].map(HeaderName::from_static))
+        .max_age(Duration::from_secs(24 * 60 * 60));
+
+    tonic::transport::Server::builder()
+        .accept_http1(true)
+        .layer(cors)
+        .layer(GrpcWebLayer::new())
+        .add_service(policy_evaluator::new(pool.clone(), keys.clone()))
+        .add_service(policy_admin::new(pool.clone(), keys.clone()))
+        .add_service(users::new(pool, keys, password_policy))
+        .into_service()
+}
+
+#[derive(Debug)]
+pub enum BearerError {
+    Missing,
+    NotBearer,
+    Expired,
+    Invalid,
+}
+
+impl From<BearerError> for Status {
+    fn from(e: BearerError) -> Status {
+        match e {
+            BearerError::Missing => Status::unauthenticated("missing authorization"),
+            BearerError::NotBearer => Status::unauthenticated("authorization must be a bearer token"),
+            BearerError::Expired => Status::unauthenticated("access token has expired"),
+            BearerError::Invalid => Status::unauthenticated("invalid access token"),
+        }
+    }
+}
+
+/// Decode the access token sent as `authorization: Bearer <token>` metadata
+pub fn bearer_claims(keys: &KeyStore, metadata: &MetadataMap) -> Result<jwt::AccessTokenClaims, BearerError> {
+    let header = match metadata.get("authorization").and_then(|v| v.to_str().ok()) {
+        Some(v) => v,
+        None => return Err(BearerError::Missing),
+    };
+
+    let token = match header.strip_prefix("Bearer ") {
+        Some(t) => t.trim(),
+        None => return Err(BearerError::NotBearer),
+    };
+
+    match jwt::decode_token::<jwt::AccessTokenClaims>(keys, token) {
+        Ok(v) => Ok(v.claims),
+        Err(jwt::Error::ExpiredToken) => Err(BearerError::Expired),
+        Err(jwt::Error::DecodeError) => Err(BearerError::Invalid),
+    }
+}
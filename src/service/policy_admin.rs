@@ -0,0 +1,177 @@
+use sqlx::postgres::PgPool;
+use tonic::{Request, Response, Status};
+
+use crate::api::draft::access_controls::v1::{
+    policy_admin_server::{PolicyAdmin, PolicyAdminServer},
+    CreatePolicyRequest,
+    CreatePolicyResponse,
+    DeletePolicyRequest,
+    DeletePolicyResponse,
+    ListPoliciesRequest,
+    ListPoliciesResponse,
+    ListPolicyVersionsRequest,
+    ListPolicyVersionsResponse,
+    RollbackPoliciesRequest,
+    RollbackPoliciesResponse,
+    UpdatePolicyRequest,
+    UpdatePolicyResponse,
+};
+use crate::common::keys::Keys;
+use crate::controller::policies::{self, PoliciesError, PolicyFilter};
+use crate::controller::revoked_tokens::is_access_token_revoked;
+use crate::service::bearer_claims;
+
+pub struct PolicyAdminService {
+    pool: PgPool,
+    keys: Keys,
+}
+
+pub fn new(pool: PgPool, keys: Keys) -> PolicyAdminServer<PolicyAdminService> {
+    PolicyAdminServer::new(PolicyAdminService { pool, keys })
+}
+
+impl PolicyAdminService {
+    async fn authorize<T>(&self, request: &Request<T>) -> Result<String, Status> {
+        authorize(&self.pool, &self.keys, request).await
+    }
+}
+
+/// The caller has to be allowed CHANGE_PERMISSION on `access_control_policies`,
+/// returns the subject of their access token to record as `changed_by`
+pub async fn authorize<T>(pool: &PgPool, keys: &Keys, request: &Request<T>) -> Result<String, Status> {
+    let claims = bearer_claims(keys, request.metadata())?;
+
+    match is_access_token_revoked(pool, &claims).await {
+        Ok(false) => {},
+        Ok(true) => return Err(Status::unauthenticated("access token was revoked")),
+        Err(_e) => return Err(Status::internal("policy administration failed")),
+    }
+
+    let subject = policies::subject_for_user(pool, &claims.sub)
+        .await
+        .map_err(to_status)?;
+
+    match policies::can_change_permission(pool, subject).await {
+        Ok(true) => Ok(claims.sub),
+        Ok(false) => Err(Status::permission_denied("not allowed to change access control policies")),
+        Err(e) => Err(to_status(e)),
+    }
+}
+
+#[tonic::async_trait]
+impl PolicyAdmin for PolicyAdminService {
+    async fn create_policy(
+        &self,
+        request: Request<CreatePolicyRequest>,
+    ) -> Result<Response<CreatePolicyResponse>, Status> {
+        let changed_by = self.authorize(&request).await?;
+        let policy = match request.into_inner().policy {
+            Some(p) => p,
+            None => return Err(Status::invalid_argument("policy is required")),
+        };
+
+        match policies::create_policy(&self.pool, policy, &changed_by).await {
+            Ok((policy, version)) => Ok(Response::new(CreatePolicyResponse {
+                policy: Some(policy),
+                version,
+            })),
+            Err(e) => Err(to_status(e)),
+        }
+    }
+
+    async fn list_policies(
+        &self,
+        request: Request<ListPoliciesRequest>,
+    ) -> Result<Response<ListPoliciesResponse>, Status> {
+        self.authorize(&request).await?;
+        let req = request.into_inner();
+
+        let filter = PolicyFilter {
+            data_type: req.data_type,
+            subject_user_id: req.subject_user_id,
+            subject_group_id: req.subject_group_id,
+            operation: req.operation,
+        };
+
+        let version = policies::current_version(&self.pool).await.map_err(to_status)?;
+        match policies::find_policies(&self.pool, &filter).await {
+            Ok(policies) => Ok(Response::new(ListPoliciesResponse { policies, version })),
+            Err(e) => Err(to_status(e)),
+        }
+    }
+
+    async fn update_policy(
+        &self,
+        request: Request<UpdatePolicyRequest>,
+    ) -> Result<Response<UpdatePolicyResponse>, Status> {
+        let changed_by = self.authorize(&request).await?;
+        let policy = match request.into_inner().policy {
+            Some(p) => p,
+            None => return Err(Status::invalid_argument("policy is required")),
+        };
+
+        match policies::update_policy(&self.pool, policy, &changed_by).await {
+            Ok((policy, version)) => Ok(Response::new(UpdatePolicyResponse {
+                policy: Some(policy),
+                version,
+            })),
+            Err(e) => Err(to_status(e)),
+        }
+    }
+
+    async fn delete_policy(
+        &self,
+        request: Request<DeletePolicyRequest>,
+    ) -> Result<Response<DeletePolicyResponse>, Status> {
+        let changed_by = self.authorize(&request).await?;
+        let req = request.into_inner();
+
+        match policies::delete_policy(&self.pool, &req.id, &changed_by).await {
+            Ok(version) => Ok(Response::new(DeletePolicyResponse { version })),
+            Err(e) => Err(to_status(e)),
+        }
+    }
+
+    async fn list_policy_versions(
+        &self,
+        request: Request<ListPolicyVersionsRequest>,
+    ) -> Result<Response<ListPolicyVersionsResponse>, Status> {
+        self.authorize(&request).await?;
+        let req = request.into_inner();
+
+        let policy_id = match req.policy_id.as_str() {
+            "" => None,
+            id => Some(id),
+        };
+
+        match policies::find_versions(&self.pool, policy_id).await {
+            Ok(versions) => Ok(Response::new(ListPolicyVersionsResponse { versions })),
+            Err(e) => Err(to_status(e)),
+        }
+    }
+
+    async fn rollback_policies(
+        &self,
+        request: Request<RollbackPoliciesRequest>,
+    ) -> Result<Response<RollbackPoliciesResponse>, Status> {
+        let changed_by = self.authorize(&request).await?;
+        let req = request.into_inner();
+
+        match policies::rollback_policies(&self.pool, req.version, &changed_by).await {
+            Ok(version) => Ok(Response::new(RollbackPoliciesResponse { version })),
+            Err(e) => Err(to_status(e)),
+        }
+    }
+}
+
+fn to_status(e: PoliciesError) -> Status {
+    match e {
+        PoliciesError::NotFound => Status::not_found("policy not found"),
+        PoliciesError::UnknownVersion => Status::not_found("unknown policy set version"),
+        PoliciesError::InvalidPolicy(field) => Status::invalid_argument(format!("invalid policy {}", field)),
+        e => {
+            println!("policy administration failed {:?}", e);
+            Status::internal("policy administration failed")
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use sqlx::postgres::PgPool;
+use tonic::{Request, Response, Status};
+
+use crate::api::draft::access_controls::v1::{
+    policy_evaluator_server::{PolicyEvaluator, PolicyEvaluatorServer},
+    EvaluatePolicyRequest,
+    EvaluatePolicyResponse,
+    Operation,
+};
+use crate::common::keys::Keys;
+use crate::controller::policies;
+use crate::service::policy_admin;
+
+pub struct PolicyEvaluatorService {
+    pool: PgPool,
+    keys: Keys,
+}
+
+pub fn new(pool: PgPool, keys: Keys) -> PolicyEvaluatorServer<PolicyEvaluatorService> {
+    PolicyEvaluatorServer::new(PolicyEvaluatorService { pool, keys })
+}
+
+#[tonic::async_trait]
+impl PolicyEvaluator for PolicyEvaluatorService {
+    async fn evaluate_policy(
+        &self,
+        request: Request<EvaluatePolicyRequest>,
+    ) -> Result<Response<EvaluatePolicyResponse>, Status> {
+        // the trace shows every policy, so only policy administrators get it
+        if request.get_ref().explain {
+            policy_admin::authorize(&self.pool, &self.keys, &request).await?;
+        }
+
+        let req = request.into_inner();
+
+        match Operation::from_i32(req.operation) {
+            None | Some(Operation::DataOperationUnspecified) => {
+                return Err(Status::invalid_argument("operation is required"))
+            },
+            Some(_v) => {},
+        }
+
+        if req.data_type.is_empty() {
+            return Err(Status::invalid_argument("data_type is required"))
+        }
+
+        if req.explain {
+            return match policies::explain_policy(&self.pool, &req).await {
+                Ok((outcome, trace)) => Ok(Response::new(EvaluatePolicyResponse {
+                    outcome: outcome as i32,
+                    trace: Some(trace),
+                })),
+                Err(e) => {
+                    println!("failed to explain policy {:?}", e);
+                    Err(Status::internal("failed to evaluate policy"))
+                }
+            }
+        }
+
+        match policies::evaluate_policy(&self.pool, &req).await {
+            Ok(outcome) => Ok(Response::new(EvaluatePolicyResponse {
+                outcome: outcome as i32,
+                trace: None,
+            })),
+            Err(e) => {
+                println!("failed to evaluate policy {:?}", e);
+                Err(Status::internal("failed to evaluate policy"))
+            }
+        }
+    }
+}
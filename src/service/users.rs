@@ -0,0 +1,129 @@
+use sqlx::postgres::PgPool;
+use tonic::{Request, Response, Status};
+
+use crate::api::draft::users::v1::{
+    users_service_server::{UsersService, UsersServiceServer},
+    ConfirmWithCodeRequest,
+    ConfirmWithCodeResponse,
+    SignupRequest,
+    SignupResponse,
+};
+use crate::common::{client_ip::ClientIp, jwt, keys::Keys, password_policy::PasswordPolicies};
+use crate::controller::confirmation_codes::{self, ConfirmationCodesError};
+use crate::controller::users::{self, InsertUserParams, UsersError};
+
+pub struct UsersServiceImpl {
+    pool: PgPool,
+    keys: Keys,
+    password_policy: PasswordPolicies,
+}
+
+pub fn new(pool: PgPool, keys: Keys, password_policy: PasswordPolicies) -> UsersServiceServer<UsersServiceImpl> {
+    UsersServiceServer::new(UsersServiceImpl { pool, keys, password_policy })
+}
+
+#[tonic::async_trait]
+impl UsersService for UsersServiceImpl {
+    async fn signup(
+        &self,
+        request: Request<SignupRequest>,
+    ) -> Result<Response<SignupResponse>, Status> {
+        let ip = request
+            .extensions()
+            .get::<ClientIp>()
+            .map(|v| v.0.to_string())
+            .unwrap_or_default();
+        let req = request.into_inner();
+
+        if !req.user_name.contains('@') {
+            return Err(Status::invalid_argument("user_name must be an email"))
+        }
+
+        // the message is the same error code the signup form gets
+        if let Err(violation) = self.password_policy.check(&req.password, &req.user_name) {
+            return Err(Status::invalid_argument(violation.code()))
+        }
+
+        // the first user of the system is the admin
+        let role_name = match users::count_users(&self.pool).await {
+            Ok(v) if v > 0 => String::from("default"),
+            Ok(_v) => String::from("admin"),
+            Err(_e) => String::from("default"),
+        };
+
+        let user_id = match users::insert_user(&self.pool, &InsertUserParams {
+            email: req.user_name.clone(),
+            password: req.password,
+            role_name,
+        }).await {
+            Ok(v) => v,
+            Err(UsersError::FailedUserInsertUniqueEmail) => return Err(Status::already_exists("email is already in use")),
+            Err(e) => {
+                println!("failed to insert user {:?}", e);
+                return Err(Status::internal("failed to signup"))
+            }
+        };
+
+        // the code is only mailed, like the signup form, so whoever calls this has to own the email
+        match confirmation_codes::create_confirmation_code(&self.pool, user_id, &req.user_name, &ip).await {
+            Ok(_code) => Ok(Response::new(SignupResponse {})),
+            Err(e) => {
+                println!("failed to create a confirmation code {:?}", e);
+                Err(Status::internal("failed to signup"))
+            }
+        }
+    }
+
+    async fn confirm_with_code(
+        &self,
+        request: Request<ConfirmWithCodeRequest>,
+    ) -> Result<Response<ConfirmWithCodeResponse>, Status> {
+        let req = request.into_inner();
+
+        let user_id = match confirmation_codes::confirm_with_code(&self.pool, &req.user_name, &req.confirmation_code).await {
+            Ok(v) => v,
+            // an unknown or already confirmed email gets the same error as a wrong code
+            Err(ConfirmationCodesError::InvalidCode)
+            | Err(ConfirmationCodesError::NotFound)
+            | Err(ConfirmationCodesError::AlreadyConfirmed) => {
+                return Err(Status::invalid_argument("invalid confirmation code"))
+            },
+            Err(ConfirmationCodesError::Expired) => return Err(Status::deadline_exceeded("confirmation code has expired")),
+            Err(ConfirmationCodesError::TooManyAttempts) => {
+                return Err(Status::resource_exhausted("too many attempts, request a new confirmation code later"))
+            },
+            Err(e) => {
+                println!("failed to confirm {:?}", e);
+                return Err(Status::internal("failed to confirm"))
+            }
+        };
+
+        let scopes = match users::find_scopes(&self.pool, user_id).await {
+            Ok(v) => v,
+            Err(e) => {
+                println!("failed to find the users scopes {:?}", e);
+                return Err(Status::internal("failed to confirm"))
+            }
+        };
+
+        // a confirmed user is signed in, but without a refresh token
+        match jwt::ForgeOptions::new()
+            .subject(req.user_name)
+            .issuer(self.keys.issuer().to_owned())
+            .audience(self.keys.audience().to_vec())
+            .authorized_parties(self.keys.client_id().to_owned())
+            .scopes(scopes)
+            .forge(&self.keys) {
+                Ok(tokens) => Ok(Response::new(ConfirmWithCodeResponse {
+                    redirect_url: String::from("/app"),
+                    pairwise_pseudonymous_id: String::new(),
+                    id_token: tokens.id_token,
+                    access_token: tokens.access_token,
+                })),
+                Err(e) => {
+                    println!("and error occurred when forging the tokens {:?}", e);
+                    Err(Status::internal("failed to confirm"))
+                }
+            }
+    }
+}